@@ -0,0 +1,83 @@
+//! Generates the feature matrix of `tests/feature_matrix.rs` from
+//! `[package.metadata.feature-matrix]` and `[features]` in `Cargo.toml`: one
+//! ignored test per listed combination, plus the declared features the
+//! matrix must cover. Run it with
+//! `cargo test --test feature_matrix -- --ignored`.
+
+use std::{env, fmt::Write, fs, path::Path};
+
+/// Lines of the `[table]` section, up to the next table header.
+fn section<'a>(manifest: &'a str, table: &'a str) -> impl Iterator<Item = &'a str> {
+    manifest
+        .lines()
+        .skip_while(move |line| line.trim() != table)
+        .skip(1)
+        .take_while(|line| !line.trim_start().starts_with('['))
+}
+
+/// Quoted strings of the `combinations = [...]` array.
+fn combinations(manifest: &str) -> Vec<String> {
+    let lines: Vec<&str> = section(manifest, "[package.metadata.feature-matrix]")
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .collect();
+    lines
+        .iter()
+        .skip_while(|line| !line.starts_with("combinations"))
+        .skip(1)
+        .take_while(|line| !line.starts_with(']'))
+        .filter_map(|line| {
+            line.trim_end_matches(',')
+                .strip_prefix('"')?
+                .strip_suffix('"')
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+/// Features of the `[features]` table, without `default`.
+fn declared_features(manifest: &str) -> Vec<String> {
+    section(manifest, "[features]")
+        .filter_map(|line| {
+            line.split_once('=')
+                .map(|(name, _)| name.trim().to_string())
+        })
+        .filter(|name| !name.starts_with('#') && name != "default")
+        .collect()
+}
+
+/// Test name for a combination, e.g. `counters_test_utils` or `no_features`.
+fn test_name(features: &str) -> String {
+    if features.is_empty() {
+        return "no_features".to_string();
+    }
+    features.replace([',', '-'], "_")
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.toml");
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let manifest = fs::read_to_string("Cargo.toml").expect("failed to read Cargo.toml");
+    let combinations = combinations(&manifest);
+    let mut out = String::new();
+    writeln!(out, "const COMBINATIONS: &[&str] = &{combinations:?};").unwrap();
+    writeln!(
+        out,
+        "const DECLARED_FEATURES: &[&str] = &{:?};",
+        declared_features(&manifest)
+    )
+    .unwrap();
+    for features in &combinations {
+        writeln!(
+            out,
+            "#[test]\n\
+             #[ignore = \"runs nested cargo builds\"]\n\
+             fn {}() {{\n    check_combination({features:?});\n}}",
+            test_name(features)
+        )
+        .unwrap();
+    }
+    let dir = env::var("OUT_DIR").expect("OUT_DIR is not set");
+    fs::write(Path::new(&dir).join("feature_matrix.rs"), out)
+        .expect("failed to write the feature matrix");
+}
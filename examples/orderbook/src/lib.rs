@@ -0,0 +1,376 @@
+//! A minimal on-chain order book built on `rb-tree`: one account holds the
+//! bid and ask trees as a [`Forest`] sharing one node region and allocator.
+//!
+//! Orders rest under an [`OrderKey`] of price and placement sequence, so each
+//! book is in price-time priority with the best order first. Bid prices are
+//! stored inverted to make that hold for both sides. The link of every node is
+//! the order's remaining quantity.
+//!
+//! Every instruction takes the accounts `[book (writable), payer (signer,
+//! writable), system_program]`; the payer funds the book as it grows.
+
+use bytemuck::NoUninit;
+use index_mem_alloc::MemoryMap;
+use rb_tree::{Forest, RBTree, Side, TreeHeader};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    log::sol_log_compute_units,
+    msg,
+    program::set_return_data,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program,
+};
+use std::{fmt, mem::size_of};
+
+/// Layout of the book account: the tree header, the two root words, the order
+/// sequence and the allocator bitmap, followed by the node region.
+pub const HEADER_OFFSET: usize = 0;
+pub const BID_ROOT_OFFSET: usize = HEADER_OFFSET + size_of::<TreeHeader>();
+pub const ASK_ROOT_OFFSET: usize = BID_ROOT_OFFSET + size_of::<u32>();
+pub const SEQUENCE_OFFSET: usize = ASK_ROOT_OFFSET + size_of::<u32>();
+pub const BITMAP_OFFSET: usize = SEQUENCE_OFFSET + size_of::<u64>();
+/// Orders the book can hold across both sides.
+pub const CAPACITY: usize = 1024;
+pub const NON_TREE_DATA_SIZE: usize = BITMAP_OFFSET + CAPACITY / 8;
+/// Start of the node region, aligned past the non-tree data.
+pub const REGION_START: usize = RBTree::region_start(NON_TREE_DATA_SIZE);
+
+#[cfg(not(feature = "no-entrypoint"))]
+solana_program::entrypoint!(process_instruction);
+
+/// Key of a resting order. Ordered by price, then by placement, so the first
+/// node of a book is its best and oldest order.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, NoUninit)]
+pub struct OrderKey {
+    /// Price for asks, `u64::MAX - price` for bids.
+    pub book_price: u64,
+    pub sequence: u64,
+}
+
+impl OrderKey {
+    pub fn new(side: Side, price: u64, sequence: u64) -> Self {
+        let book_price = match side {
+            Side::Ask => price,
+            Side::Bid => u64::MAX - price,
+        };
+        Self {
+            book_price,
+            sequence,
+        }
+    }
+
+    pub fn price(&self, side: Side) -> u64 {
+        match side {
+            Side::Ask => self.book_price,
+            Side::Bid => u64::MAX - self.book_price,
+        }
+    }
+}
+
+impl fmt::Display for OrderKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}#{}", self.book_price, self.sequence)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BookInstruction {
+    /// Formats a zeroed book account.
+    Init,
+    /// Rests an order; returns its sequence as return data.
+    Place { side: Side, price: u64, quantity: u32 },
+    /// Removes a resting order.
+    Cancel {
+        side: Side,
+        price: u64,
+        sequence: u64,
+    },
+    /// Takes up to `quantity` from the opposite book at prices no worse than
+    /// `limit`; returns the filled quantity as return data.
+    Match {
+        side: Side,
+        limit: u64,
+        quantity: u64,
+    },
+}
+
+impl BookInstruction {
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        match *self {
+            Self::Init => data.push(0),
+            Self::Place {
+                side,
+                price,
+                quantity,
+            } => {
+                data.extend([1, side_byte(side)]);
+                data.extend(price.to_le_bytes());
+                data.extend(quantity.to_le_bytes());
+            }
+            Self::Cancel {
+                side,
+                price,
+                sequence,
+            } => {
+                data.extend([2, side_byte(side)]);
+                data.extend(price.to_le_bytes());
+                data.extend(sequence.to_le_bytes());
+            }
+            Self::Match {
+                side,
+                limit,
+                quantity,
+            } => {
+                data.extend([3, side_byte(side)]);
+                data.extend(limit.to_le_bytes());
+                data.extend(quantity.to_le_bytes());
+            }
+        }
+        data
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        let (&tag, rest) = data
+            .split_first()
+            .ok_or(ProgramError::InvalidInstructionData)?;
+        if tag == 0 {
+            return Ok(Self::Init);
+        }
+        let side = match rest.first() {
+            Some(0) => Side::Bid,
+            Some(1) => Side::Ask,
+            _ => return Err(ProgramError::InvalidInstructionData),
+        };
+        let price = read_u64(rest, 1)?;
+        match tag {
+            1 => Ok(Self::Place {
+                side,
+                price,
+                quantity: rest
+                    .get(9..13)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .map(u32::from_le_bytes)
+                    .ok_or(ProgramError::InvalidInstructionData)?,
+            }),
+            2 => Ok(Self::Cancel {
+                side,
+                price,
+                sequence: read_u64(rest, 9)?,
+            }),
+            3 => Ok(Self::Match {
+                side,
+                limit: price,
+                quantity: read_u64(rest, 9)?,
+            }),
+            _ => Err(ProgramError::InvalidInstructionData),
+        }
+    }
+}
+
+fn side_byte(side: Side) -> u8 {
+    match side {
+        Side::Bid => 0,
+        Side::Ask => 1,
+    }
+}
+
+fn read_u64(data: &[u8], at: usize) -> Result<u64, ProgramError> {
+    data.get(at..at + 8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u64::from_le_bytes)
+        .ok_or(ProgramError::InvalidInstructionData)
+}
+
+/// Builds an instruction of this program against `book`.
+pub fn instruction(
+    program_id: &Pubkey,
+    book: &Pubkey,
+    payer: &Pubkey,
+    instruction: &BookInstruction,
+) -> Instruction {
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new(*book, false),
+            AccountMeta::new(*payer, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+pub fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let accounts = &mut accounts.iter();
+    let book = next_account_info(accounts)?;
+    let payer = next_account_info(accounts)?;
+    let system_program = next_account_info(accounts)?;
+    if book.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    match BookInstruction::unpack(data)? {
+        BookInstruction::Init => init(book),
+        BookInstruction::Place {
+            side,
+            price,
+            quantity,
+        } => {
+            let mut forest = open(book)?;
+            let sequence = next_sequence(book)?;
+            let tree = forest.tree_mut(tree_index(side));
+            sol_log_compute_units();
+            tree.insert_unique(
+                OrderKey::new(side, price, sequence),
+                quantity,
+                book,
+                payer,
+                system_program,
+            )
+            .map_err(|error| {
+                error.log();
+                ProgramError::from(error.error)
+            })?;
+            sol_log_compute_units();
+            msg!("placed {:?} {} x {} as #{}", side, price, quantity, sequence);
+            set_return_data(&sequence.to_le_bytes());
+            Ok(())
+        }
+        BookInstruction::Cancel {
+            side,
+            price,
+            sequence,
+        } => {
+            let mut forest = open(book)?;
+            let tree = forest.tree_mut(tree_index(side));
+            sol_log_compute_units();
+            let removed = tree.remove_entry(OrderKey::new(side, price, sequence))?;
+            sol_log_compute_units();
+            let Some(removed) = removed else {
+                return Err(ProgramError::InvalidArgument);
+            };
+            msg!("cancelled #{} with {} left", sequence, removed.link);
+            Ok(())
+        }
+        BookInstruction::Match {
+            side,
+            limit,
+            quantity,
+        } => {
+            let maker_side = match side {
+                Side::Bid => Side::Ask,
+                Side::Ask => Side::Bid,
+            };
+            let mut forest = open(book)?;
+            let tree = forest.tree_mut(tree_index(maker_side));
+            sol_log_compute_units();
+            let filled = take(tree, maker_side, limit, quantity, book, payer, system_program)?;
+            sol_log_compute_units();
+            set_return_data(&filled.to_le_bytes());
+            Ok(())
+        }
+    }
+}
+
+/// Fills up to `quantity` against the best orders of the `maker_side` book
+/// priced no worse than `limit`. A partly filled order keeps its key, and so
+/// its time priority. Returns the quantity filled.
+fn take<'a, 'info>(
+    tree: &mut RBTree,
+    maker_side: Side,
+    limit: u64,
+    quantity: u64,
+    book: &'a AccountInfo<'info>,
+    payer: &'a AccountInfo<'info>,
+    system_program: &'a AccountInfo<'info>,
+) -> Result<u64, ProgramError> {
+    let mut filled = 0;
+    while filled < quantity {
+        let Some((key, resting)) = tree.first::<OrderKey>() else {
+            break;
+        };
+        let price = key.price(maker_side);
+        let crosses = match maker_side {
+            Side::Ask => price <= limit,
+            Side::Bid => price >= limit,
+        };
+        if !crosses {
+            break;
+        }
+        let fill = (quantity - filled).min(resting as u64) as u32;
+        tree.pop_first::<OrderKey>()?;
+        if fill < resting {
+            tree.try_insert(key, resting - fill, book, payer, system_program)?;
+        }
+        msg!("filled #{} at {} x {}", key.sequence, price, fill);
+        filled += fill as u64;
+    }
+    Ok(filled)
+}
+
+fn tree_index(side: Side) -> usize {
+    match side {
+        Side::Bid => 0,
+        Side::Ask => 1,
+    }
+}
+
+fn init(book: &AccountInfo) -> ProgramResult {
+    let mut data = book.try_borrow_mut_data()?;
+    if data.len() < NON_TREE_DATA_SIZE {
+        return Err(ProgramError::AccountDataTooSmall);
+    }
+    let header = data.as_mut_ptr().wrapping_add(HEADER_OFFSET) as *mut TreeHeader;
+    if unsafe { header.read_unaligned() }.magic != 0 {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+    let fresh = TreeHeader::new(NON_TREE_DATA_SIZE).with_key::<OrderKey>();
+    unsafe { header.write_unaligned(fresh) };
+    data[BID_ROOT_OFFSET..SEQUENCE_OFFSET].fill(0xFF);
+    Ok(())
+}
+
+/// Takes the next order sequence from the book.
+fn next_sequence(book: &AccountInfo) -> Result<u64, ProgramError> {
+    let mut data = book.try_borrow_mut_data()?;
+    let word = &mut data[SEQUENCE_OFFSET..BITMAP_OFFSET];
+    let sequence = u64::from_le_bytes(word.try_into().unwrap());
+    word.copy_from_slice(&(sequence + 1).to_le_bytes());
+    Ok(sequence)
+}
+
+/// Opens the bid and ask trees of `book`. The data is not kept borrowed, as
+/// inserts reallocate the account.
+fn open(book: &AccountInfo) -> Result<Forest, ProgramError> {
+    let len = book.data_len();
+    if len < NON_TREE_DATA_SIZE {
+        return Err(ProgramError::AccountDataTooSmall);
+    }
+    let base = book.try_borrow_mut_data()?.as_mut_ptr();
+    unsafe {
+        Forest::new(
+            open_allocator(base.add(BITMAP_OFFSET)),
+            base.add(REGION_START) as *mut u64,
+            NON_TREE_DATA_SIZE,
+            base.add(HEADER_OFFSET) as *mut TreeHeader,
+            len.saturating_sub(REGION_START),
+            &[
+                base.add(BID_ROOT_OFFSET) as *mut u32,
+                base.add(ASK_ROOT_OFFSET) as *mut u32,
+            ],
+        )
+        .map_err(ProgramError::from)
+    }
+}
+
+/// Slot allocator over the `CAPACITY`-bit bitmap of the book.
+fn open_allocator(bitmap: *mut u8) -> MemoryMap {
+    MemoryMap::new(bitmap, CAPACITY)
+}
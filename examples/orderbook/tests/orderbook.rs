@@ -0,0 +1,209 @@
+use orderbook::{
+    instruction, BookInstruction, OrderKey, ASK_ROOT_OFFSET, BID_ROOT_OFFSET, HEADER_OFFSET,
+    NON_TREE_DATA_SIZE,
+};
+use rb_tree::{
+    bench::{BenchRecord, BenchReport},
+    Side, TreeView,
+};
+use solana_program_test::{processor, BanksClient, ProgramTest};
+use solana_sdk::{
+    account::Account, pubkey::Pubkey, rent::Rent, signature::Keypair, signer::Signer,
+    transaction::Transaction,
+};
+
+struct Book {
+    banks: BanksClient,
+    payer: Keypair,
+    program_id: Pubkey,
+    book: Pubkey,
+    report: BenchReport,
+}
+
+impl Book {
+    async fn start() -> Self {
+        let program_id = Pubkey::new_unique();
+        let book = Pubkey::new_unique();
+        let mut test = ProgramTest::new(
+            "orderbook",
+            program_id,
+            processor!(orderbook::process_instruction),
+        );
+        test.add_account(
+            book,
+            Account {
+                lamports: Rent::default().minimum_balance(NON_TREE_DATA_SIZE),
+                data: vec![0; NON_TREE_DATA_SIZE],
+                owner: program_id,
+                ..Account::default()
+            },
+        );
+        let (banks, payer, _) = test.start().await;
+        let mut book = Self {
+            banks,
+            payer,
+            program_id,
+            book,
+            report: BenchReport::default(),
+        };
+        book.send("init", BookInstruction::Init).await;
+        book
+    }
+
+    /// Runs `op`, recording its compute units, and returns its return data.
+    async fn send(&mut self, name: &str, op: BookInstruction) -> Vec<u8> {
+        let tree_size =
+            self.orders(Side::Bid).await.len() + self.orders(Side::Ask).await.len();
+        let blockhash = self.banks.get_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction(
+                &self.program_id,
+                &self.book,
+                &self.payer.pubkey(),
+                &op,
+            )],
+            Some(&self.payer.pubkey()),
+            &[&self.payer],
+            blockhash,
+        );
+        let outcome = self
+            .banks
+            .process_transaction_with_metadata(transaction)
+            .await
+            .unwrap();
+        outcome.result.unwrap();
+        let metadata = outcome.metadata.unwrap();
+        self.report.push(BenchRecord {
+            op: name.to_string(),
+            tree_size: tree_size as u64,
+            compute_units: Some(metadata.compute_units_consumed),
+            bytes_written: 0,
+            rotations: 0,
+        });
+        metadata
+            .return_data
+            .map(|data| data.data)
+            .unwrap_or_default()
+    }
+
+    async fn place(&mut self, side: Side, price: u64, quantity: u32) -> u64 {
+        let data = self
+            .send(
+                "place",
+                BookInstruction::Place {
+                    side,
+                    price,
+                    quantity,
+                },
+            )
+            .await;
+        return_word(data)
+    }
+
+    /// `(price, sequence, quantity)` of the resting orders of `side`, best
+    /// first, read off-chain through a `TreeView`.
+    async fn orders(&mut self, side: Side) -> Vec<(u64, u64, u32)> {
+        let account = self.banks.get_account(self.book).await.unwrap();
+        let data = account.map(|account| account.data).unwrap_or_default();
+        if data.len() < NON_TREE_DATA_SIZE {
+            return Vec::new();
+        }
+        let root_offset = match side {
+            Side::Bid => BID_ROOT_OFFSET,
+            Side::Ask => ASK_ROOT_OFFSET,
+        };
+        let view =
+            TreeView::<OrderKey>::new(&data, NON_TREE_DATA_SIZE, root_offset, Some(HEADER_OFFSET))
+                .unwrap();
+        view.iter()
+            .map(|(key, quantity)| (key.price(side), key.sequence, quantity))
+            .collect()
+    }
+}
+
+/// Decodes a `u64` return value; the runtime trims trailing zero bytes.
+fn return_word(data: Vec<u8>) -> u64 {
+    let mut bytes = [0; 8];
+    bytes[..data.len()].copy_from_slice(&data);
+    u64::from_le_bytes(bytes)
+}
+
+#[tokio::test]
+async fn place_cancel_match() {
+    let mut book = Book::start().await;
+
+    let ask_101 = book.place(Side::Ask, 101, 5).await;
+    let ask_100 = book.place(Side::Ask, 100, 3).await;
+    let ask_100_later = book.place(Side::Ask, 100, 4).await;
+    let bid_99 = book.place(Side::Bid, 99, 7).await;
+    let bid_98 = book.place(Side::Bid, 98, 2).await;
+    assert_eq!(
+        book.orders(Side::Ask).await,
+        vec![(100, ask_100, 3), (100, ask_100_later, 4), (101, ask_101, 5)]
+    );
+    assert_eq!(book.orders(Side::Bid).await, vec![(99, bid_99, 7), (98, bid_98, 2)]);
+
+    book.send(
+        "cancel",
+        BookInstruction::Cancel {
+            side: Side::Bid,
+            price: 98,
+            sequence: bid_98,
+        },
+    )
+    .await;
+    assert_eq!(book.orders(Side::Bid).await, vec![(99, bid_99, 7)]);
+
+    // Takes both orders at 100 in time priority and part of the one at 101.
+    let filled = book
+        .send(
+            "match",
+            BookInstruction::Match {
+                side: Side::Bid,
+                limit: 101,
+                quantity: 9,
+            },
+        )
+        .await;
+    assert_eq!(return_word(filled), 9);
+    assert_eq!(book.orders(Side::Ask).await, vec![(101, ask_101, 3)]);
+
+    // Nothing rests at or above 100 on the bid side.
+    let filled = book
+        .send(
+            "match",
+            BookInstruction::Match {
+                side: Side::Ask,
+                limit: 100,
+                quantity: 1,
+            },
+        )
+        .await;
+    assert_eq!(return_word(filled), 0);
+
+    println!("{}", book.report.to_json());
+}
+
+#[tokio::test]
+async fn book_grows_with_orders() {
+    let mut book = Book::start().await;
+    for i in 0..64 {
+        book.place(Side::Ask, 1_000 + i % 8, 1).await;
+    }
+    let asks = book.orders(Side::Ask).await;
+    assert_eq!(asks.len(), 64);
+    assert!(asks.windows(2).all(|pair| (pair[0].0, pair[0].1) < (pair[1].0, pair[1].1)));
+    let filled = book
+        .send(
+            "match",
+            BookInstruction::Match {
+                side: Side::Bid,
+                limit: u64::MAX,
+                quantity: 64,
+            },
+        )
+        .await;
+    assert_eq!(return_word(filled), 64);
+    assert!(book.orders(Side::Ask).await.is_empty());
+    println!("{}", book.report.to_json());
+}
@@ -0,0 +1,10 @@
+use index_mem_alloc::MemoryMap;
+use orderbook::CAPACITY;
+use rb_tree::testing::check_realloc_stability;
+
+#[test]
+fn iteration_survives_account_growth() {
+    let mut bitmap = vec![0u8; CAPACITY / 8];
+    let inserts = check_realloc_stability(MemoryMap::new(bitmap.as_mut_ptr(), CAPACITY));
+    assert!(inserts > 0);
+}
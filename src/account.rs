@@ -0,0 +1,179 @@
+use crate::{
+    forest::claim_handles, in_region, node_stride, region_slots, RBTree, TreeError, TreeHeader,
+    LAYOUT_EXACT_STRIDE, NULL_NODE, ROOT_UNRECORDED, TREE_MAGIC, TREE_VERSION,
+};
+use index_mem_alloc::MemoryMap;
+use solana_program::account_info::AccountInfo;
+use std::{cell::Cell, mem::size_of, ops::Range, ptr};
+
+/// Checks that the root word, the header, if any, and the allocator storage
+/// at `bitmap` lie inside the first `non_tree_data_size` bytes without
+/// overlapping.
+pub(crate) fn check_non_tree_layout(
+    non_tree_data_size: usize,
+    root_offset: usize,
+    header_offset: Option<usize>,
+    bitmap: &Range<usize>,
+) -> Result<(), TreeError> {
+    let root = root_offset..root_offset + size_of::<u32>();
+    let header = header_offset.map(|offset| offset..offset + size_of::<TreeHeader>());
+    let words: Vec<&Range<usize>> = [Some(&root), header.as_ref(), Some(bitmap)]
+        .into_iter()
+        .flatten()
+        .collect();
+    let fits = words.iter().all(|word| word.end <= non_tree_data_size);
+    let disjoint = words.iter().enumerate().all(|(i, a)| {
+        words[i + 1..]
+            .iter()
+            .all(|b| a.end <= b.start || b.end <= a.start)
+    });
+    if !fits || !disjoint {
+        return Err(TreeError::InvalidLayout);
+    }
+    Ok(())
+}
+
+impl RBTree {
+    /// Formats a fresh account for a tree of `T` keys and opens it: clears the
+    /// allocator storage at `bitmap`, writes the empty root at `root_offset`
+    /// and, unless `header_offset` is `None`, a header recording the key size
+    /// and root offset. All three must lie inside the first
+    /// `non_tree_data_size` bytes without overlapping.
+    ///
+    /// `bitmap` holds the storage of an allocator of `slots` slots, sized as
+    /// [`index-mem-alloc` v0.1.4](https://github.com/deriverse/index-mem-alloc/tree/v0.1.4)
+    /// requires, and the handle's allocator is opened over it with
+    /// `MemoryMap::new`. The tree only allocates and frees slots through the
+    /// allocator, so the layout of the cleared bytes is not assumed here.
+    ///
+    /// The handle points into `data` like one built by hand, so it must not
+    /// outlive the borrow. Accounts whose header is already written are
+    /// refused with [`TreeError::AlreadyInitialized`].
+    pub fn init<T>(
+        data: &mut [u8],
+        non_tree_data_size: usize,
+        root_offset: usize,
+        header_offset: Option<usize>,
+        bitmap: Range<usize>,
+        slots: usize,
+    ) -> Result<Self, TreeError> {
+        check_non_tree_layout(non_tree_data_size, root_offset, header_offset, &bitmap)?;
+        if data.len() < non_tree_data_size {
+            return Err(TreeError::InvalidLayout);
+        }
+        if let Some(header) = header_offset {
+            let magic = unsafe { ptr::read_unaligned(data[header..].as_ptr() as *const u32) };
+            if magic == TREE_MAGIC {
+                return Err(TreeError::AlreadyInitialized);
+            }
+        }
+
+        let base = data.as_mut_ptr();
+        let root = base.wrapping_add(root_offset) as *mut u32;
+        claim_handles(&[root], base as usize..base as usize + data.len())?;
+        data[bitmap.clone()].fill(0);
+        data[root_offset..root_offset + size_of::<u32>()].copy_from_slice(&NULL_NODE.to_le_bytes());
+        let header = match header_offset {
+            Some(offset) => unsafe {
+                let header = base.add(offset) as *mut TreeHeader;
+                header.write_unaligned(
+                    TreeHeader::new(non_tree_data_size)
+                        .with_key::<T>()
+                        .with_root_offset(root_offset),
+                );
+                header
+            },
+            None => ptr::null_mut(),
+        };
+        let region_start = match header_offset {
+            Some(_) => RBTree::region_start(non_tree_data_size),
+            None => non_tree_data_size,
+        };
+        Ok(RBTree {
+            pt: MemoryMap::new(base.wrapping_add(bitmap.start), slots),
+            root,
+            // A fresh account may end inside the padding; its region is empty.
+            entry: base.wrapping_add(region_start) as *mut u64,
+            non_tree_data_size,
+            header,
+            region_len: Cell::new(data.len().saturating_sub(region_start)),
+            grown_from: Cell::new(None),
+        })
+    }
+    /// Opens the tree of `T` keys in `account` from its header at
+    /// `header_offset`, which must record the root offset, as
+    /// [`RBTree::init`] does. Before any node is touched it checks the
+    /// header against this build and `T`, that the account is large enough
+    /// for the node count the header claims and that the root sref lies
+    /// inside the node region. `pt` is the account's allocator.
+    ///
+    /// Like [`RBTree::init`], the handle does not keep the data borrowed; see
+    /// [`TreeGuard`](crate::TreeGuard) for a handle that does.
+    pub fn load<T>(
+        account: &AccountInfo,
+        pt: MemoryMap,
+        header_offset: usize,
+    ) -> Result<Self, TreeError> {
+        // A live borrow means another handle is using the data.
+        let mut data = account
+            .try_borrow_mut_data()
+            .map_err(|_| TreeError::AliasedHandle)?;
+        Self::load_data::<T>(&mut data, pt, header_offset)
+    }
+
+    /// [`RBTree::load`] over account data already borrowed.
+    pub(crate) fn load_data<T>(
+        data: &mut [u8],
+        pt: MemoryMap,
+        header_offset: usize,
+    ) -> Result<Self, TreeError> {
+        let header = data
+            .get(header_offset..header_offset + size_of::<TreeHeader>())
+            .ok_or(TreeError::InvalidLayout)?;
+        let header = unsafe { ptr::read_unaligned(header.as_ptr() as *const TreeHeader) };
+        if header.magic != TREE_MAGIC || header.version != TREE_VERSION {
+            return Err(TreeError::InvalidHeader);
+        }
+        header.check_layout()?;
+        header.check_key_size::<T>()?;
+        let non_tree_data_size = header.non_tree_data_size as usize;
+        let root_offset = header.root_offset as usize;
+        if header.root_offset == ROOT_UNRECORDED
+            || data.len() < non_tree_data_size
+            || header_offset + size_of::<TreeHeader>() > non_tree_data_size
+            || root_offset + size_of::<u32>() > non_tree_data_size
+        {
+            return Err(TreeError::InvalidLayout);
+        }
+
+        let region_start = header.region_start();
+        let region_len = data.len().saturating_sub(region_start);
+        let stride = node_stride::<T, u32>(header.node_layout & LAYOUT_EXACT_STRIDE != 0);
+        let capacity = region_slots::<T, u32>(region_len, stride);
+        if header.node_count as usize > capacity {
+            return Err(TreeError::CapacityMismatch {
+                capacity: capacity as u32,
+                allocated: header.node_count,
+            });
+        }
+        let root = unsafe { ptr::read_unaligned(data[root_offset..].as_ptr() as *const u32) };
+        if root != NULL_NODE && !in_region::<T, u32>(root, region_len, stride) {
+            return Err(TreeError::Corrupted { sref: root });
+        }
+
+        let base = data.as_mut_ptr();
+        let root = base.wrapping_add(root_offset) as *mut u32;
+        claim_handles(&[root], base as usize..base as usize + data.len())?;
+        let tree = RBTree {
+            pt,
+            root,
+            entry: base.wrapping_add(region_start) as *mut u64,
+            non_tree_data_size,
+            header: base.wrapping_add(header_offset) as *mut TreeHeader,
+            region_len: Cell::new(region_len),
+            grown_from: Cell::new(None),
+        };
+        tree.check_header()?;
+        Ok(tree)
+    }
+}
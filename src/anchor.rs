@@ -0,0 +1,108 @@
+//! Trees in Anchor zero-copy accounts. Such an account holds the type's
+//! discriminator, then the type itself, then the node region: the type is the
+//! tree's non-tree data, and keeps the header, root word and allocator
+//! storage in fields of its own. [`TreeAccount`] names those fields by their
+//! offsets within the type; the discriminator in front of them is accounted
+//! for here rather than in every program. [`init_tree`] clears the allocator
+//! storage and opens the allocator over it, as [`RBTree::init`] does; other
+//! instructions open it themselves and hand it to [`load_tree`].
+//!
+//! ```ignore
+//! #[account(zero_copy)]
+//! pub struct Book {
+//!     pub tree_header: [u8; size_of::<TreeHeader>()],
+//!     pub root: u32,
+//!     pub bitmap: [u8; CAPACITY / 8],
+//!     pub authority: Pubkey,
+//! }
+//!
+//! impl TreeAccount for Book {
+//!     const HEADER_OFFSET: usize = offset_of!(Book, tree_header);
+//!     const ROOT_OFFSET: usize = offset_of!(Book, root);
+//!     const BITMAP_OFFSET: usize = offset_of!(Book, bitmap);
+//!     const BITMAP_LEN: usize = CAPACITY / 8;
+//!     const CAPACITY: usize = CAPACITY;
+//! }
+//!
+//! // In the instruction creating the account (`#[account(zero)]`):
+//! let tree = init_tree::<Book, OrderKey>(&ctx.accounts.book)?;
+//! // In the others, with the program's allocator over `Book::bitmap`:
+//! let pt = book_allocator(&ctx.accounts.book)?;
+//! let tree = load_tree::<Book, OrderKey>(&ctx.accounts.book, pt)?;
+//! ```
+//!
+//! Only `AccountLoader` is supported: an `Account` serializes its type back
+//! over the data when the instruction ends, overwriting the header and root
+//! word the tree maintains.
+
+use crate::{RBTree, TreeError};
+use anchor_lang::{prelude::AccountLoader, Owner, ZeroCopy};
+use index_mem_alloc::MemoryMap;
+use std::mem::size_of;
+
+/// Zero-copy account type holding the header, root word and allocator
+/// storage of a tree, at the offsets of its fields (e.g. from `offset_of!`).
+/// The node region follows the type.
+pub trait TreeAccount: ZeroCopy + Owner {
+    /// Offset of a `[u8; size_of::<TreeHeader>()]` field for the header.
+    const HEADER_OFFSET: usize;
+    /// Offset of the `u32` root word.
+    const ROOT_OFFSET: usize;
+    /// Offset of the allocator storage, of `BITMAP_LEN` bytes.
+    const BITMAP_OFFSET: usize;
+    const BITMAP_LEN: usize;
+    /// Slots of the allocator.
+    const CAPACITY: usize;
+    /// Bytes in front of the node region: the discriminator and the type.
+    const NON_TREE_DATA_SIZE: usize = Self::DISCRIMINATOR.len() + size_of::<Self>();
+}
+
+/// Offset in the account of the field at `offset` in `A`.
+fn account_offset<A: TreeAccount>(offset: usize) -> usize {
+    A::DISCRIMINATOR.len() + offset
+}
+
+/// Formats the tree of an account being created and opens it, see
+/// [`RBTree::init`]. Call it in the instruction creating the account, whose
+/// discriminator Anchor writes when the instruction ends.
+pub fn init_tree<A: TreeAccount, T>(loader: &AccountLoader<A>) -> Result<RBTree, TreeError> {
+    let account = loader.as_ref();
+    // A live borrow means another handle is using the data.
+    let mut data = account
+        .try_borrow_mut_data()
+        .map_err(|_| TreeError::AliasedHandle)?;
+    let discriminator = data
+        .get(..A::DISCRIMINATOR.len())
+        .ok_or(TreeError::InvalidLayout)?;
+    if discriminator.iter().any(|&b| b != 0) {
+        return Err(TreeError::AlreadyInitialized);
+    }
+    let bitmap = account_offset::<A>(A::BITMAP_OFFSET);
+    RBTree::init::<T>(
+        &mut data,
+        A::NON_TREE_DATA_SIZE,
+        account_offset::<A>(A::ROOT_OFFSET),
+        Some(account_offset::<A>(A::HEADER_OFFSET)),
+        bitmap..bitmap + A::BITMAP_LEN,
+        A::CAPACITY,
+    )
+}
+
+/// Opens the tree of an initialized account after checking its
+/// discriminator, see [`RBTree::load`]. `pt` is the account's allocator.
+pub fn load_tree<A: TreeAccount, T>(
+    loader: &AccountLoader<A>,
+    pt: MemoryMap,
+) -> Result<RBTree, TreeError> {
+    let account = loader.as_ref();
+    {
+        // Only read here; a live mutable borrow is another handle.
+        let data = account
+            .try_borrow_data()
+            .map_err(|_| TreeError::AliasedHandle)?;
+        if data.get(..A::DISCRIMINATOR.len()) != Some(A::DISCRIMINATOR) {
+            return Err(TreeError::InvalidHeader);
+        }
+    }
+    RBTree::load::<T>(account, pt, account_offset::<A>(A::HEADER_OFFSET))
+}
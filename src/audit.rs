@@ -0,0 +1,47 @@
+use crate::{storage::Storage, NodePtr, RBTree};
+
+/// Slot usage found by [`RBTree::audit_slots`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SlotAudit {
+    /// Slots the allocator reports as allocated.
+    pub allocated: u32,
+    /// Slots the allocator reports as free.
+    pub free: u32,
+    /// Free slots still holding what looks like a node (its sref field matches
+    /// the slot), i.e. data left behind by a delete.
+    pub stale: u32,
+    /// Allocated slots whose sref field does not match their position,
+    /// including slots outside the node region.
+    pub mismatched: Vec<u32>,
+}
+
+impl RBTree {
+    /// Classifies the first `slots` node slots by allocator state and contents.
+    /// Meant for off-chain investigation of account dumps; it reads every slot
+    /// that lies within the node region.
+    pub fn audit_slots<T>(&self, slots: u32) -> SlotAudit {
+        let mut audit = SlotAudit::default();
+        for sref in 0..slots {
+            let node = unsafe {
+                NodePtr::<T>::bounded(
+                    self.entry,
+                    self.region_len.get(),
+                    self.stride::<T, u32>(),
+                    sref,
+                )
+            };
+            if self.pt.is_allocated(sref as usize) {
+                audit.allocated += 1;
+                if node.sref() != sref {
+                    audit.mismatched.push(sref);
+                }
+            } else {
+                audit.free += 1;
+                if node.sref() == sref {
+                    audit.stale += 1;
+                }
+            }
+        }
+        audit
+    }
+}
@@ -0,0 +1,164 @@
+//! Per-subtree aggregates kept in the node values. The balancing code reports
+//! every structural change through the [`Storage`] hooks, and [`Augmented`]
+//! answers them by recomputing the aggregates of the affected nodes from
+//! their children, so any aggregate that can be computed that way (a total,
+//! a count, a maximum) stays exact through inserts, deletes and rotations.
+
+use crate::{storage::Storage, Node, NodePtr, Payer, RBTree, TreeError};
+use solana_program::account_info::AccountInfo;
+use std::marker::PhantomData;
+
+/// Node value carrying an aggregate over the subtree rooted at its node, in
+/// a tree with keys of type `K`.
+pub trait Augment<K>: Copy {
+    /// Recomputes the aggregate from the node's key and own data and the
+    /// values of its children.
+    fn refresh(&mut self, key: &K, left: Option<&Self>, right: Option<&Self>);
+}
+
+/// An [`RBTree`] whose nodes hold `K` keys and `A` values, balanced with
+/// their aggregates kept up to date.
+pub(crate) struct Augmented<K, A> {
+    pub(crate) tree: RBTree,
+    _node: PhantomData<(K, A)>,
+}
+
+impl<K: Copy, A: Augment<K>> Storage for Augmented<K, A> {
+    #[inline]
+    fn root_word(&self) -> *mut u32 {
+        self.tree.root
+    }
+    #[inline]
+    fn node_entry(&self) -> *mut u64 {
+        self.tree.entry
+    }
+    #[inline]
+    fn node_region_len(&self) -> usize {
+        self.tree.region_len.get()
+    }
+    #[inline]
+    fn stride<T, V>(&self) -> usize {
+        self.tree.stride::<T, V>()
+    }
+    fn rotated<T: Copy, V>(&self, lower: NodePtr<T, V>, upper: NodePtr<T, V>) {
+        // The balancing code is generic over the node type; every node
+        // reaching here belongs to this tree and is a `Node<K, A>`.
+        refresh(augmented::<T, V, K, A>(lower));
+        refresh(augmented::<T, V, K, A>(upper));
+    }
+    fn propagate<T: Copy, V>(&self, from: NodePtr<T, V>) {
+        refresh_path(augmented::<T, V, K, A>(from));
+    }
+}
+
+impl<K: Copy + Ord, A: Augment<K>> Augmented<K, A> {
+    pub(crate) fn new(tree: RBTree) -> Self {
+        Self {
+            tree,
+            _node: PhantomData,
+        }
+    }
+
+    /// Inserts `key` with `value`, after any equal keys. Returns the new
+    /// node, or the error of the header check or of the allocation.
+    pub(crate) fn insert<'a, 'info>(
+        &self,
+        key: K,
+        value: A,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<NodePtr<K, A>, TreeError> {
+        let node = self
+            .tree
+            .new_node(key, value, tree_acc, payer, system_program)?;
+        let mut parent = NodePtr::null();
+        let mut next = self.root_node::<K, A>();
+        while !next.is_null() {
+            parent = next;
+            next = if key < next.key() {
+                next.left()
+            } else {
+                next.right()
+            };
+        }
+        self.link(parent, node);
+        self.tree.touch();
+        Ok(node)
+    }
+
+    /// Removes a node holding `key` and returns its value, or `None` if there
+    /// is none. Fails without changing the tree as
+    /// [`RBTree::try_delete`] does.
+    pub(crate) fn remove(&mut self, key: K) -> Result<Option<A>, TreeError> {
+        self.tree.check_header()?;
+        let node = self.search::<K, A>(key);
+        if node.is_null() {
+            return Ok(None);
+        }
+        let sref = node.sref();
+        if !self.tree.pt.is_allocated(sref as usize) {
+            return Err(TreeError::DeallocFailed { sref });
+        }
+        self.tree.check_links(node)?;
+        let value = node.value();
+        self.unlink(node);
+        self.tree
+            .pt
+            .dealloc(sref as usize)
+            .map_err(|_| TreeError::DeallocFailed { sref })?;
+        self.tree.count_node(false);
+        self.tree.touch();
+        Ok(Some(value))
+    }
+
+    /// Changes the value of the live node at `sref` through `update`, then
+    /// refreshes the aggregates from it to the root. Returns `false` if
+    /// `sref` is not a live node.
+    pub(crate) fn update(&self, sref: u32, update: impl FnOnce(&mut A)) -> bool {
+        let node = self.tree.live_node::<K, A>(sref);
+        if node.is_null() {
+            return false;
+        }
+        let mut value = node.value();
+        update(&mut value);
+        node.set_value(value);
+        refresh_path(node);
+        self.tree.touch();
+        true
+    }
+}
+
+fn augmented<T, V, K, A>(node: NodePtr<T, V>) -> NodePtr<K, A> {
+    NodePtr(node.0 as *mut Node<K, A>, node.1, node.2, node.3)
+}
+
+/// Value of `node`, or `None` for a null node.
+pub(crate) fn value_of<K, A: Copy>(node: NodePtr<K, A>) -> Option<A> {
+    if node.is_null() {
+        return None;
+    }
+    Some(node.value())
+}
+
+/// Recomputes the aggregate of `node` from its children.
+fn refresh<K: Copy, A: Augment<K>>(node: NodePtr<K, A>) {
+    if node.is_null() {
+        return;
+    }
+    let mut value = node.value();
+    value.refresh(
+        &node.key(),
+        value_of(node.left()).as_ref(),
+        value_of(node.right()).as_ref(),
+    );
+    node.set_value(value);
+}
+
+/// Recomputes the aggregates from `node` up to the root.
+pub(crate) fn refresh_path<K: Copy, A: Augment<K>>(mut node: NodePtr<K, A>) {
+    while !node.is_null() {
+        refresh(node);
+        node = node.parent();
+    }
+}
@@ -0,0 +1,383 @@
+//! Benchmark reports: per-operation measurements written as JSON, so runs can
+//! be attached to PRs, published, and diffed against a baseline.
+//!
+//! Off-chain runs fill in bytes written and rotations through
+//! [`measure`]; compute units are only known inside a validator, so harnesses
+//! running under program-test set them from the transaction logs with
+//! [`consumed_units`]. [`trace_access`] counts the nodes an operation reads
+//! and how far apart in the account they lie, a stand-in for the memory
+//! operations it costs on SBF.
+
+use crate::RBTree;
+use std::{
+    cell::Cell,
+    fmt::{self, Write},
+    slice,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+static ROTATIONS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// Running [`AccessStats`] and the offset of the last node visited.
+    static ACCESS: Cell<(AccessStats, Option<u64>)> = const { Cell::new((AccessStats::ZERO, None)) };
+}
+
+/// Counts one rotation, called from the rotate routines.
+pub(crate) fn count_rotation() {
+    ROTATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a visit to the node at byte `offset` of the node region, called
+/// whenever a link is followed.
+pub(crate) fn count_visit(offset: u64) {
+    ACCESS.with(|access| {
+        let (mut stats, last) = access.get();
+        stats.visits += 1;
+        stats.spread += last.map_or(0, |last| last.abs_diff(offset));
+        access.set((stats, Some(offset)));
+    });
+}
+
+/// Node reads of an operation, see [`trace_access`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccessStats {
+    /// Nodes reached by following a link, the root included.
+    pub visits: u64,
+    /// Bytes between consecutively visited nodes, summed: low when the
+    /// operation walks the account data front to back.
+    pub spread: u64,
+}
+
+impl AccessStats {
+    const ZERO: Self = Self {
+        visits: 0,
+        spread: 0,
+    };
+}
+
+/// Runs `f` and counts the nodes it visits on this thread.
+pub fn trace_access<R>(f: impl FnOnce() -> R) -> (R, AccessStats) {
+    let outer = ACCESS.with(|access| access.replace((AccessStats::ZERO, None)));
+    let result = f();
+    let (stats, _) = ACCESS.with(|access| access.replace(outer));
+    (result, stats)
+}
+
+/// Measurements of one operation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BenchRecord {
+    pub op: String,
+    /// Nodes in the tree before the operation.
+    pub tree_size: u64,
+    pub compute_units: Option<u64>,
+    /// Bytes of the node region changed by the operation.
+    pub bytes_written: u64,
+    pub rotations: u64,
+}
+
+/// Runs `f` against `tree` and records the node region bytes it changed and
+/// the rotations it performed. The tree needs a bounded `region_len`.
+pub fn measure<T: Copy, R>(
+    tree: &mut RBTree,
+    op: &str,
+    f: impl FnOnce(&mut RBTree) -> R,
+) -> (R, BenchRecord) {
+    let tree_size = tree.iter::<T>().count() as u64;
+    let before = region(tree).to_vec();
+    let rotations = ROTATIONS.load(Ordering::Relaxed);
+    let result = f(tree);
+    let rotations = ROTATIONS.load(Ordering::Relaxed) - rotations;
+    let after = region(tree);
+    let bytes_written = before.iter().zip(after).filter(|(a, b)| a != b).count()
+        + after.len().saturating_sub(before.len());
+    let record = BenchRecord {
+        op: op.to_string(),
+        tree_size,
+        compute_units: None,
+        bytes_written: bytes_written as u64,
+        rotations,
+    };
+    (result, record)
+}
+
+fn region(tree: &RBTree) -> &[u8] {
+    unsafe { slice::from_raw_parts(tree.entry as *const u8, tree.region_len.get()) }
+}
+
+/// Compute units reported by a `Program ... consumed N of M compute units`
+/// log line.
+pub fn consumed_units(log: &str) -> Option<u64> {
+    let (_, rest) = log.split_once(" consumed ")?;
+    rest.split_once(" of ")?.0.parse().ok()
+}
+
+/// A set of [`BenchRecord`]s, serialized as a JSON array of flat objects.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BenchReport {
+    pub records: Vec<BenchRecord>,
+}
+
+impl BenchReport {
+    pub fn push(&mut self, record: BenchRecord) {
+        self.records.push(record);
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("[\n");
+        for (i, record) in self.records.iter().enumerate() {
+            let compute_units = record
+                .compute_units
+                .map_or_else(|| "null".to_string(), |units| units.to_string());
+            let _ = write!(
+                json,
+                "  {{\"op\": \"{}\", \"tree_size\": {}, \"compute_units\": {}, \"bytes_written\": {}, \"rotations\": {}}}",
+                escape(&record.op),
+                record.tree_size,
+                compute_units,
+                record.bytes_written,
+                record.rotations,
+            );
+            json.push_str(if i + 1 < self.records.len() {
+                ",\n"
+            } else {
+                "\n"
+            });
+        }
+        json.push(']');
+        json
+    }
+
+    /// Parses a report written by [`BenchReport::to_json`]. Unknown fields are
+    /// ignored so newer reports can still be compared against older ones.
+    pub fn from_json(json: &str) -> Result<Self, ReportError> {
+        let mut parser = Parser {
+            input: json.as_bytes(),
+            pos: 0,
+        };
+        let mut report = Self::default();
+        parser.expect(b'[')?;
+        if parser.peek() == Some(b']') {
+            return Ok(report);
+        }
+        loop {
+            report.push(parser.record()?);
+            match parser.next()? {
+                b',' => continue,
+                b']' => return Ok(report),
+                _ => return Err(parser.error()),
+            }
+        }
+    }
+
+    /// Pairs every record with the baseline record of the same operation and
+    /// tree size. Records without a baseline counterpart are left out.
+    pub fn compare(&self, baseline: &Self) -> Comparison {
+        let mut comparison = Comparison::default();
+        for current in &self.records {
+            let same_run =
+                |base: &&BenchRecord| base.op == current.op && base.tree_size == current.tree_size;
+            if let Some(base) = baseline.records.iter().find(same_run) {
+                comparison.deltas.push(BenchDelta {
+                    baseline: base.clone(),
+                    current: current.clone(),
+                });
+            }
+        }
+        comparison
+    }
+}
+
+/// A record and its baseline counterpart.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BenchDelta {
+    pub baseline: BenchRecord,
+    pub current: BenchRecord,
+}
+
+/// Result of [`BenchReport::compare`], displayed as one line per operation and
+/// tree size.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Comparison {
+    pub deltas: Vec<BenchDelta>,
+}
+
+impl Comparison {
+    /// Whether any metric got worse.
+    pub fn regressed(&self) -> bool {
+        self.deltas.iter().any(|delta| {
+            let (base, current) = (&delta.baseline, &delta.current);
+            current.bytes_written > base.bytes_written
+                || current.rotations > base.rotations
+                || matches!(
+                    (base.compute_units, current.compute_units),
+                    (Some(base), Some(current)) if current > base
+                )
+        })
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for delta in &self.deltas {
+            let (base, current) = (&delta.baseline, &delta.current);
+            write!(f, "{} n={}:", current.op, current.tree_size)?;
+            if let (Some(base), Some(current)) = (base.compute_units, current.compute_units) {
+                write!(f, " cu {}", change(base, current))?;
+            }
+            writeln!(
+                f,
+                " bytes {} rotations {}",
+                change(base.bytes_written, current.bytes_written),
+                change(base.rotations, current.rotations),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn change(base: u64, current: u64) -> String {
+    if base == 0 {
+        return format!("{base} -> {current}");
+    }
+    let percent = (current as f64 - base as f64) * 100.0 / base as f64;
+    format!("{base} -> {current} ({percent:+.1}%)")
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// A report is not valid JSON of the expected shape.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReportError {
+    /// Byte offset of the offending input.
+    pub offset: usize,
+}
+
+impl fmt::Display for ReportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "malformed benchmark report at byte {}", self.offset)
+    }
+}
+
+impl std::error::Error for ReportError {}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self) -> ReportError {
+        ReportError { offset: self.pos }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        while self
+            .input
+            .get(self.pos)
+            .is_some_and(u8::is_ascii_whitespace)
+        {
+            self.pos += 1;
+        }
+        self.input.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<u8, ReportError> {
+        let byte = self.peek().ok_or_else(|| self.error())?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), ReportError> {
+        if self.next()? != byte {
+            return Err(self.error());
+        }
+        Ok(())
+    }
+
+    fn record(&mut self) -> Result<BenchRecord, ReportError> {
+        let mut record = BenchRecord {
+            op: String::new(),
+            tree_size: 0,
+            compute_units: None,
+            bytes_written: 0,
+            rotations: 0,
+        };
+        self.expect(b'{')?;
+        loop {
+            let field = self.string()?;
+            self.expect(b':')?;
+            match field.as_str() {
+                "op" => record.op = self.string()?,
+                "tree_size" => record.tree_size = self.number()?.ok_or_else(|| self.error())?,
+                "compute_units" => record.compute_units = self.number()?,
+                "bytes_written" => {
+                    record.bytes_written = self.number()?.ok_or_else(|| self.error())?
+                }
+                "rotations" => record.rotations = self.number()?.ok_or_else(|| self.error())?,
+                _ => self.skip_value()?,
+            }
+            match self.next()? {
+                b',' => continue,
+                b'}' => return Ok(record),
+                _ => return Err(self.error()),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ReportError> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            let byte = *self.input.get(self.pos).ok_or_else(|| self.error())?;
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    bytes.push(*self.input.get(self.pos).ok_or_else(|| self.error())?);
+                    self.pos += 1;
+                }
+                _ => bytes.push(byte),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error())
+    }
+
+    /// A number, or `None` for `null`.
+    fn number(&mut self) -> Result<Option<u64>, ReportError> {
+        self.peek();
+        let start = self.pos;
+        while self
+            .input
+            .get(self.pos)
+            .is_some_and(u8::is_ascii_alphanumeric)
+        {
+            self.pos += 1;
+        }
+        match &self.input[start..self.pos] {
+            b"null" => Ok(None),
+            digits => std::str::from_utf8(digits)
+                .ok()
+                .and_then(|digits| digits.parse().ok())
+                .map(Some)
+                .ok_or(ReportError { offset: start }),
+        }
+    }
+
+    fn skip_value(&mut self) -> Result<(), ReportError> {
+        if self.peek() == Some(b'"') {
+            self.string()?;
+            return Ok(());
+        }
+        while self
+            .input
+            .get(self.pos)
+            .is_some_and(|&byte| byte != b',' && byte != b'}')
+        {
+            self.pos += 1;
+        }
+        Ok(())
+    }
+}
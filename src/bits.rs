@@ -0,0 +1,183 @@
+use crate::{sref::narrow, Node, NodePtr, RBTree, SrefWord};
+use std::fmt;
+
+/// Color and flag bits of a node, stored in the word that used to hold only
+/// the color. Bit 0 is the color (set for red); the remaining bits are flags,
+/// all clear today, so new flags do not need another layout change.
+///
+/// The full-word color was always 0 or 1, which reads back as the same color
+/// with no flags: existing accounts decode unchanged.
+///
+/// With the `packed-color` feature nodes have no color word: the color lives
+/// in the high bit of the parent link and flags are dropped when stored.
+#[repr(transparent)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct NodeBits(u32);
+
+impl NodeBits {
+    const RED_BIT: u32 = 1;
+    /// Bits available to flags, before shifting.
+    const FLAG_BITS: u32 = !Self::RED_BIT;
+
+    pub const BLACK: Self = Self(0);
+    pub const RED: Self = Self(Self::RED_BIT);
+
+    /// Reads a word written with the full-word color encoding, where any
+    /// nonzero value meant red.
+    pub const fn from_color_word(word: u32) -> Self {
+        Self((word != 0) as u32)
+    }
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn is_red(self) -> bool {
+        self.0 & Self::RED_BIT != 0
+    }
+
+    /// The same bits with the color replaced.
+    pub const fn with_red(self, red: bool) -> Self {
+        Self((self.0 & Self::FLAG_BITS) | red as u32)
+    }
+
+    /// Flag bits, shifted down past the color bit.
+    pub const fn flags(self) -> u32 {
+        (self.0 & Self::FLAG_BITS) >> 1
+    }
+
+    /// The same color with the flags replaced. Flags above bit 30 are dropped.
+    pub const fn with_flags(self, flags: u32) -> Self {
+        Self((self.0 & Self::RED_BIT) | (flags << 1))
+    }
+}
+
+impl fmt::Debug for NodeBits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NodeBits")
+            .field("red", &self.is_red())
+            .field("flags", &self.flags())
+            .finish()
+    }
+}
+
+impl RBTree {
+    /// Rewrites the color word of every allocated slot among the first `slots`
+    /// through [`NodeBits::from_color_word`], for accounts whose words may
+    /// hold values other than 0 and 1 (e.g. written by tooling that stored
+    /// other truthy values). Returns the number of nodes changed.
+    pub fn migrate_color_words<T>(&self, slots: u32) -> u32 {
+        let mut changed = 0;
+        for node in self.iter_slab::<T>(slots) {
+            let bits = node.bits();
+            let migrated = NodeBits::from_color_word(bits.bits());
+            if migrated != bits {
+                node.set_bits(migrated);
+                changed += 1;
+            }
+        }
+        if changed > 0 {
+            self.touch();
+        }
+        changed
+    }
+}
+
+impl<T, V> NodePtr<T, V> {
+    /// Color and flags of the node; black with no flags for a null pointer.
+    pub fn bits(&self) -> NodeBits {
+        if self.is_null() {
+            return NodeBits::BLACK;
+        }
+        unsafe { (*self.0).bits() }
+    }
+}
+
+/// Bit of the parent link holding the color with the `packed-color` feature.
+/// Srefs never reach it, see [`MAX_NODES`](crate::MAX_NODES).
+#[cfg(feature = "packed-color")]
+const PARENT_RED: SrefWord = 1 << (SrefWord::BITS - 1);
+
+impl<T, V> Node<T, V> {
+    /// Node with the given links, storing `bits` wherever this build keeps
+    /// the color.
+    pub(crate) fn new(
+        key: T,
+        parent: u32,
+        left: u32,
+        right: u32,
+        sref: u32,
+        bits: NodeBits,
+        link: V,
+    ) -> Self {
+        let mut node = Self {
+            key,
+            parent: narrow(parent),
+            left: narrow(left),
+            right: narrow(right),
+            sref: narrow(sref),
+            #[cfg(not(feature = "packed-color"))]
+            bits,
+            link,
+        };
+        node.set_bits(bits);
+        node
+    }
+
+    pub(crate) fn parent_sref(&self) -> u32 {
+        parent_sref_of(self.parent)
+    }
+
+    #[cfg(not(feature = "packed-color"))]
+    pub(crate) fn set_parent_sref(&mut self, sref: u32) {
+        self.parent = narrow(sref);
+    }
+
+    #[cfg(not(feature = "packed-color"))]
+    pub(crate) fn bits(&self) -> NodeBits {
+        self.bits
+    }
+
+    #[cfg(not(feature = "packed-color"))]
+    pub(crate) fn set_bits(&mut self, bits: NodeBits) {
+        self.bits = bits;
+    }
+
+    #[cfg(feature = "packed-color")]
+    pub(crate) fn set_parent_sref(&mut self, sref: u32) {
+        self.parent = (narrow(sref) & !PARENT_RED) | (self.parent & PARENT_RED);
+    }
+
+    #[cfg(feature = "packed-color")]
+    pub(crate) fn bits(&self) -> NodeBits {
+        NodeBits::BLACK.with_red(self.parent & PARENT_RED != 0)
+    }
+
+    /// Stores the color only, there being no room for flags.
+    #[cfg(feature = "packed-color")]
+    pub(crate) fn set_bits(&mut self, bits: NodeBits) {
+        self.parent = (self.parent & !PARENT_RED) | if bits.is_red() { PARENT_RED } else { 0 };
+    }
+}
+
+/// Parent sref held in a stored parent word.
+#[cfg(not(feature = "packed-color"))]
+#[inline]
+pub(crate) fn parent_sref_of(word: SrefWord) -> u32 {
+    crate::sref::widen(word)
+}
+
+/// Parent sref held in a stored parent word, with the color masked off; the
+/// null link keeps its remaining bits set.
+#[cfg(feature = "packed-color")]
+#[inline]
+pub(crate) fn parent_sref_of(word: SrefWord) -> u32 {
+    match word & !PARENT_RED {
+        sref if sref == SrefWord::MAX & !PARENT_RED => crate::NULL_NODE,
+        sref => crate::sref::widen(sref),
+    }
+}
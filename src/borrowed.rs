@@ -0,0 +1,238 @@
+use crate::{
+    storage::Storage, Entry, Iter, NodePtr, OpError, Operation, Phase, RBTree, Removed, TreeError,
+    TreeHeader, NULL_NODE,
+};
+use bytemuck::NoUninit;
+use index_mem_alloc::MemoryMap;
+use std::{
+    marker::PhantomData,
+    ops::{Range, RangeBounds},
+};
+
+/// Tree of `T` keys over account data borrowed for `'a`. Entries come out by
+/// value and nodes are named by sref only, so nothing it hands out reaches
+/// into the data after the borrow ends, and the borrow keeps the data from
+/// being read or written any other way while the tree is in use.
+///
+/// A slice cannot grow: an insert needing a slot past the end of the data
+/// fails with [`TreeError::NodeLimit`]. Size the account up front, e.g.
+/// with [`RBTree::reserve`], or insert through [`TreeGuard`](crate::TreeGuard)
+/// when it has to grow.
+pub struct RBTreeRef<'a, T> {
+    tree: RBTree,
+    _data: PhantomData<(&'a mut [u8], T)>,
+}
+
+impl<'a, T: Copy + Ord> RBTreeRef<'a, T> {
+    /// Formats `data` for a fresh tree, see [`RBTree::init`].
+    pub fn init(
+        data: &'a mut [u8],
+        non_tree_data_size: usize,
+        root_offset: usize,
+        header_offset: Option<usize>,
+        bitmap: Range<usize>,
+        slots: usize,
+    ) -> Result<Self, TreeError> {
+        RBTree::init::<T>(
+            data,
+            non_tree_data_size,
+            root_offset,
+            header_offset,
+            bitmap,
+            slots,
+        )
+        .map(Self::wrap)
+    }
+
+    /// Opens the tree in `data` from its header at `header_offset`, with the
+    /// checks of [`RBTree::load`]. `pt` is the account's allocator.
+    pub fn load(
+        data: &'a mut [u8],
+        pt: MemoryMap,
+        header_offset: usize,
+    ) -> Result<Self, TreeError> {
+        RBTree::load_data::<T>(data, pt, header_offset).map(Self::wrap)
+    }
+
+    fn wrap(tree: RBTree) -> Self {
+        Self {
+            tree,
+            _data: PhantomData,
+        }
+    }
+
+    pub fn header(&self) -> Option<TreeHeader> {
+        self.tree.header()
+    }
+
+    /// Number of entries, see [`RBTree::len`].
+    pub fn len(&self) -> usize {
+        self.tree.len::<T>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Inserts `key` after any nodes already holding it and returns the sref
+    /// of the new node, like [`RBTree::try_insert`] but within the data.
+    pub fn insert(&mut self, key: T, link: u32) -> Result<u32, TreeError> {
+        let node = self.tree.new_node_in_region(key, link)?;
+        self.tree.link_in_order(node);
+        self.tree.touch();
+        Ok(node.sref())
+    }
+
+    /// The entry of `key`, see [`RBTree::entry`].
+    pub fn entry(&mut self, key: T) -> Result<RefEntry<'_, T>, TreeError> {
+        self.tree.entry(key).map(RefEntry)
+    }
+
+    /// Sets the link of the node holding `key`, or inserts `key` with `link`
+    /// if there is none, see [`RBTree::upsert`].
+    pub fn upsert(&mut self, key: T, link: u32) -> Result<Option<u32>, OpError>
+    where
+        T: NoUninit,
+    {
+        let entry = self
+            .tree
+            .entry(key)
+            .map_err(|e| e.during(Operation::Insert, Phase::Validate, NULL_NODE, &key))?;
+        match entry {
+            Entry::Occupied(mut entry) => Ok(Some(entry.insert(link))),
+            Entry::Vacant(entry) => entry.insert_in_region(link).map(|_| None),
+        }
+    }
+
+    /// Link of a node holding `key`.
+    pub fn get(&self, key: T) -> Option<u32> {
+        let node = self.tree.search::<T, u32>(key);
+        (!node.is_null()).then(|| node.link())
+    }
+
+    pub fn contains_key(&self, key: T) -> bool {
+        !self.tree.search::<T, u32>(key).is_null()
+    }
+
+    /// Removes a node holding `key`, see [`RBTree::try_remove`].
+    pub fn remove(&mut self, key: T) -> Result<Removed<T>, OpError>
+    where
+        T: NoUninit,
+    {
+        self.tree.try_remove(key)
+    }
+
+    /// Removes the node at `sref`, see [`RBTree::delete_by_sref`].
+    pub fn delete_by_sref(&mut self, sref: u32) -> Result<Option<Removed<T>>, TreeError> {
+        self.tree.delete_by_sref(sref)
+    }
+
+    /// Removes every node whose key falls within `range`, handing each to
+    /// `removed`, see [`RBTree::remove_range`].
+    pub fn remove_range<R: RangeBounds<T>>(
+        &mut self,
+        range: R,
+        removed: impl FnMut(Removed<T>),
+    ) -> Result<usize, TreeError> {
+        self.tree.remove_range(range, removed)
+    }
+
+    /// Removes every node, see [`RBTree::clear`].
+    pub fn clear(&mut self) -> Result<(), OpError> {
+        self.tree.clear::<T>()
+    }
+
+    pub fn pop_first(&mut self) -> Result<Option<(T, u32)>, TreeError> {
+        self.tree.pop_first()
+    }
+
+    pub fn pop_last(&mut self) -> Result<Option<(T, u32)>, TreeError> {
+        self.tree.pop_last()
+    }
+
+    pub fn first(&self) -> Option<(T, u32)> {
+        self.tree.first()
+    }
+
+    pub fn last(&self) -> Option<(T, u32)> {
+        self.tree.last()
+    }
+
+    /// Entry with the largest key at most `key`.
+    pub fn floor(&self, key: T) -> Option<(T, u32)> {
+        entry_of(self.tree.floor(key))
+    }
+
+    /// Entry with the smallest key at least `key`.
+    pub fn ceiling(&self, key: T) -> Option<(T, u32)> {
+        entry_of(self.tree.ceiling(key))
+    }
+
+    /// Entry with the smallest key above `key`.
+    pub fn successor(&self, key: T) -> Option<(T, u32)> {
+        entry_of(self.tree.successor(key))
+    }
+
+    /// Entry with the largest key below `key`.
+    pub fn predecessor(&self, key: T) -> Option<(T, u32)> {
+        entry_of(self.tree.predecessor(key))
+    }
+
+    /// Entries in ascending key order.
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.tree.iter()
+    }
+
+    /// Entries whose keys fall within `range`, in ascending order.
+    pub fn range<R: RangeBounds<T>>(&self, range: R) -> Iter<'_, T> {
+        self.tree.range(range)
+    }
+}
+
+fn entry_of<T: Copy>(node: NodePtr<T>) -> Option<(T, u32)> {
+    (!node.is_null()).then(|| (node.key(), node.link()))
+}
+
+/// A key's place in an [`RBTreeRef`], see [`Entry`]. The node is named by
+/// its sref, so nothing of it outlives the borrow of the tree.
+pub struct RefEntry<'t, T>(Entry<'t, T>);
+
+impl<'t, T: Copy + Ord> RefEntry<'t, T> {
+    pub fn key(&self) -> T {
+        self.0.key()
+    }
+
+    /// Link of the node holding the key, or `None` if the entry is vacant.
+    pub fn get(&self) -> Option<u32> {
+        match &self.0 {
+            Entry::Occupied(entry) => Some(entry.get()),
+            Entry::Vacant(_) => None,
+        }
+    }
+
+    /// Updates the link of an occupied entry through `f`; a vacant entry is
+    /// returned as is.
+    pub fn and_modify(self, f: impl FnOnce(&mut u32)) -> Self {
+        Self(self.0.and_modify(f))
+    }
+
+    /// Sref of the node holding the key, inserting it with `link` if absent.
+    pub fn or_insert(self, link: u32) -> Result<u32, OpError>
+    where
+        T: NoUninit,
+    {
+        match self.0 {
+            Entry::Occupied(entry) => Ok(entry.node().sref()),
+            Entry::Vacant(entry) => entry.insert_in_region(link).map(|node| node.sref()),
+        }
+    }
+
+    /// Removes the node holding the key and returns its link, or `None` if
+    /// the entry is vacant.
+    pub fn remove(self) -> Result<Option<u32>, TreeError> {
+        match self.0 {
+            Entry::Occupied(entry) => entry.remove().map(Some),
+            Entry::Vacant(_) => Ok(None),
+        }
+    }
+}
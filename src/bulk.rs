@@ -0,0 +1,549 @@
+use crate::{
+    account::check_non_tree_layout, grow_account, node_stride, region_bytes, slot,
+    storage::Storage, Node, NodeBits, NodePtr, OpError, Operation, Payer, Phase, RBTree, TreeError,
+    TreeHeader, NULL_NODE,
+};
+use index_mem_alloc::MemoryMap;
+use solana_program::account_info::AccountInfo;
+use std::{ops::Range, ptr};
+
+/// Writes sorted `entries` into the slots listed in `srefs` as a balanced
+/// red-black tree and returns the root sref (`NULL_NODE` when empty).
+///
+/// Splitting at the middle keeps every level but the last one full, so all
+/// nodes above the last level are black and the last level is red.
+pub(crate) fn write_balanced<T: Copy>(
+    entry: *mut u64,
+    stride: usize,
+    entries: &[(T, u32)],
+    srefs: &[u32],
+) -> u32 {
+    let full_levels = (entries.len() + 1).ilog2();
+    write_subtree(entry, stride, entries, srefs, NULL_NODE, 0, full_levels)
+}
+
+/// Black nodes on the leftmost path down from `node`, which in a valid tree
+/// is the count on every path.
+fn black_height<T: Copy>(mut node: NodePtr<T>) -> u32 {
+    let mut height = 0;
+    while !node.is_null() {
+        height += node.is_black_color() as u32;
+        node = node.left();
+    }
+    height
+}
+
+fn write_subtree<T: Copy>(
+    entry: *mut u64,
+    stride: usize,
+    entries: &[(T, u32)],
+    srefs: &[u32],
+    parent: u32,
+    depth: u32,
+    full_levels: u32,
+) -> u32 {
+    if entries.is_empty() {
+        return NULL_NODE;
+    }
+    let mid = entries.len() / 2;
+    let sref = srefs[mid];
+    let left = write_subtree(
+        entry,
+        stride,
+        &entries[..mid],
+        &srefs[..mid],
+        sref,
+        depth + 1,
+        full_levels,
+    );
+    let right = write_subtree(
+        entry,
+        stride,
+        &entries[mid + 1..],
+        &srefs[mid + 1..],
+        sref,
+        depth + 1,
+        full_levels,
+    );
+    let (key, link) = entries[mid];
+    unsafe {
+        // Account images built off-chain need not be aligned.
+        slot::<T, u32>(entry, sref, stride).write_unaligned(Node::new(
+            key,
+            parent,
+            left,
+            right,
+            sref,
+            NodeBits::BLACK.with_red(depth >= full_levels),
+            link,
+        ));
+    }
+    sref
+}
+
+/// Builds the bytes of an account holding `entries` as a tree, for test
+/// fixtures and genesis tooling.
+///
+/// The image is `non_tree_data_size` bytes of non-tree data followed by room
+/// for `capacity` nodes, from [`RBTree::region_start`] on when it has a
+/// header. The slots are taken from an allocator of `capacity` slots opened
+/// over the storage at `bitmap`, as [`RBTree::init`] does, so the image
+/// holds the allocator state that goes with the tree. Entries are sorted by
+/// key (keeping the given order for equal keys) and stored in those slots;
+/// the root sref is written at `root_offset` and a fresh header at
+/// `header_offset`.
+pub fn build_account_image<T: Copy + Ord>(
+    entries: &[(T, u32)],
+    non_tree_data_size: usize,
+    capacity: usize,
+    root_offset: usize,
+    header_offset: Option<usize>,
+    bitmap: Range<usize>,
+) -> Result<Vec<u8>, TreeError> {
+    if entries.len() > capacity {
+        return Err(TreeError::CapacityMismatch {
+            capacity: capacity as u32,
+            allocated: entries.len() as u32,
+        });
+    }
+    check_non_tree_layout(non_tree_data_size, root_offset, header_offset, &bitmap)?;
+    let mut sorted = entries.to_vec();
+    sorted.sort_by_key(|&(key, _)| key);
+
+    let region_start = match header_offset {
+        Some(_) => RBTree::region_start(non_tree_data_size),
+        None => non_tree_data_size,
+    };
+    // Accounts without a header keep the v0.1.3 node stride.
+    let stride = node_stride::<T, u32>(header_offset.is_some());
+    let mut image = vec![0u8; region_start + region_bytes::<T, u32>(capacity, stride)];
+    let base = image.as_mut_ptr();
+    let mut pt = MemoryMap::new(base.wrapping_add(bitmap.start), capacity);
+    let mut srefs = Vec::with_capacity(sorted.len());
+    for _ in &sorted {
+        let sref = pt.alloc().map_err(|_| TreeError::AllocFailed)?;
+        srefs.push(sref as u32);
+    }
+    srefs.sort_unstable();
+    unsafe {
+        let root = write_balanced(base.add(region_start) as *mut u64, stride, &sorted, &srefs);
+        (base.add(root_offset) as *mut u32).write_unaligned(root);
+        if let Some(offset) = header_offset {
+            let header = TreeHeader {
+                node_count: sorted.len() as u32,
+                ..TreeHeader::new(non_tree_data_size)
+                    .with_key::<T>()
+                    .with_root_offset(root_offset)
+            };
+            (base.add(offset) as *mut TreeHeader).write_unaligned(header);
+        }
+    }
+    Ok(image)
+}
+
+impl RBTree {
+    /// Fills an empty tree with `entries`, sorted by key (equal keys keep
+    /// their order), writing a balanced tree in one pass instead of one
+    /// insert and fixup per entry, e.g. for migrations and initialization by
+    /// crank. The slots are taken up front and the account grown once for
+    /// all of them; on failure the slots are freed and the tree stays empty.
+    pub fn build_from_sorted<'a, 'info, T: Copy + Ord>(
+        &self,
+        entries: &[(T, u32)],
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<(), OpError> {
+        let invalid =
+            |error: TreeError| error.at(Operation::BuildFromSorted, Phase::Validate, NULL_NODE);
+        self.check_header().map_err(invalid)?;
+        if !self.is_empty() {
+            return Err(invalid(TreeError::NotEmpty));
+        }
+        if entries.windows(2).any(|pair| pair[0].0 > pair[1].0) {
+            return Err(invalid(TreeError::Unsorted));
+        }
+        for (key, _) in entries {
+            self.check_key(key).map_err(invalid)?;
+        }
+        let mut srefs = self
+            .alloc_slots::<T, u32>(entries.len())
+            .map_err(|e| self.slots_failed::<T>(e, Operation::BuildFromSorted, tree_acc))?;
+        let Some(&last) = srefs.iter().max() else {
+            return Ok(());
+        };
+        let region_start = self.region_offset();
+        if let Err(error) = grow_account(
+            region_start + region_bytes::<T, u32>(last as usize + 1, self.stride::<T, u32>()),
+            self.growth_start(tree_acc),
+            tree_acc,
+            payer.into(),
+            system_program,
+        ) {
+            return self
+                .free_slots(&srefs)
+                .and(Err(error))
+                .map_err(|e| self.slots_failed::<T>(e, Operation::BuildFromSorted, tree_acc));
+        }
+        self.grow_region(tree_acc.data_len() - region_start);
+        srefs.sort_unstable();
+        let root = write_balanced(self.entry, self.stride::<T, u32>(), entries, &srefs);
+        self.set_root_sref(root);
+        self.count_nodes(entries.len(), true);
+        self.touch();
+        Ok(())
+    }
+
+    /// Inserts every `(key, link)` of `entries` and returns their srefs in the
+    /// same order. The slots are taken up front and the account grown with a
+    /// single transfer and realloc for the batch, where inserting one by one
+    /// would pay a transfer CPI per node. Fails before anything is inserted
+    /// if a key is out of range or the slots cannot be had.
+    pub fn insert_many<'a, 'info, T: Copy + Ord>(
+        &self,
+        entries: &[(T, u32)],
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<Vec<u32>, OpError> {
+        let invalid =
+            |error: TreeError| error.at(Operation::InsertMany, Phase::Validate, NULL_NODE);
+        self.check_header().map_err(invalid)?;
+        for (key, _) in entries {
+            self.check_key(key).map_err(invalid)?;
+        }
+        let srefs = self
+            .alloc_slots::<T, u32>(entries.len())
+            .map_err(|e| self.slots_failed::<T>(e, Operation::InsertMany, tree_acc))?;
+        let Some(&last) = srefs.iter().max() else {
+            return Ok(srefs);
+        };
+        let region_start = self.region_offset();
+        if let Err(error) = grow_account(
+            region_start + region_bytes::<T, u32>(last as usize + 1, self.stride::<T, u32>()),
+            self.growth_start(tree_acc),
+            tree_acc,
+            payer.into(),
+            system_program,
+        ) {
+            return self
+                .free_slots(&srefs)
+                .and(Err(error))
+                .map_err(|e| self.slots_failed::<T>(e, Operation::InsertMany, tree_acc));
+        }
+        self.grow_region(tree_acc.data_len() - region_start);
+        for (&(key, link), &sref) in entries.iter().zip(&srefs) {
+            let node = NodePtr::write(
+                self.entry,
+                self.region_len.get(),
+                self.stride::<T, u32>(),
+                sref,
+                key,
+                link,
+            );
+            self.link_in_order(node);
+            self.count_node(true);
+        }
+        self.touch();
+        Ok(srefs)
+    }
+
+    /// Moves every node with a key of at least `key` into `upper`, an empty
+    /// tree with its own root word in the same node region, e.g. to carve
+    /// off crossed orders for settlement. Both parts are rebuilt balanced in
+    /// the slots they already use, so nothing is allocated; it takes O(n).
+    pub fn split_at<T: Copy + Ord>(&mut self, key: T, upper: &mut RBTree) -> Result<(), TreeError> {
+        self.check_header()?;
+        upper.check_header()?;
+        if self.root == upper.root {
+            return Err(TreeError::AliasedHandle);
+        }
+        if self.entry != upper.entry || self.non_tree_data_size != upper.non_tree_data_size {
+            return Err(TreeError::InvalidLayout);
+        }
+        if !upper.is_empty() {
+            return Err(TreeError::NotEmpty);
+        }
+        let nodes = self.collect_nodes::<T>();
+        let at = nodes.partition_point(|&(k, _, _)| k < key);
+        let (below, above) = nodes.split_at(at);
+        self.rebuild(below);
+        upper.rebuild(above);
+        upper.move_counts(self, above.len());
+        self.touch();
+        upper.touch();
+        Ok(())
+    }
+
+    /// Moves every node of `other`, a tree with its own root word in the same
+    /// node region, into this one, leaving `other` empty. Either tree may hold
+    /// the lower keys, but the key ranges must not overlap, though the
+    /// largest key of the lower tree may equal the smallest of the upper
+    /// one; the equal keys of the lower tree then read first. The trees are
+    /// joined at the spine of the taller one, so this takes O(log n) and
+    /// nothing is allocated. Trees sharing a header, as in a
+    /// [`Forest`](crate::Forest), keep their joint node count; trees with
+    /// headers of their own move the count of `other` over in one update,
+    /// read in O(1) when its header records its root (see [`RBTree::len`]).
+    ///
+    /// Counterpart of [`RBTree::split_at`]; see
+    /// [`RBTree::merge_overlapping`] for trees whose keys interleave. Trees in
+    /// different accounts are consolidated with [`RBTree::insert_many`] and
+    /// [`RBTree::clear`] instead.
+    pub fn merge<T: Copy + Ord>(&mut self, other: &mut RBTree) -> Result<(), TreeError> {
+        self.check_mergeable(other)?;
+        let moved = if self.header != other.header {
+            other.len::<T>()
+        } else {
+            0
+        };
+        let other_is_upper = match (self.last::<T>(), other.first::<T>()) {
+            (Some((max, _)), Some((min, _))) if max > min => {
+                if other.last::<T>().map(|(key, _)| key) > self.first::<T>().map(|(key, _)| key) {
+                    return Err(TreeError::Overlapping);
+                }
+                false
+            }
+            _ => true,
+        };
+        let (lower, upper): (&RBTree, &RBTree) = if other_is_upper {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let lower_root = lower.get_root::<T>();
+        let upper_root = upper.get_root::<T>();
+        if upper_root.is_null() {
+            self.set_root_sref(lower_root.sref());
+        } else if lower_root.is_null() {
+            self.set_root_sref(upper_root.sref());
+        } else {
+            // The smallest node of the upper tree becomes the pivot between
+            // the two; taking it out first leaves the upper tree valid.
+            let pivot = upper_root.min_node();
+            upper.unlink(pivot);
+            let upper_root = upper.get_root::<T>();
+            self.join(lower_root, pivot, upper_root);
+        }
+        other.set_root_sref(NULL_NODE);
+        self.move_counts(other, moved);
+        self.touch();
+        other.touch();
+        Ok(())
+    }
+
+    /// Moves every node of `other` into this tree like [`RBTree::merge`], for
+    /// key ranges that may interleave. Equal keys of `other` read after those
+    /// of this tree. Both trees are walked and the result rebuilt balanced in
+    /// the slots the nodes already use, so this takes O(n + m).
+    pub fn merge_overlapping<T: Copy + Ord>(
+        &mut self,
+        other: &mut RBTree,
+    ) -> Result<(), TreeError> {
+        self.check_mergeable(other)?;
+        let ours = self.collect_nodes::<T>();
+        let theirs = other.collect_nodes::<T>();
+        let mut merged = Vec::with_capacity(ours.len() + theirs.len());
+        let (mut i, mut j) = (0, 0);
+        while i < ours.len() && j < theirs.len() {
+            if theirs[j].0 < ours[i].0 {
+                merged.push(theirs[j]);
+                j += 1;
+            } else {
+                merged.push(ours[i]);
+                i += 1;
+            }
+        }
+        merged.extend_from_slice(&ours[i..]);
+        merged.extend_from_slice(&theirs[j..]);
+        other.set_root_sref(NULL_NODE);
+        self.rebuild(&merged);
+        self.move_counts(other, theirs.len());
+        self.touch();
+        other.touch();
+        Ok(())
+    }
+
+    /// Checks that `other` is a distinct tree over the same node region.
+    fn check_mergeable(&self, other: &RBTree) -> Result<(), TreeError> {
+        self.check_header()?;
+        other.check_header()?;
+        if self.root == other.root {
+            return Err(TreeError::AliasedHandle);
+        }
+        if self.entry != other.entry || self.non_tree_data_size != other.non_tree_data_size {
+            return Err(TreeError::InvalidLayout);
+        }
+        Ok(())
+    }
+
+    /// Links the subtrees rooted at `lower` and `upper` under `pivot`, whose
+    /// key lies between them, and makes the result the root of this tree.
+    ///
+    /// The pivot goes in red on the spine of the taller subtree, facing the
+    /// shorter one, above the first black node of the same black height, so
+    /// only red-red violations above it can arise and the insert fixup
+    /// clears them.
+    fn join<T: Copy>(&self, lower: NodePtr<T>, mut pivot: NodePtr<T>, upper: NodePtr<T>) {
+        let (lower_height, upper_height) = (black_height(lower), black_height(upper));
+        let taller_is_lower = lower_height >= upper_height;
+        let (mut node, mut height, target) = if taller_is_lower {
+            (lower, lower_height, upper_height)
+        } else {
+            (upper, upper_height, lower_height)
+        };
+        let mut parent = NodePtr::null();
+        while !(node.is_black_color() && height == target) {
+            if node.is_black_color() {
+                height -= 1;
+            }
+            parent = node;
+            node = if taller_is_lower {
+                node.right()
+            } else {
+                node.left()
+            };
+        }
+        let (left, right) = if taller_is_lower {
+            (node, upper)
+        } else {
+            (lower, node)
+        };
+        pivot.set_left(left);
+        pivot.set_right(right);
+        for mut child in [left, right] {
+            if !child.is_null() {
+                child.set_parent(pivot);
+            }
+        }
+        pivot.set_parent(parent);
+        if parent.is_null() {
+            self.set_root_sref(pivot.sref());
+        } else {
+            self.set_root_sref(if taller_is_lower { lower } else { upper }.sref());
+            if taller_is_lower {
+                parent.set_right(pivot);
+            } else {
+                parent.set_left(pivot);
+            }
+        }
+        pivot.set_red_color();
+        self.insert_fixup(pivot);
+    }
+
+    /// Carries `moved` nodes over from the count of `other` when the two
+    /// trees keep separate headers.
+    fn move_counts(&self, other: &RBTree, moved: usize) {
+        if self.header != other.header {
+            other.count_nodes(moved, false);
+            self.count_nodes(moved, true);
+        }
+    }
+
+    /// `(key, link, sref)` of every node, in key order.
+    fn collect_nodes<T: Copy>(&self) -> Vec<(T, u32, u32)> {
+        let mut nodes = Vec::new();
+        let mut node = self.get_root::<T>().min_node();
+        while !node.is_null() {
+            nodes.push((node.key(), node.link(), node.sref()));
+            node = node.next();
+        }
+        nodes
+    }
+
+    /// Links `nodes`, sorted `(key, link, sref)` triples, as a balanced tree
+    /// under this root in the slots they name.
+    fn rebuild<T: Copy>(&self, nodes: &[(T, u32, u32)]) {
+        let srefs: Vec<u32> = nodes.iter().map(|&(_, _, sref)| sref).collect();
+        let entries: Vec<(T, u32)> = nodes.iter().map(|&(key, link, _)| (key, link)).collect();
+        self.set_root_sref(write_balanced(
+            self.entry,
+            self.stride::<T, u32>(),
+            &entries,
+            &srefs,
+        ));
+    }
+
+    /// Takes `count` slots from the allocator, all below the node limit, or
+    /// none at all.
+    pub(crate) fn alloc_slots<T, V>(&self, count: usize) -> Result<Vec<u32>, TreeError> {
+        let mut pt = self.pt.clone();
+        let max = Self::node_limit::<T, V>(self.region_offset(), self.stride::<T, V>());
+        let mut srefs = Vec::with_capacity(count);
+        while srefs.len() < count {
+            let error = match pt.alloc() {
+                Ok(index) if index < max => {
+                    srefs.push(index as u32);
+                    continue;
+                }
+                Ok(index) => {
+                    srefs.push(index as u32);
+                    TreeError::NodeLimit { max: max as u32 }
+                }
+                Err(_) => TreeError::AllocFailed,
+            };
+            return self.free_slots(&srefs).and(Err(error));
+        }
+        Ok(srefs)
+    }
+
+    /// Context for a batch that could not take or fund its slots, with the
+    /// arena numbers of `tree_acc`.
+    fn slots_failed<T>(
+        &self,
+        error: TreeError,
+        operation: Operation,
+        tree_acc: &AccountInfo,
+    ) -> OpError {
+        error
+            .at(operation, error.alloc_phase(), NULL_NODE)
+            .with_stats(self.arena_stats::<T>(tree_acc.data_len()))
+    }
+
+    /// Returns slots taken by [`RBTree::alloc_slots`] to the allocator. Every
+    /// slot is freed even if one fails with [`TreeError::DeallocFailed`],
+    /// which is then returned for the first such slot.
+    pub(crate) fn free_slots(&self, srefs: &[u32]) -> Result<(), TreeError> {
+        let mut pt = self.pt.clone();
+        let mut freed = Ok(());
+        for &sref in srefs {
+            if pt.dealloc(sref as usize).is_err() && freed.is_ok() {
+                freed = Err(TreeError::DeallocFailed { sref });
+            }
+        }
+        freed
+    }
+
+    /// Rebuilds the tree from the keys and links of every allocated slot among
+    /// the first `slots`, discarding all parent, child and color data. Nodes
+    /// keep their srefs. For recovering accounts whose links are corrupted but
+    /// whose node payloads survived. Returns the number of nodes in the tree.
+    ///
+    /// Every allocated slot is taken to be one of this tree's nodes, so the
+    /// tree must own the allocator, which only its header can show by
+    /// recording its root. Trees sharing one, as in a
+    /// [`Forest`](crate::Forest), and trees without a header fail with
+    /// [`TreeError::SharedAllocator`] without being changed.
+    pub fn repair_from_slab<T: Copy + Ord>(&self, slots: u32) -> Result<u32, TreeError> {
+        self.check_header()?;
+        if self.recorded_len().is_none() {
+            return Err(TreeError::SharedAllocator);
+        }
+        let mut nodes: Vec<(T, u32, u32)> = self
+            .iter_slab::<T>(slots)
+            .map(|node| (node.key(), node.link(), node.sref()))
+            .collect();
+        nodes.sort_by_key(|&(key, _, _)| key);
+        let entries: Vec<(T, u32)> = nodes.iter().map(|&(key, link, _)| (key, link)).collect();
+        let srefs: Vec<u32> = nodes.iter().map(|&(_, _, sref)| sref).collect();
+        let root = write_balanced(self.entry, self.stride::<T, u32>(), &entries, &srefs);
+        self.set_root_sref(root);
+        if !self.header.is_null() {
+            unsafe {
+                ptr::addr_of_mut!((*self.header).node_count).write_unaligned(srefs.len() as u32)
+            }
+        }
+        self.touch();
+        Ok(srefs.len() as u32)
+    }
+}
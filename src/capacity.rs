@@ -0,0 +1,225 @@
+use crate::{
+    grow_account, growth_instructions, growth_limit, node_stride, region_bytes, region_slots,
+    storage::Storage, Payer, RBTree, SrefWord, TreeError, UNBOUNDED_REGION,
+};
+use solana_program::{account_info::AccountInfo, system_instruction::MAX_PERMITTED_DATA_LENGTH};
+
+/// Most nodes any tree can address: srefs are stored as [`SrefWord`]s, whose
+/// all-ones value is the null link and, with the `packed-color` feature, whose
+/// high bit holds the color. The account size limit usually binds first, see
+/// [`RBTree::max_nodes`].
+pub const MAX_NODES: usize = if cfg!(feature = "packed-color") {
+    (SrefWord::MAX >> 1) as usize
+} else {
+    SrefWord::MAX as usize
+};
+
+/// Allocator occupancy and account size, attached to errors for postmortems
+/// (see [`crate::OpError::with_stats`]).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArenaStats {
+    /// Allocated slots among those the account can hold.
+    pub allocated: u32,
+    /// Whole nodes the account can hold.
+    pub slots: u32,
+    pub account_len: u64,
+}
+
+/// Allocator occupancy over the slots of the node region, returned by
+/// [`RBTree::alloc_stats`] to decide when to compact or shrink.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocStats {
+    pub allocated: u32,
+    pub free: u32,
+    /// Highest allocated sref, `None` when no slot is allocated.
+    pub highest: Option<u32>,
+    /// Free slots below `highest`, which [`RBTree::compact_step`] can fill
+    /// so that [`RBTree::shrink_to_fit`] releases them.
+    pub holes: u32,
+}
+
+impl AllocStats {
+    /// Share of the slots up to `highest` that are free, from 0 for a packed
+    /// allocator to nearly 1.
+    pub fn fragmentation(&self) -> f64 {
+        match self.highest {
+            Some(highest) => self.holes as f64 / (highest as f64 + 1.0),
+            None => 0.0,
+        }
+    }
+}
+
+impl RBTree {
+    /// Most nodes a tree of `T` keys can hold: [`MAX_NODES`], or fewer when
+    /// the largest account the runtime allows fills up first. Inserts beyond
+    /// it fail with [`TreeError::NodeLimit`]. Counts nodes laid out back to
+    /// back, as in accounts with a header; accounts without one fit at least
+    /// as many.
+    pub const fn max_nodes<T>(non_tree_data_size: usize) -> usize {
+        Self::node_limit::<T, u32>(non_tree_data_size, node_stride::<T, u32>(true))
+    }
+
+    /// [`RBTree::max_nodes`] for nodes with `V` values `stride` bytes apart.
+    pub(crate) const fn node_limit<T, V>(non_tree_data_size: usize, stride: usize) -> usize {
+        let fit = region_slots::<T, V>(
+            (MAX_PERMITTED_DATA_LENGTH as usize).saturating_sub(non_tree_data_size),
+            stride,
+        );
+        if fit < MAX_NODES {
+            fit
+        } else {
+            MAX_NODES
+        }
+    }
+
+    /// Number of whole nodes that fit in an account of `len` bytes whose first
+    /// `non_tree_data_size` bytes are not part of the tree, laid out back to
+    /// back from [`RBTree::region_start`] as in accounts with a header.
+    pub const fn capacity_from_account<T>(len: usize, non_tree_data_size: usize) -> usize {
+        region_slots::<T, u32>(
+            len.saturating_sub(Self::region_start(non_tree_data_size)),
+            node_stride::<T, u32>(true),
+        )
+    }
+
+    /// Grows the account toward room for `slots` nodes by as much as the
+    /// runtime allows in one instruction, and returns how many more
+    /// instructions it takes, 0 once there is room. For growth past
+    /// [`TreeError::GrowthLimit`], e.g. ahead of a large
+    /// [`RBTree::build_from_sorted`]: call it once per instruction until it
+    /// returns 0.
+    pub fn grow_toward<'a, 'info, T>(
+        &self,
+        slots: usize,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<u32, TreeError> {
+        self.check_header()?;
+        let stride = self.stride::<T, u32>();
+        let max = Self::node_limit::<T, u32>(self.region_offset(), stride);
+        if slots > max {
+            return Err(TreeError::NodeLimit { max: max as u32 });
+        }
+        let region_start = self.region_offset();
+        let target = region_start + region_bytes::<T, u32>(slots, stride);
+        let start_len = self.growth_start(tree_acc);
+        grow_account(
+            target.min(growth_limit(start_len)),
+            start_len,
+            tree_acc,
+            payer.into(),
+            system_program,
+        )?;
+        self.grow_region(tree_acc.data_len() - region_start);
+        Ok(growth_instructions(tree_acc.data_len(), target))
+    }
+
+    /// Grows the account to hold the next `additional` nodes with one
+    /// transfer and one realloc, so that inserting them later takes no
+    /// transfer CPI. The slots are not kept: they are the ones the allocator
+    /// hands out next.
+    ///
+    /// Only the node region grows. The allocator storage is sized for its
+    /// slots when the account is formatted and sits before the region, so it
+    /// cannot grow here: an allocator without `additional` free slots fails
+    /// with [`TreeError::AllocFailed`] before any transfer or realloc. Growth
+    /// past what one instruction allows fails with
+    /// [`TreeError::GrowthLimit`]; see [`RBTree::grow_toward`].
+    pub fn reserve<'a, 'info, T>(
+        &self,
+        additional: usize,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<(), TreeError> {
+        self.check_header()?;
+        let srefs = self.alloc_slots::<T, u32>(additional)?;
+        self.free_slots(&srefs)?;
+        let Some(&last) = srefs.iter().max() else {
+            return Ok(());
+        };
+        let region_start = self.region_offset();
+        grow_account(
+            region_start + region_bytes::<T, u32>(last as usize + 1, self.stride::<T, u32>()),
+            self.growth_start(tree_acc),
+            tree_acc,
+            payer.into(),
+            system_program,
+        )?;
+        self.grow_region(tree_acc.data_len() - region_start);
+        Ok(())
+    }
+
+    /// Reads the allocator state of every slot in the node region. Slots of
+    /// other trees sharing the allocator count as allocated.
+    ///
+    /// Needs a bounded `region_len`.
+    pub fn alloc_stats<T>(&self) -> Result<AllocStats, TreeError> {
+        let region_len = self.region_len.get();
+        if region_len == UNBOUNDED_REGION {
+            return Err(TreeError::InvalidLayout);
+        }
+        let mut stats = AllocStats::default();
+        for sref in 0..region_slots::<T, u32>(region_len, self.stride::<T, u32>()) as u32 {
+            if self.pt.is_allocated(sref as usize) {
+                stats.allocated += 1;
+                stats.highest = Some(sref);
+            } else {
+                stats.free += 1;
+            }
+        }
+        stats.holes = stats
+            .highest
+            .map_or(0, |highest| highest + 1 - stats.allocated);
+        Ok(stats)
+    }
+
+    /// Counts the allocated slots of an account of `account_len` bytes. Reads
+    /// the allocator for every slot, so it belongs on error paths and
+    /// off-chain.
+    pub fn arena_stats<T>(&self, account_len: usize) -> ArenaStats {
+        self.arena_stats_of::<T, u32>(account_len)
+    }
+
+    /// [`RBTree::arena_stats`] for nodes with `V` values.
+    pub(crate) fn arena_stats_of<T, V>(&self, account_len: usize) -> ArenaStats {
+        let slots = region_slots::<T, V>(
+            account_len.saturating_sub(self.region_offset()),
+            self.stride::<T, V>(),
+        );
+        ArenaStats {
+            allocated: (0..slots)
+                .filter(|&sref| self.pt.is_allocated(sref))
+                .count() as u32,
+            slots: slots as u32,
+            account_len: account_len as u64,
+        }
+    }
+
+    /// Checks that the allocator agrees with an account of `account_len`
+    /// bytes: no slot among the allocator's first `allocator_slots` may be
+    /// allocated beyond the nodes the account can hold. Such a slot points at
+    /// data that does not exist, which happens when the account is resized
+    /// without going through the tree.
+    pub fn check_capacity<T>(
+        &self,
+        account_len: usize,
+        allocator_slots: usize,
+    ) -> Result<(), TreeError> {
+        if account_len < self.non_tree_data_size {
+            return Err(TreeError::InvalidLayout);
+        }
+        let capacity = region_slots::<T, u32>(
+            account_len.saturating_sub(self.region_offset()),
+            self.stride::<T, u32>(),
+        );
+        if let Some(sref) = (capacity..allocator_slots).find(|&sref| self.pt.is_allocated(sref)) {
+            return Err(TreeError::CapacityMismatch {
+                capacity: capacity as u32,
+                allocated: sref as u32,
+            });
+        }
+        Ok(())
+    }
+}
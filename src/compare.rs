@@ -0,0 +1,133 @@
+use crate::{RBTree, TreeView};
+use std::{cell::Cell, cmp::Ordering};
+
+/// Keys collected by [`RBTree::intersection_keys`] or
+/// [`RBTree::difference_keys`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyComparison<T> {
+    /// Selected keys, in ascending order.
+    pub keys: Vec<T>,
+    /// Whether every key of the first tree was compared. `false` when `max`
+    /// keys were collected or the visit budget ran out first; the walk then
+    /// has to be resumed past the last key it reached.
+    pub done: bool,
+}
+
+/// Walks two ascending key streams together, collecting up to `max` keys that
+/// `keep` selects from the first stream and reading at most `visits` keys
+/// from both. Equal keys pair up one to one, so duplicate keys are compared
+/// as multisets.
+fn walk<T: Copy + Ord>(
+    first: impl Iterator<Item = T>,
+    second: impl Iterator<Item = T>,
+    max: usize,
+    visits: usize,
+    keep: fn(Option<Ordering>) -> bool,
+) -> KeyComparison<T> {
+    let visited = Cell::new(0);
+    let spent = || visited.get() >= visits;
+    let mut first = first.inspect(|_| visited.set(visited.get() + 1));
+    let mut second = second.inspect(|_| visited.set(visited.get() + 1));
+    let mut keys = Vec::new();
+    let stopped = |keys| KeyComparison { keys, done: false };
+    if spent() {
+        return stopped(keys);
+    }
+    let mut b = second.next();
+    loop {
+        if keys.len() == max || spent() {
+            return stopped(keys);
+        }
+        let Some(a) = first.next() else {
+            return KeyComparison { keys, done: true };
+        };
+        while b.is_some_and(|b| b < a) {
+            if spent() {
+                return stopped(keys);
+            }
+            b = second.next();
+        }
+        let ordering = b.map(|b| a.cmp(&b));
+        if keep(ordering) {
+            keys.push(a);
+        }
+        if ordering == Some(Ordering::Equal) {
+            if spent() {
+                return stopped(keys);
+            }
+            b = second.next();
+        }
+    }
+}
+
+fn keys<T>(entries: impl Iterator<Item = (T, u32)>) -> impl Iterator<Item = T> {
+    entries.map(|(key, _)| key)
+}
+
+fn in_both(ordering: Option<Ordering>) -> bool {
+    ordering == Some(Ordering::Equal)
+}
+
+fn only_in_first(ordering: Option<Ordering>) -> bool {
+    ordering != Some(Ordering::Equal)
+}
+
+impl RBTree {
+    /// Up to `max` keys present in both trees, in ascending order, e.g. to
+    /// reconcile a book with its shadow copy. The walk reads at most
+    /// `visits` keys from the two trees together, which bounds its cost for
+    /// on-chain use.
+    pub fn intersection_keys<T: Copy + Ord>(
+        &self,
+        other: &RBTree,
+        max: usize,
+        visits: usize,
+    ) -> KeyComparison<T> {
+        walk(keys(self.iter()), keys(other.iter()), max, visits, in_both)
+    }
+
+    /// Up to `max` keys of this tree missing from `other`, in ascending order,
+    /// reading at most `visits` keys as [`RBTree::intersection_keys`] does.
+    pub fn difference_keys<T: Copy + Ord>(
+        &self,
+        other: &RBTree,
+        max: usize,
+        visits: usize,
+    ) -> KeyComparison<T> {
+        walk(
+            keys(self.iter()),
+            keys(other.iter()),
+            max,
+            visits,
+            only_in_first,
+        )
+    }
+}
+
+impl<T: Copy + Ord> TreeView<'_, T> {
+    /// [`RBTree::intersection_keys`] for off-chain views.
+    pub fn intersection_keys(
+        &self,
+        other: &TreeView<'_, T>,
+        max: usize,
+        visits: usize,
+    ) -> KeyComparison<T> {
+        walk(keys(self.iter()), keys(other.iter()), max, visits, in_both)
+    }
+
+    /// [`RBTree::difference_keys`] for off-chain views.
+    pub fn difference_keys(
+        &self,
+        other: &TreeView<'_, T>,
+        max: usize,
+        visits: usize,
+    ) -> KeyComparison<T> {
+        walk(
+            keys(self.iter()),
+            keys(other.iter()),
+            max,
+            visits,
+            only_in_first,
+        )
+    }
+}
@@ -0,0 +1,119 @@
+use crate::{storage::Storage, NodePtr, RBTree, Removed, TreeError};
+use std::ops::Bound;
+
+/// Position in a tree that can step both ways and remove the node it is on,
+/// obtained from [`RBTree::cursor_at`]. Walking and deleting in one pass is
+/// what a matching loop does with the opposite side of the book.
+///
+/// Past either end the cursor is on no node: [`Cursor::current`] returns
+/// `None` and stepping does nothing.
+///
+/// The cursor borrows the tree, so while it lives every delete goes through
+/// it. [`Cursor::remove`] and [`Cursor::delete_by_sref`] may take any node;
+/// when they take the one the cursor is on, it moves to the next node like
+/// [`Cursor::remove_current`] does. A cursor made [`Cursor::strict`] refuses
+/// that instead with [`TreeError::CursorActive`], for loops that must never
+/// skip past the node they look at.
+pub struct Cursor<'a, T> {
+    tree: &'a mut RBTree,
+    node: NodePtr<T>,
+    strict: bool,
+}
+
+impl<T: Copy + Ord> Cursor<'_, T> {
+    /// `(key, link, sref)` of the node the cursor is on.
+    pub fn current(&self) -> Option<(T, u32, u32)> {
+        if self.node.is_null() {
+            return None;
+        }
+        Some((self.node.key(), self.node.link(), self.node.sref()))
+    }
+
+    /// Moves to the next node in key order.
+    pub fn move_next(&mut self) {
+        self.node = self.node.next();
+    }
+
+    /// Moves to the previous node in key order.
+    pub fn move_prev(&mut self) {
+        self.node = self.node.prev();
+    }
+
+    /// Removes the node the cursor is on and moves to the next one. Returns
+    /// the removed `(key, link, sref)`, or fails as [`RBTree::try_delete`]
+    /// does, leaving the cursor where it was.
+    pub fn remove_current(&mut self) -> Result<Option<(T, u32, u32)>, TreeError> {
+        let Some(current) = self.current() else {
+            return Ok(None);
+        };
+        let next = self.node.next();
+        // Deletion relinks nodes without moving them, so `next` stays valid.
+        self.tree.try_delete(self.node)?;
+        self.node = next;
+        Ok(Some(current))
+    }
+
+    /// Makes deletes of the current node through [`Cursor::remove`] and
+    /// [`Cursor::delete_by_sref`] fail instead of moving the cursor.
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Removes a node holding `key`, which need not be the current one.
+    pub fn remove(&mut self, key: T) -> Result<Option<Removed<T>>, TreeError> {
+        let node = self.tree.search::<T, u32>(key);
+        self.take(node)
+    }
+
+    /// Removes the node stored at `sref`, see [`RBTree::delete_by_sref`].
+    pub fn delete_by_sref(&mut self, sref: u32) -> Result<Option<Removed<T>>, TreeError> {
+        let node = self.tree.node::<T>(sref).unwrap_or_else(NodePtr::null);
+        self.take(node)
+    }
+
+    fn take(&mut self, node: NodePtr<T>) -> Result<Option<Removed<T>>, TreeError> {
+        if !node.is_null() && node == self.node {
+            if self.strict {
+                return Err(TreeError::CursorActive { sref: node.sref() });
+            }
+            let next = node.next();
+            let removed = self.tree.take(node)?;
+            self.node = next;
+            return Ok(removed);
+        }
+        self.tree.take(node)
+    }
+}
+
+impl RBTree {
+    /// Cursor on the first node whose key is at least `key`.
+    pub fn cursor_at<T: Copy + Ord>(&mut self, key: T) -> Cursor<'_, T> {
+        let node = self.lower_bound_node(Bound::Included(&key));
+        Cursor {
+            tree: self,
+            node,
+            strict: false,
+        }
+    }
+
+    /// Cursor on the node with the smallest key.
+    pub fn cursor_front<T: Copy + Ord>(&mut self) -> Cursor<'_, T> {
+        let node = self.get_root().min_node();
+        Cursor {
+            tree: self,
+            node,
+            strict: false,
+        }
+    }
+
+    /// Cursor on the node with the largest key.
+    pub fn cursor_back<T: Copy + Ord>(&mut self) -> Cursor<'_, T> {
+        let node = self.get_root().max_node();
+        Cursor {
+            tree: self,
+            node,
+            strict: false,
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use crate::{
+    storage::Storage, NodePtr, OpError, Operation, Payer, Phase, RBTree, TreeError, NULL_NODE,
+};
+use bytemuck::NoUninit;
+use solana_program::account_info::AccountInfo;
+use std::cmp::Ordering;
+
+/// A key's place in the tree, found by [`RBTree::entry`] in one traversal:
+/// either the node holding it or the spot a new node would be linked at.
+pub enum Entry<'t, T> {
+    Occupied(OccupiedEntry<'t, T>),
+    Vacant(VacantEntry<'t, T>),
+}
+
+/// A node holding the key. With duplicate keys it is whichever one the
+/// search met first.
+pub struct OccupiedEntry<'t, T> {
+    tree: &'t mut RBTree,
+    node: NodePtr<T>,
+}
+
+/// The spot a node for an absent key would be linked at.
+pub struct VacantEntry<'t, T> {
+    tree: &'t mut RBTree,
+    key: T,
+    parent: NodePtr<T>,
+}
+
+impl RBTree {
+    /// Finds the node holding `key`, or where it would go, for inserting or
+    /// updating in a single traversal. The tree stays borrowed until the entry
+    /// is used, as any other change could move the spot it points at.
+    ///
+    /// The header is checked here, once, for whatever the entry is then
+    /// used for; fails with the errors of [`RBTree::check_header`].
+    pub fn entry<T: Copy + Ord>(&mut self, key: T) -> Result<Entry<'_, T>, TreeError> {
+        self.check_header()?;
+        let mut parent = NodePtr::null();
+        let mut node = self.get_root::<T>();
+        while !node.is_null() {
+            match key.cmp(&node.key()) {
+                Ordering::Less => {
+                    parent = node;
+                    node = node.left();
+                }
+                Ordering::Greater => {
+                    parent = node;
+                    node = node.right();
+                }
+                Ordering::Equal => return Ok(Entry::Occupied(OccupiedEntry { tree: self, node })),
+            }
+        }
+        Ok(Entry::Vacant(VacantEntry {
+            tree: self,
+            key,
+            parent,
+        }))
+    }
+
+    /// Sets the link of the node holding `key`, or inserts `key` with `link`
+    /// if there is none, in one traversal. Returns the previous link, or
+    /// `None` if a node was inserted.
+    pub fn upsert<'a, 'info, T: Copy + Ord + NoUninit>(
+        &mut self,
+        key: T,
+        link: u32,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<Option<u32>, OpError> {
+        let entry = self
+            .entry(key)
+            .map_err(|e| e.during(Operation::Insert, Phase::Validate, NULL_NODE, &key))?;
+        match entry {
+            Entry::Occupied(mut entry) => Ok(Some(entry.insert(link))),
+            Entry::Vacant(entry) => entry
+                .insert(link, tree_acc, payer, system_program)
+                .map(|_| None),
+        }
+    }
+}
+
+impl<'t, T: Copy + Ord> Entry<'t, T> {
+    pub fn key(&self) -> T {
+        match self {
+            Self::Occupied(entry) => entry.key(),
+            Self::Vacant(entry) => entry.key,
+        }
+    }
+
+    /// Updates the link of an occupied entry through `f`; a vacant entry is
+    /// returned as is.
+    pub fn and_modify(self, f: impl FnOnce(&mut u32)) -> Self {
+        if let Self::Occupied(entry) = &self {
+            let mut link = entry.get();
+            f(&mut link);
+            entry.node.set_value(link);
+            entry.tree.touch();
+        }
+        self
+    }
+
+    /// The node holding the key, inserting it with `link` if absent. The
+    /// account is only grown and charged rent when a node is inserted.
+    pub fn or_insert<'a, 'info>(
+        self,
+        link: u32,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<NodePtr<T>, OpError>
+    where
+        T: NoUninit,
+    {
+        self.or_insert_with(|| link, tree_acc, payer, system_program)
+    }
+
+    /// Like [`Entry::or_insert`], computing the link only when inserting.
+    pub fn or_insert_with<'a, 'info>(
+        self,
+        link: impl FnOnce() -> u32,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<NodePtr<T>, OpError>
+    where
+        T: NoUninit,
+    {
+        match self {
+            Self::Occupied(entry) => Ok(entry.node),
+            Self::Vacant(entry) => entry.insert(link(), tree_acc, payer, system_program),
+        }
+    }
+}
+
+impl<'t, T: Copy + Ord> OccupiedEntry<'t, T> {
+    pub fn key(&self) -> T {
+        self.node.key()
+    }
+
+    pub fn get(&self) -> u32 {
+        self.node.value()
+    }
+
+    pub fn node(&self) -> NodePtr<T> {
+        self.node
+    }
+
+    /// Replaces the link and returns the old one.
+    pub fn insert(&mut self, link: u32) -> u32 {
+        let old = self.get();
+        self.node.set_value(link);
+        self.tree.touch();
+        old
+    }
+
+    /// Removes the node and returns its link. Fails as
+    /// [`RBTree::try_delete`] does.
+    pub fn remove(self) -> Result<u32, TreeError> {
+        let link = self.get();
+        self.tree.try_delete(self.node)?;
+        Ok(link)
+    }
+}
+
+impl<'t, T: Copy + Ord> VacantEntry<'t, T> {
+    pub fn key(&self) -> T {
+        self.key
+    }
+
+    /// Inserts the key with `link` at the spot the search ended, growing the
+    /// account if needed, and returns the new node.
+    pub fn insert<'a, 'info>(
+        self,
+        link: u32,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<NodePtr<T>, OpError>
+    where
+        T: NoUninit,
+    {
+        self.insert_with(
+            |tree, key| tree.new_node(key, link, tree_acc, payer, system_program),
+            |_| tree_acc.data_len(),
+        )
+    }
+
+    /// Like [`VacantEntry::insert`], within the node region as it is, see
+    /// [`RBTree::new_node_in_region`].
+    pub(crate) fn insert_in_region(self, link: u32) -> Result<NodePtr<T>, OpError>
+    where
+        T: NoUninit,
+    {
+        self.insert_with(
+            |tree, key| tree.new_node_in_region(key, link),
+            |tree| tree.region_offset() + tree.region_len.get(),
+        )
+    }
+
+    /// Links the node `new_node` writes; a failure is reported with the
+    /// arena stats of an account of `data_len` bytes.
+    fn insert_with(
+        self,
+        new_node: impl FnOnce(&RBTree, T) -> Result<NodePtr<T>, TreeError>,
+        data_len: impl FnOnce(&RBTree) -> usize,
+    ) -> Result<NodePtr<T>, OpError>
+    where
+        T: NoUninit,
+    {
+        let Self { tree, key, parent } = self;
+        tree.check_key(&key)
+            .map_err(|e| e.during(Operation::Insert, Phase::Validate, NULL_NODE, &key))?;
+        let node = new_node(tree, key).map_err(|e| {
+            e.during(Operation::Insert, e.alloc_phase(), parent.sref(), &key)
+                .with_stats(tree.arena_stats::<T>(data_len(tree)))
+        })?;
+        tree.link(parent, node);
+        tree.touch();
+        Ok(node)
+    }
+}
@@ -0,0 +1,391 @@
+use crate::ArenaStats;
+use bytemuck::{bytes_of, NoUninit};
+use solana_program::{msg, program_error::ProgramError};
+use std::fmt;
+
+/// Leading key bytes kept by [`ErrorContext`].
+pub const CONTEXT_KEY_BYTES: usize = 32;
+
+/// Errors reported by tree operations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TreeError {
+    /// The header is missing its magic bytes or has an unknown version.
+    InvalidHeader,
+    /// The handle's `non_tree_data_size` differs from the value the account
+    /// was initialized with.
+    NonTreeDataSizeMismatch { initialized: u32, given: usize },
+    /// Root or header offsets fall outside the non-tree data, or the account is
+    /// smaller than its non-tree data.
+    InvalidLayout,
+    /// The allocator holds slot `allocated` although the account only has
+    /// room for `capacity` nodes.
+    CapacityMismatch { capacity: u32, allocated: u32 },
+    /// The allocator has no free slot.
+    AllocFailed,
+    /// The key is already stored in the node at `sref`.
+    DuplicateKey { sref: u32, link: u32 },
+    /// Two handles share a root word or read one region with different
+    /// layouts, a second handle spans account data another one already does
+    /// under `strict-handles`, or a [`TreeGuard`](crate::TreeGuard) could not
+    /// take back the borrow of its account data.
+    AliasedHandle,
+    /// The key lies outside the range allowed by the header.
+    KeyOutOfRange,
+    /// The key normalizer rejected the key, e.g. it is not on the tick grid.
+    NonConformingKey,
+    /// The tree already holds the `max` nodes it can address, see
+    /// [`RBTree::max_nodes`](crate::RBTree::max_nodes).
+    NodeLimit { max: u32 },
+    /// A strict cursor is on the node at `sref`, which must be removed with
+    /// [`Cursor::remove_current`](crate::Cursor::remove_current).
+    CursorActive { sref: u32 },
+    /// The operation needs an empty tree.
+    NotEmpty,
+    /// The entries are not sorted by key.
+    Unsorted,
+    /// The key ranges of two trees overlap.
+    Overlapping,
+    /// Growing the account needs `needed` lamports but the payer only holds
+    /// `available`.
+    InsufficientFunds { needed: u64, available: u64 },
+    /// The transfer topping the account up to rent exemption failed.
+    RentTransferFailed,
+    /// The account could not be reallocated to hold a new node.
+    ReallocFailed,
+    /// The allocator does not hold slot `sref`, so the node linked there
+    /// cannot be freed.
+    DeallocFailed { sref: u32 },
+    /// No node holds the key.
+    KeyNotFound,
+    /// The node at `sref` and its parent or children disagree on how they
+    /// are linked, so changing it would spread the damage.
+    Corrupted { sref: u32 },
+    /// The account would grow by more than the runtime allows in one
+    /// instruction. Growing by the most allowed in each, it takes
+    /// `instructions` instructions, see [`RBTree::grow_toward`](crate::RBTree::grow_toward).
+    GrowthLimit { instructions: u32 },
+    /// Growing a pre-funded account needs a balance of `needed` lamports to
+    /// stay rent-exempt but it holds `balance`, see
+    /// [`Payer::PreFunded`](crate::Payer::PreFunded).
+    NotRentExempt { needed: u64, balance: u64 },
+    /// The account's nodes have the `initialized` layout flags but this
+    /// build reads nodes with the `built` ones, see
+    /// [`TreeHeader::node_layout`](crate::TreeHeader::node_layout).
+    LayoutMismatch { initialized: u32, built: u32 },
+    /// The account was initialized for keys of `initialized` bytes but the
+    /// handle uses keys of `given` bytes.
+    KeySizeMismatch { initialized: u32, given: usize },
+    /// The account was initialized with its root word at offset
+    /// `initialized` but the handle's root word is at `given`.
+    RootMismatch { initialized: u32, given: usize },
+    /// The account already holds a tree header, see
+    /// [`RBTree::init`](crate::RBTree::init).
+    AlreadyInitialized,
+    /// The key range to store has its minimum above its maximum, see
+    /// [`RBTree::set_key_range`](crate::RBTree::set_key_range).
+    EmptyKeyRange,
+    /// The interval to insert has its `lo` above its `hi`, see
+    /// [`IntervalTree::insert`](crate::IntervalTree::insert).
+    EmptyInterval,
+    /// The tree cannot be shown to own its allocator, which other trees may
+    /// share, so its allocated slots cannot all be taken as its own nodes,
+    /// see [`RBTree::repair_from_slab`](crate::RBTree::repair_from_slab).
+    SharedAllocator,
+    /// The slot at `sref` was not taken from this tree, see
+    /// [`RBTree::insert_reusing`](crate::RBTree::insert_reusing).
+    ForeignSlot { sref: u32 },
+}
+
+impl TreeError {
+    /// Stable code carried by [`ProgramError::Custom`].
+    pub const fn code(&self) -> u32 {
+        match self {
+            Self::InvalidHeader => 0,
+            Self::NonTreeDataSizeMismatch { .. } => 1,
+            Self::InvalidLayout => 2,
+            Self::CapacityMismatch { .. } => 3,
+            Self::AllocFailed => 4,
+            Self::DuplicateKey { .. } => 5,
+            Self::AliasedHandle => 6,
+            Self::KeyOutOfRange => 7,
+            Self::NonConformingKey => 8,
+            Self::NodeLimit { .. } => 9,
+            Self::CursorActive { .. } => 10,
+            Self::NotEmpty => 11,
+            Self::Unsorted => 12,
+            Self::Overlapping => 13,
+            Self::InsufficientFunds { .. } => 14,
+            Self::RentTransferFailed => 15,
+            Self::ReallocFailed => 16,
+            Self::DeallocFailed { .. } => 17,
+            Self::KeyNotFound => 18,
+            Self::Corrupted { .. } => 19,
+            Self::GrowthLimit { .. } => 20,
+            Self::NotRentExempt { .. } => 21,
+            Self::LayoutMismatch { .. } => 22,
+            Self::KeySizeMismatch { .. } => 23,
+            Self::RootMismatch { .. } => 24,
+            Self::AlreadyInitialized => 25,
+            Self::EmptyKeyRange => 26,
+            Self::EmptyInterval => 27,
+            Self::SharedAllocator => 28,
+            Self::ForeignSlot { .. } => 29,
+        }
+    }
+}
+
+impl fmt::Display for TreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidHeader => write!(f, "invalid tree header"),
+            Self::NonTreeDataSizeMismatch { initialized, given } => write!(
+                f,
+                "non_tree_data_size is {given} but the tree was initialized with {initialized}"
+            ),
+            Self::InvalidLayout => write!(f, "tree layout does not fit the account"),
+            Self::CapacityMismatch {
+                capacity,
+                allocated,
+            } => write!(
+                f,
+                "slot {allocated} is allocated but the account only holds {capacity} nodes"
+            ),
+            Self::AllocFailed => write!(f, "no free node slot"),
+            Self::DuplicateKey { sref, link } => {
+                write!(f, "key already stored at sref {sref} (link {link})")
+            }
+            Self::AliasedHandle => write!(f, "tree handles alias the same region"),
+            Self::KeyOutOfRange => write!(f, "key outside the allowed range"),
+            Self::NonConformingKey => write!(f, "key rejected by the normalizer"),
+            Self::NodeLimit { max } => write!(f, "tree is at its limit of {max} nodes"),
+            Self::CursorActive { sref } => write!(f, "node {sref} is under a strict cursor"),
+            Self::NotEmpty => write!(f, "tree is not empty"),
+            Self::Unsorted => write!(f, "entries are not sorted by key"),
+            Self::Overlapping => write!(f, "key ranges of the trees overlap"),
+            Self::InsufficientFunds { needed, available } => write!(
+                f,
+                "growing the account needs {needed} lamports but the payer holds {available}"
+            ),
+            Self::RentTransferFailed => write!(f, "rent transfer to the tree account failed"),
+            Self::ReallocFailed => write!(f, "tree account could not be reallocated"),
+            Self::DeallocFailed { sref } => write!(f, "slot {sref} is not allocated"),
+            Self::KeyNotFound => write!(f, "key not found"),
+            Self::Corrupted { sref } => write!(f, "links around node {sref} are corrupted"),
+            Self::GrowthLimit { instructions } => write!(
+                f,
+                "growing the account takes {instructions} instructions at the realloc limit"
+            ),
+            Self::NotRentExempt { needed, balance } => write!(
+                f,
+                "account holds {balance} lamports but needs {needed} to stay rent-exempt"
+            ),
+            Self::LayoutMismatch { initialized, built } => write!(
+                f,
+                "nodes were laid out with flags {initialized} but this build uses {built}"
+            ),
+            Self::KeySizeMismatch { initialized, given } => write!(
+                f,
+                "keys are {given} bytes but the tree was initialized with {initialized}"
+            ),
+            Self::RootMismatch { initialized, given } => write!(
+                f,
+                "root word is at offset {given} but the tree was initialized with {initialized}"
+            ),
+            Self::AlreadyInitialized => write!(f, "account already holds a tree"),
+            Self::EmptyKeyRange => write!(f, "key range minimum is above its maximum"),
+            Self::EmptyInterval => write!(f, "interval lo is above its hi"),
+            Self::SharedAllocator => write!(f, "allocator may be shared with other trees"),
+            Self::ForeignSlot { sref } => write!(f, "slot {sref} was not taken from this tree"),
+        }
+    }
+}
+
+impl std::error::Error for TreeError {}
+
+impl From<TreeError> for ProgramError {
+    fn from(e: TreeError) -> Self {
+        ProgramError::Custom(e.code())
+    }
+}
+
+/// Tree operation that failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Insert,
+    /// [`RBTree::try_delete`](crate::RBTree::try_delete) of a given node.
+    Delete,
+    /// [`RBTree::try_remove`](crate::RBTree::try_remove) of a key.
+    Remove,
+    InsertMany,
+    BuildFromSorted,
+    Drain,
+    Compact,
+}
+
+/// Step of an operation at which it failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Header and handle checks before anything is touched.
+    Validate,
+    /// Walking the tree to the key.
+    Search,
+    /// Taking a slot from the allocator or handing one back.
+    Alloc,
+    /// Funding and growing the account.
+    Realloc,
+    /// Unlinking a node and rebalancing, including the check that its parent
+    /// and children link back to it.
+    Fixup,
+}
+
+/// Breadcrumbs describing where an operation failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub operation: Operation,
+    pub phase: Phase,
+    /// Node involved, or `NULL_NODE` if none was reached.
+    pub sref: u32,
+    key: [u8; CONTEXT_KEY_BYTES],
+    key_len: u8,
+}
+
+impl ErrorContext {
+    /// Captures the leading bytes of `key`, which has no padding so that
+    /// all of them are initialized.
+    pub fn new<T: NoUninit>(operation: Operation, phase: Phase, sref: u32, key: &T) -> Self {
+        let key = bytes_of(key);
+        let key_len = key.len().min(CONTEXT_KEY_BYTES);
+        let mut bytes = [0u8; CONTEXT_KEY_BYTES];
+        bytes[..key_len].copy_from_slice(&key[..key_len]);
+        Self {
+            operation,
+            phase,
+            sref,
+            key: bytes,
+            key_len: key_len as u8,
+        }
+    }
+
+    /// Context of an operation that has no key to capture, e.g. a delete by
+    /// node.
+    pub const fn at(operation: Operation, phase: Phase, sref: u32) -> Self {
+        Self {
+            operation,
+            phase,
+            sref,
+            key: [0; CONTEXT_KEY_BYTES],
+            key_len: 0,
+        }
+    }
+
+    pub fn key_bytes(&self) -> &[u8] {
+        &self.key[..self.key_len as usize]
+    }
+}
+
+/// [`TreeError`] together with the [`ErrorContext`] it happened in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpError {
+    pub error: TreeError,
+    pub context: ErrorContext,
+    /// Arena numbers at the time of the failure, if they were collected.
+    pub stats: Option<ArenaStats>,
+}
+
+impl OpError {
+    pub fn with_stats(self, stats: ArenaStats) -> Self {
+        Self {
+            stats: Some(stats),
+            ..self
+        }
+    }
+
+    /// Writes the error, its context and any arena statistics to the program
+    /// log in one line.
+    pub fn log(&self) {
+        match self.stats {
+            Some(stats) => msg!(
+                "rb-tree: {:?} failed during {:?}: {} (sref {}, key {:?}, {}/{} slots allocated, account {} bytes)",
+                self.context.operation,
+                self.context.phase,
+                self.error,
+                self.context.sref,
+                self.context.key_bytes(),
+                stats.allocated,
+                stats.slots,
+                stats.account_len
+            ),
+            None => msg!(
+                "rb-tree: {:?} failed during {:?}: {} (sref {}, key {:?})",
+                self.context.operation,
+                self.context.phase,
+                self.error,
+                self.context.sref,
+                self.context.key_bytes()
+            ),
+        }
+    }
+}
+
+impl fmt::Display for OpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} failed during {:?}: {}",
+            self.context.operation, self.context.phase, self.error
+        )
+    }
+}
+
+impl std::error::Error for OpError {}
+
+impl From<OpError> for ProgramError {
+    fn from(e: OpError) -> Self {
+        e.error.into()
+    }
+}
+
+impl From<OpError> for TreeError {
+    fn from(e: OpError) -> Self {
+        e.error
+    }
+}
+
+impl TreeError {
+    /// Phase at which taking a slot for a new node stopped with this error:
+    /// funding and growing the account, or the allocator itself.
+    pub(crate) fn alloc_phase(self) -> Phase {
+        match self {
+            Self::InsufficientFunds { .. }
+            | Self::RentTransferFailed
+            | Self::ReallocFailed
+            | Self::GrowthLimit { .. }
+            | Self::NotRentExempt { .. } => Phase::Realloc,
+            _ => Phase::Alloc,
+        }
+    }
+
+    /// Attaches where the error happened.
+    pub fn during<T: NoUninit>(
+        self,
+        operation: Operation,
+        phase: Phase,
+        sref: u32,
+        key: &T,
+    ) -> OpError {
+        OpError {
+            error: self,
+            context: ErrorContext::new(operation, phase, sref, key),
+            stats: None,
+        }
+    }
+
+    /// Attaches where the error happened, without key bytes.
+    pub const fn at(self, operation: Operation, phase: Phase, sref: u32) -> OpError {
+        OpError {
+            error: self,
+            context: ErrorContext::at(operation, phase, sref),
+            stats: None,
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use crate::{NodePtr, RBTree};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Color {
+    Red,
+    Black,
+}
+
+/// Textbook form of a red-black tree with explicit leaves, for feeding the
+/// structure to external verifiers and reference implementations.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Tree<T> {
+    Leaf,
+    Node {
+        color: Color,
+        key: T,
+        left: Box<Tree<T>>,
+        right: Box<Tree<T>>,
+    },
+}
+
+impl RBTree {
+    /// Copies the structure and colors of the tree into a [`Tree`]. Intended
+    /// for off-chain use: it allocates one box per node.
+    pub fn export<T: Copy>(&self) -> Tree<T> {
+        export_node(self.get_root())
+    }
+}
+
+fn export_node<T: Copy>(node: NodePtr<T>) -> Tree<T> {
+    if node.is_null() {
+        return Tree::Leaf;
+    }
+    Tree::Node {
+        color: if node.is_red_color() {
+            Color::Red
+        } else {
+            Color::Black
+        },
+        key: node.key(),
+        left: Box::new(export_node(node.left())),
+        right: Box::new(export_node(node.right())),
+    }
+}
@@ -0,0 +1,136 @@
+//! Fixed-capacity trees kept in an inline array rather than an account, for
+//! tests and off-chain tools that want the on-chain algorithms without an
+//! account, an allocator account or a system program.
+
+use crate::{storage::Storage, Iter, Node, NodePtr, NULL_NODE};
+use std::{
+    cell::{Cell, UnsafeCell},
+    mem::{size_of, MaybeUninit},
+};
+
+/// Tree of at most `N` nodes, stored in a fixed array owned by the value.
+/// Rotations, fixups and unlinking are the ones [`RBTree`](crate::RBTree)
+/// runs, so the same operations produce the same shapes; only slot
+/// allocation differs, the lowest free slot being taken.
+///
+/// Node pointers handed out point into the value and are invalidated when it
+/// moves.
+pub struct StaticTree<T, const N: usize> {
+    nodes: UnsafeCell<MaybeUninit<[Node<T>; N]>>,
+    allocated: [bool; N],
+    root: Cell<u32>,
+    len: usize,
+}
+
+impl<T, const N: usize> Storage for StaticTree<T, N> {
+    #[inline]
+    fn root_word(&self) -> *mut u32 {
+        self.root.as_ptr()
+    }
+    #[inline]
+    fn node_entry(&self) -> *mut u64 {
+        self.nodes.get() as *mut u64
+    }
+    #[inline]
+    fn node_region_len(&self) -> usize {
+        N * size_of::<Node<T>>()
+    }
+}
+
+impl<T, const N: usize> Default for StaticTree<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> StaticTree<T, N> {
+    pub fn new() -> Self {
+        Self {
+            nodes: UnsafeCell::new(MaybeUninit::uninit()),
+            allocated: [false; N],
+            root: Cell::new(NULL_NODE),
+            len: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub fn get_root(&self) -> NodePtr<T> {
+        self.root_node()
+    }
+
+    /// Inserts `key`, equal keys going right of the existing ones as in
+    /// [`RBTree::insert`](crate::RBTree::insert). Returns the sref of the new
+    /// node, or [`NULL_NODE`] when all `N` slots are taken.
+    pub fn insert(&mut self, key: T, link: u32) -> u32
+    where
+        T: Copy + PartialOrd,
+    {
+        let Some(sref) = self.allocated.iter().position(|&taken| !taken) else {
+            return NULL_NODE;
+        };
+        self.allocated[sref] = true;
+        self.len += 1;
+        let node = NodePtr::write(
+            self.node_entry(),
+            self.node_region_len(),
+            self.stride::<T, u32>(),
+            sref as u32,
+            key,
+            link,
+        );
+        let mut y = NodePtr::null();
+        let mut x = self.get_root();
+        while !x.is_null() {
+            y = x;
+            if key < x.key() {
+                x = x.left();
+            } else {
+                x = x.right();
+            }
+        }
+        self.link(y, node);
+        sref as u32
+    }
+
+    pub fn find_node(&self, key: T) -> NodePtr<T>
+    where
+        T: Copy + Ord,
+    {
+        self.search(key)
+    }
+
+    /// Removes a node holding `key` and returns its link, or [`NULL_NODE`] if
+    /// there is none.
+    pub fn remove(&mut self, key: T) -> u32
+    where
+        T: Copy + Ord,
+    {
+        let node = self.find_node(key);
+        if node.is_null() {
+            return NULL_NODE;
+        }
+        let link = node.link();
+        self.unlink(node);
+        self.allocated[node.sref() as usize] = false;
+        self.len -= 1;
+        link
+    }
+
+    /// Iterates over `(key, link)` in ascending key order.
+    pub fn iter(&self) -> Iter<'_, T> {
+        let root = self.get_root();
+        Iter::new(root.min_node(), root.max_node(), None)
+    }
+}
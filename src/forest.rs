@@ -0,0 +1,251 @@
+use crate::{header::region_pad, OpError, RBTree, TreeError, TreeHeader};
+use index_mem_alloc::MemoryMap;
+use solana_program::account_info::AccountInfo;
+use std::{cell::Cell, mem::size_of, ops::Range};
+
+impl RBTree {
+    /// Whether `other` is a second handle on the same tree (overlapping root
+    /// words), or reads an overlapping node region with a different layout.
+    /// Mutating through two aliasing handles lets one act on nodes the other
+    /// has already relinked.
+    pub fn aliases(&self, other: &RBTree) -> bool {
+        let word = |root: *mut u32| root as usize..root as usize + size_of::<u32>();
+        let region = |tree: &RBTree| {
+            tree.entry as usize..(tree.entry as usize).saturating_add(tree.region_len.get())
+        };
+        overlap(&word(self.root), &word(other.root))
+            || (overlap(&region(self), &region(other))
+                && (self.entry != other.entry
+                    || self.non_tree_data_size != other.non_tree_data_size))
+    }
+}
+
+const fn overlap(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+/// Records the handles about to be built over `roots`, spanning `data`: the
+/// account bytes from the start of the data to the end of the node region.
+/// With the `strict-handles` feature it fails with
+/// [`TreeError::AliasedHandle`] if any other handle alive on this thread
+/// spans an overlapping range; a handle releases its claim when dropped.
+/// Without the feature it does nothing.
+pub(crate) fn claim_handles(roots: &[*mut u32], data: Range<usize>) -> Result<(), TreeError> {
+    #[cfg(feature = "strict-handles")]
+    return live::claim(roots, data);
+    #[cfg(not(feature = "strict-handles"))]
+    {
+        let _ = (roots, data);
+        Ok(())
+    }
+}
+
+/// Drops the claims on `roots` of handles that were never built.
+pub(crate) fn release_handles(roots: &[*mut u32]) {
+    #[cfg(feature = "strict-handles")]
+    live::release(roots);
+    #[cfg(not(feature = "strict-handles"))]
+    let _ = roots;
+}
+
+/// Account data spanned by the handles alive on this thread, for the
+/// `strict-handles` check.
+#[cfg(feature = "strict-handles")]
+mod live {
+    use super::overlap;
+    use crate::{RBTree, TreeError};
+    use std::{cell::RefCell, ops::Range};
+
+    struct Claim {
+        root: usize,
+        span: Range<usize>,
+    }
+
+    thread_local! {
+        static CLAIMS: RefCell<Vec<Claim>> = const { RefCell::new(Vec::new()) };
+    }
+
+    pub(super) fn claim(roots: &[*mut u32], span: Range<usize>) -> Result<(), TreeError> {
+        CLAIMS.with_borrow_mut(|claims| {
+            if claims.iter().any(|claim| overlap(&claim.span, &span)) {
+                return Err(TreeError::AliasedHandle);
+            }
+            claims.extend(roots.iter().map(|&root| Claim {
+                root: root as usize,
+                span: span.clone(),
+            }));
+            Ok(())
+        })
+    }
+
+    pub(super) fn release(roots: &[*mut u32]) {
+        CLAIMS.with_borrow_mut(|claims| {
+            claims.retain(|claim| !roots.iter().any(|&root| root as usize == claim.root));
+        });
+    }
+
+    impl Drop for RBTree {
+        fn drop(&mut self) {
+            release(&[self.root]);
+        }
+    }
+}
+
+/// Trees sharing one node region and allocator, each with its own root word
+/// (e.g. the bid and ask sides of a book in one account).
+///
+/// This is the supported way of sharing a region: every root is checked to be
+/// distinct up front, and handing out trees through `&mut self` keeps a
+/// single tree handle alive at a time. Under `strict-handles` its trees are
+/// claimed together, so they may span the same account data.
+pub struct Forest {
+    trees: Vec<RBTree>,
+}
+
+impl Forest {
+    /// Opens one tree per root word. `entry` points at the node region,
+    /// [`TreeHeader::region_start`] bytes into the account, and `region_len`
+    /// covers the rest of it.
+    pub fn new(
+        pt: MemoryMap,
+        entry: *mut u64,
+        non_tree_data_size: usize,
+        header: *mut TreeHeader,
+        region_len: usize,
+        roots: &[*mut u32],
+    ) -> Result<Self, TreeError> {
+        let region = entry as usize;
+        let data = region.saturating_sub(non_tree_data_size + region_pad(header))
+            ..region.saturating_add(region_len);
+        claim_handles(roots, data)?;
+        Self::open(pt, entry, non_tree_data_size, header, region_len, roots)
+            .inspect_err(|_| release_handles(roots))
+    }
+
+    fn open(
+        pt: MemoryMap,
+        entry: *mut u64,
+        non_tree_data_size: usize,
+        header: *mut TreeHeader,
+        region_len: usize,
+        roots: &[*mut u32],
+    ) -> Result<Self, TreeError> {
+        let mut trees: Vec<RBTree> = Vec::with_capacity(roots.len());
+        for &root in roots {
+            let tree = RBTree {
+                pt: pt.clone(),
+                root,
+                entry,
+                non_tree_data_size,
+                header,
+                region_len: Cell::new(region_len),
+                grown_from: Cell::new(None),
+            };
+            if trees.iter().any(|other| other.aliases(&tree)) {
+                return Err(TreeError::AliasedHandle);
+            }
+            tree.check_header()?;
+            trees.push(tree);
+        }
+        Ok(Self { trees })
+    }
+
+    pub fn len(&self) -> usize {
+        self.trees.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trees.is_empty()
+    }
+
+    pub fn tree(&self, index: usize) -> &RBTree {
+        self.sync_region_len();
+        &self.trees[index]
+    }
+
+    pub fn tree_mut(&mut self, index: usize) -> &mut RBTree {
+        self.sync_region_len();
+        &mut self.trees[index]
+    }
+
+    /// Moves every node of tree `from` with a key of at least `key` into the
+    /// empty tree `into`. See [`RBTree::split_at`].
+    pub fn split_at<T: Copy + Ord>(
+        &mut self,
+        from: usize,
+        into: usize,
+        key: T,
+    ) -> Result<(), TreeError> {
+        let (lower, upper) = self.pair_mut(from, into)?;
+        lower.split_at(key, upper)
+    }
+
+    /// Moves every node of tree `from` into tree `into`, whose key ranges
+    /// must not overlap. See [`RBTree::merge`].
+    pub fn merge<T: Copy + Ord>(&mut self, into: usize, from: usize) -> Result<(), TreeError> {
+        let (into, from) = self.pair_mut(into, from)?;
+        into.merge::<T>(from)
+    }
+
+    /// Moves every node of tree `from` into tree `into`. See
+    /// [`RBTree::merge_overlapping`].
+    pub fn merge_overlapping<T: Copy + Ord>(
+        &mut self,
+        into: usize,
+        from: usize,
+    ) -> Result<(), TreeError> {
+        let (into, from) = self.pair_mut(into, from)?;
+        into.merge_overlapping::<T>(from)
+    }
+
+    /// Shrinks the shared account down to the last slot any tree uses. See
+    /// [`RBTree::shrink_to_fit`].
+    pub fn shrink_to_fit<T>(
+        &mut self,
+        tree_acc: &AccountInfo,
+        recipient: &AccountInfo,
+    ) -> Result<u64, TreeError> {
+        self.sync_region_len();
+        let Some(tree) = self.trees.first() else {
+            return Ok(0);
+        };
+        let refund = tree.shrink_to_fit::<T>(tree_acc, recipient)?;
+        let region_len = tree.region_len.get();
+        for tree in &self.trees {
+            tree.region_len.set(region_len);
+        }
+        Ok(refund)
+    }
+
+    /// Removes every node of tree `index`, see [`RBTree::clear`]. The slots
+    /// of the other trees stay allocated.
+    pub fn clear<T: Copy>(&mut self, index: usize) -> Result<(), OpError> {
+        self.sync_region_len();
+        self.trees[index].clear::<T>()
+    }
+
+    /// Two distinct trees at once, for operations moving nodes between them.
+    fn pair_mut(&mut self, a: usize, b: usize) -> Result<(&mut RBTree, &mut RBTree), TreeError> {
+        if a == b {
+            return Err(TreeError::AliasedHandle);
+        }
+        self.sync_region_len();
+        if a < b {
+            let (head, tail) = self.trees.split_at_mut(b);
+            Ok((&mut head[a], &mut tail[0]))
+        } else {
+            let (head, tail) = self.trees.split_at_mut(a);
+            Ok((&mut tail[0], &mut head[b]))
+        }
+    }
+
+    /// Carries growth of the node region seen by one tree's inserts over to
+    /// the others.
+    fn sync_region_len(&self) {
+        if let Some(region_len) = self.trees.iter().map(|tree| tree.region_len.get()).max() {
+            for tree in &self.trees {
+                tree.region_len.set(region_len);
+            }
+        }
+    }
+}
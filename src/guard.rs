@@ -0,0 +1,115 @@
+use crate::{
+    forest::claim_handles, header::region_pad, OpError, Operation, Payer, Phase, RBTree, Removed,
+    TreeError, TreeHeader, NULL_NODE,
+};
+use bytemuck::NoUninit;
+use index_mem_alloc::MemoryMap;
+use solana_program::{account_info::AccountInfo, program_error::ProgramError};
+use std::{
+    cell::{Cell, RefMut},
+    mem::size_of,
+};
+
+/// Tree handle that keeps the account data mutably borrowed while it is alive.
+///
+/// The tree writes through raw pointers, so the `RefCell` around the account
+/// data does not see those writes. Holding the borrow for the lifetime of the
+/// handle makes any other `try_borrow_data`/`try_borrow_mut_data` on the
+/// account fail instead of observing half-rebalanced nodes.
+pub struct TreeGuard<'a, 'info> {
+    account: &'a AccountInfo<'info>,
+    data: Option<RefMut<'a, &'info mut [u8]>>,
+    tree: RBTree,
+}
+
+impl<'a, 'info> TreeGuard<'a, 'info> {
+    /// Borrows `account` and builds a tree over its data. The root word is read
+    /// from `root_offset` and the header, if any, from `header_offset`; both
+    /// must lie inside the first `non_tree_data_size` bytes. The node region
+    /// follows after the padding the header records.
+    pub fn new(
+        account: &'a AccountInfo<'info>,
+        pt: MemoryMap,
+        non_tree_data_size: usize,
+        root_offset: usize,
+        header_offset: Option<usize>,
+    ) -> Result<Self, ProgramError> {
+        let mut data = account.try_borrow_mut_data()?;
+        if data.len() < non_tree_data_size
+            || root_offset + size_of::<u32>() > non_tree_data_size
+            || header_offset
+                .is_some_and(|offset| offset + size_of::<TreeHeader>() > non_tree_data_size)
+        {
+            return Err(TreeError::InvalidLayout.into());
+        }
+        let base = data.as_mut_ptr();
+        let header = header_offset.map_or(std::ptr::null_mut(), |offset| unsafe {
+            base.add(offset) as *mut TreeHeader
+        });
+        // A fresh account may end inside the padding; its region is empty.
+        let region_start = non_tree_data_size + region_pad(header);
+        let root = unsafe { base.add(root_offset) as *mut u32 };
+        claim_handles(&[root], base as usize..base as usize + data.len())?;
+        let tree = RBTree {
+            pt,
+            root,
+            entry: base.wrapping_add(region_start) as *mut u64,
+            non_tree_data_size,
+            header,
+            region_len: Cell::new(data.len().saturating_sub(region_start)),
+            grown_from: Cell::new(None),
+        };
+        tree.check_header()?;
+        Ok(Self {
+            account,
+            data: Some(data),
+            tree,
+        })
+    }
+
+    pub fn tree(&self) -> &RBTree {
+        &self.tree
+    }
+
+    /// Removes a node holding `key` from the guarded account, see
+    /// [`RBTree::try_remove`]. Removal never reallocates, so the borrow is
+    /// held throughout.
+    pub fn remove<T: Copy + Ord + NoUninit>(&mut self, key: T) -> Result<Removed<T>, OpError> {
+        self.reborrow()
+            .map_err(|e| e.during(Operation::Remove, Phase::Validate, NULL_NODE, &key))?;
+        self.tree.try_remove(key)
+    }
+
+    /// Inserts into the guarded account and returns the sref of the new node,
+    /// see [`RBTree::try_insert`]. The borrow is released only while the
+    /// account is reallocated, which needs to borrow the data itself; if it
+    /// cannot be taken back, [`TreeError::AliasedHandle`] is returned in place
+    /// of the sref and the next call tries again.
+    pub fn insert<T: Copy + PartialOrd>(
+        &mut self,
+        key: T,
+        link: u32,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<u32, TreeError> {
+        self.reborrow()?;
+        self.data = None;
+        let inserted = self
+            .tree
+            .try_insert(key, link, self.account, payer, system_program);
+        self.reborrow()?;
+        inserted
+    }
+
+    /// Takes the data borrow back if an earlier insert could not.
+    fn reborrow(&mut self) -> Result<(), TreeError> {
+        if self.data.is_none() {
+            let data = self
+                .account
+                .try_borrow_mut_data()
+                .map_err(|_| TreeError::AliasedHandle)?;
+            self.data = Some(data);
+        }
+        Ok(())
+    }
+}
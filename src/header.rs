@@ -0,0 +1,364 @@
+use crate::{storage::Storage, RBTree, TreeError, NULL_NODE};
+use std::{cmp::Ordering, mem::size_of, ptr};
+
+/// Marks an account region holding a [`TreeHeader`].
+pub const TREE_MAGIC: u32 = u32::from_le_bytes(*b"RBTR");
+/// Current [`TreeHeader`] layout version.
+pub const TREE_VERSION: u32 = 1;
+/// Largest key size for which the header can store a key range.
+pub const KEY_BOUND_BYTES: usize = 16;
+/// Alignment of the node region in accounts with a header.
+pub const REGION_ALIGN: usize = 8;
+/// [`TreeHeader::node_layout`] flag of nodes aligned to 8 bytes, see
+/// [`Node`](crate::Node).
+pub const LAYOUT_ALIGNED: u32 = 1;
+/// [`TreeHeader::node_layout`] flag of nodes keeping their color in the
+/// parent link, see [`Node`](crate::Node).
+pub const LAYOUT_PACKED_COLOR: u32 = 2;
+/// [`TreeHeader::node_layout`] flag of nodes linked by two-byte srefs, see
+/// [`SrefWord`](crate::SrefWord).
+pub const LAYOUT_COMPACT_SREFS: u32 = 4;
+/// [`TreeHeader::node_layout`] flag of nodes laid out back to back. Without
+/// it nodes are `size_of::<Node>()` rounded down to whole words apart, as in
+/// v0.1.3 and in accounts without a header. Either stride is read by every
+/// build; only new headers record this one.
+pub const LAYOUT_EXACT_STRIDE: u32 = 8;
+/// [`TreeHeader::root_offset`] of headers not tied to one root word, e.g.
+/// shared by the trees of a [`Forest`](crate::Forest).
+pub const ROOT_UNRECORDED: u32 = u32::MAX;
+/// Node layout of this build, as recorded in [`TreeHeader::node_layout`].
+pub const NODE_LAYOUT: u32 = if cfg!(feature = "aligned-nodes") {
+    LAYOUT_ALIGNED
+} else {
+    0
+} | if cfg!(feature = "packed-color") {
+    LAYOUT_PACKED_COLOR
+} else {
+    0
+} | if cfg!(feature = "compact-srefs") {
+    LAYOUT_COMPACT_SREFS
+} else {
+    0
+} | LAYOUT_EXACT_STRIDE;
+
+/// Persisted record of how the tree was initialized. It lives in the non-tree
+/// part of the account and is cross-checked against the handle before the tree
+/// is modified, so a handle built with the wrong layout constants refuses to
+/// write instead of corrupting nodes.
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TreeHeader {
+    pub magic: u32,
+    pub version: u32,
+    pub non_tree_data_size: u32,
+    /// Incremented by every insert and delete (with the `counters` feature).
+    pub sequence: u64,
+    /// Caller-provided slot of the last mutation, see [`RBTree::mark_slot`].
+    pub last_slot: u64,
+    /// Size of the keys stored in `key_min`/`key_max`, zero when inserts are
+    /// not range-checked.
+    pub key_bound_len: u32,
+    pub key_min: [u8; KEY_BOUND_BYTES],
+    pub key_max: [u8; KEY_BOUND_BYTES],
+    /// Nodes in the node region, kept by every insert and delete. Trees of a
+    /// [`Forest`](crate::Forest) share the header, so there it is their total.
+    pub node_count: u32,
+    /// Bytes between the non-tree data and the node region, which starts at
+    /// the next multiple of [`REGION_ALIGN`].
+    pub region_pad: u32,
+    /// Layout flags of the nodes, [`NODE_LAYOUT`] of the build that
+    /// initialized the account.
+    pub node_layout: u32,
+    /// Size of the keys, zero when not recorded. A handle inserting keys of
+    /// another size reads the nodes with the wrong layout and is refused.
+    pub key_size: u32,
+    /// Offset of the root word in the account, [`ROOT_UNRECORDED`] when not
+    /// recorded. A handle whose root word lies elsewhere, e.g. in another
+    /// account, is refused.
+    pub root_offset: u32,
+}
+
+impl TreeHeader {
+    pub fn new(non_tree_data_size: usize) -> Self {
+        Self {
+            magic: TREE_MAGIC,
+            version: TREE_VERSION,
+            non_tree_data_size: non_tree_data_size as u32,
+            sequence: 0,
+            last_slot: 0,
+            key_bound_len: 0,
+            key_min: [0; KEY_BOUND_BYTES],
+            key_max: [0; KEY_BOUND_BYTES],
+            node_count: 0,
+            region_pad: (RBTree::region_start(non_tree_data_size) - non_tree_data_size) as u32,
+            node_layout: NODE_LAYOUT,
+            key_size: 0,
+            root_offset: ROOT_UNRECORDED,
+        }
+    }
+
+    /// The header with the size of `T` keys recorded.
+    pub fn with_key<T>(self) -> Self {
+        Self {
+            key_size: size_of::<T>() as u32,
+            ..self
+        }
+    }
+
+    /// The header with the offset of the root word in the account recorded.
+    /// Trees sharing a header have distinct root words and leave it
+    /// unrecorded.
+    pub fn with_root_offset(self, root_offset: usize) -> Self {
+        Self {
+            root_offset: root_offset as u32,
+            ..self
+        }
+    }
+
+    /// Checks that keys of `T` have the recorded size, if any.
+    pub fn check_key_size<T>(&self) -> Result<(), TreeError> {
+        if self.key_size != 0 && self.key_size as usize != size_of::<T>() {
+            return Err(TreeError::KeySizeMismatch {
+                initialized: self.key_size,
+                given: size_of::<T>(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that nodes were laid out like this build lays them out, at
+    /// either stride.
+    pub fn check_layout(&self) -> Result<(), TreeError> {
+        if self.node_layout & !LAYOUT_EXACT_STRIDE != NODE_LAYOUT & !LAYOUT_EXACT_STRIDE {
+            return Err(TreeError::LayoutMismatch {
+                initialized: self.node_layout,
+                built: NODE_LAYOUT,
+            });
+        }
+        Ok(())
+    }
+
+    /// Offset of the node region in the account.
+    pub fn region_start(&self) -> usize {
+        self.non_tree_data_size as usize + self.region_pad as usize
+    }
+}
+
+/// Padding recorded by the header at `header`, zero without a valid header.
+pub(crate) fn region_pad(header: *const TreeHeader) -> usize {
+    if header.is_null() {
+        return 0;
+    }
+    let header = unsafe { header.read_unaligned() };
+    if header.magic != TREE_MAGIC || header.version != TREE_VERSION {
+        return 0;
+    }
+    header.region_pad as usize
+}
+
+/// Whether the header at `header` records [`LAYOUT_EXACT_STRIDE`], false
+/// without a valid header.
+pub(crate) fn exact_stride(header: *const TreeHeader) -> bool {
+    if header.is_null() {
+        return false;
+    }
+    let header = unsafe { header.read_unaligned() };
+    header.magic == TREE_MAGIC
+        && header.version == TREE_VERSION
+        && header.node_layout & LAYOUT_EXACT_STRIDE != 0
+}
+
+impl RBTree {
+    /// Offset of the node region in an account with a header whose non-tree
+    /// data takes `non_tree_data_size` bytes: rounded up to [`REGION_ALIGN`],
+    /// so nodes never start misaligned after a prefix of e.g. 6 or 12 bytes.
+    /// Accounts without a header keep the region right after the prefix.
+    pub const fn region_start(non_tree_data_size: usize) -> usize {
+        non_tree_data_size.next_multiple_of(REGION_ALIGN)
+    }
+
+    /// Offset of this tree's node region, after the padding its header
+    /// records.
+    pub(crate) fn region_offset(&self) -> usize {
+        self.non_tree_data_size + region_pad(self.header)
+    }
+
+    /// Offset of the root word in the account, as far as the handle's
+    /// pointers tell.
+    pub(crate) fn root_offset(&self) -> usize {
+        let base = (self.entry as usize).wrapping_sub(self.region_offset());
+        (self.root as usize).wrapping_sub(base)
+    }
+
+    /// Header of the tree, or `None` for accounts formatted without one.
+    pub fn header(&self) -> Option<TreeHeader> {
+        if self.header.is_null() {
+            return None;
+        }
+        Some(unsafe { self.header.read_unaligned() })
+    }
+
+    /// Verifies that the header matches this handle. Trees without a header
+    /// always pass, except that aligned nodes need an aligned node region.
+    pub fn check_header(&self) -> Result<(), TreeError> {
+        if cfg!(feature = "aligned-nodes") && !(self.entry as usize).is_multiple_of(REGION_ALIGN) {
+            return Err(TreeError::InvalidLayout);
+        }
+        let Some(header) = self.header() else {
+            return Ok(());
+        };
+        if header.magic != TREE_MAGIC || header.version != TREE_VERSION {
+            return Err(TreeError::InvalidHeader);
+        }
+        header.check_layout()?;
+        if header.non_tree_data_size as usize != self.non_tree_data_size {
+            return Err(TreeError::NonTreeDataSizeMismatch {
+                initialized: header.non_tree_data_size,
+                given: self.non_tree_data_size,
+            });
+        }
+        if header.root_offset != ROOT_UNRECORDED
+            && header.root_offset as usize != self.root_offset()
+        {
+            return Err(TreeError::RootMismatch {
+                initialized: header.root_offset,
+                given: self.root_offset(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Mutation counter from the header, `None` for trees without a header.
+    /// Indexers compare it to detect missed updates. It stays at zero when the
+    /// writing program is built without the `counters` feature.
+    pub fn sequence(&self) -> Option<u64> {
+        self.header().map(|header| header.sequence)
+    }
+
+    /// Number of nodes, read from the header in O(1) when the header records
+    /// this tree's root word, as [`RBTree::init`] writes it. Other trees are
+    /// counted by walking them: those without a header, and those of a
+    /// [`Forest`](crate::Forest), whose shared header counts the nodes of
+    /// every tree in the region.
+    pub fn len<T: Copy>(&self) -> usize {
+        self.recorded_len()
+            .unwrap_or_else(|| self.iter::<T>().count())
+    }
+
+    /// Node count of the header if it is this tree's alone, see
+    /// [`RBTree::len`].
+    pub(crate) fn recorded_len(&self) -> Option<usize> {
+        let header = self.header()?;
+        (header.root_offset != ROOT_UNRECORDED && header.root_offset as usize == self.root_offset())
+            .then_some(header.node_count as usize)
+    }
+
+    /// Whether the tree has no nodes, without reading the header.
+    pub fn is_empty(&self) -> bool {
+        self.get_root_sref() == NULL_NODE
+    }
+
+    /// Records the slot of the current mutation in the header, for programs
+    /// that want reconciliation jobs to see when the tree last changed.
+    pub fn mark_slot(&self, slot: u64) {
+        if !self.header.is_null() {
+            unsafe { ptr::addr_of_mut!((*self.header).last_slot).write_unaligned(slot) }
+        }
+    }
+
+    /// Restricts inserts to keys in `min..=max`, e.g. a market's price band.
+    /// Needs a header and keys of at most [`KEY_BOUND_BYTES`] bytes. A range
+    /// with `min` above `max` would reject every key and fails with
+    /// [`TreeError::EmptyKeyRange`].
+    pub fn set_key_range<T: Copy + PartialOrd>(&self, min: T, max: T) -> Result<(), TreeError> {
+        if self.header.is_null() {
+            return Err(TreeError::InvalidHeader);
+        }
+        if size_of::<T>() > KEY_BOUND_BYTES {
+            return Err(TreeError::InvalidLayout);
+        }
+        if !matches!(
+            min.partial_cmp(&max),
+            Some(Ordering::Less | Ordering::Equal)
+        ) {
+            return Err(TreeError::EmptyKeyRange);
+        }
+        unsafe {
+            let mut header = self.header.read_unaligned();
+            header.key_bound_len = size_of::<T>() as u32;
+            ptr::write_unaligned(header.key_min.as_mut_ptr() as *mut T, min);
+            ptr::write_unaligned(header.key_max.as_mut_ptr() as *mut T, max);
+            self.header.write_unaligned(header);
+        }
+        Ok(())
+    }
+
+    /// Lifts the key range set by [`RBTree::set_key_range`].
+    pub fn clear_key_range(&self) {
+        if !self.header.is_null() {
+            unsafe { ptr::addr_of_mut!((*self.header).key_bound_len).write_unaligned(0) }
+        }
+    }
+
+    /// Allowed key range, or `None` if inserts are not range-checked. Fails if
+    /// the range was stored for a key type of a different size.
+    pub fn key_range<T: Copy>(&self) -> Result<Option<(T, T)>, TreeError> {
+        let Some(header) = self.header() else {
+            return Ok(None);
+        };
+        match header.key_bound_len as usize {
+            0 => Ok(None),
+            len if len == size_of::<T>() => unsafe {
+                Ok(Some((
+                    ptr::read_unaligned(header.key_min.as_ptr() as *const T),
+                    ptr::read_unaligned(header.key_max.as_ptr() as *const T),
+                )))
+            },
+            _ => Err(TreeError::InvalidHeader),
+        }
+    }
+
+    /// Fails with [`TreeError::KeyOutOfRange`] if `key` is outside the header's
+    /// key range, or [`TreeError::KeySizeMismatch`] if the header records keys
+    /// of another size.
+    pub(crate) fn check_key<T: Copy + PartialOrd>(&self, key: &T) -> Result<(), TreeError> {
+        if let Some(header) = self.header() {
+            header.check_key_size::<T>()?;
+        }
+        match self.key_range::<T>()? {
+            Some((min, max)) if *key < min || *key > max => Err(TreeError::KeyOutOfRange),
+            _ => Ok(()),
+        }
+    }
+
+    /// Adds one node to (or takes one from) the header's node count.
+    pub(crate) fn count_node(&self, added: bool) {
+        self.count_nodes(1, added);
+    }
+
+    /// Adds `nodes` nodes to (or takes them from) the header's node count.
+    pub(crate) fn count_nodes(&self, nodes: usize, added: bool) {
+        if !self.header.is_null() {
+            unsafe {
+                let count = ptr::addr_of_mut!((*self.header).node_count);
+                let old = count.read_unaligned();
+                count.write_unaligned(if added {
+                    old.wrapping_add(nodes as u32)
+                } else {
+                    old.wrapping_sub(nodes as u32)
+                });
+            }
+        }
+    }
+
+    /// Bumps the header's mutation counter. Compiled out without the
+    /// `counters` feature.
+    pub(crate) fn touch(&self) {
+        if cfg!(feature = "counters") && !self.header.is_null() {
+            unsafe {
+                let sequence = ptr::addr_of_mut!((*self.header).sequence);
+                sequence.write_unaligned(sequence.read_unaligned().wrapping_add(1));
+            }
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{
+    augment::{value_of, Augment, Augmented},
+    storage::Storage,
+    NodePtr, Payer, RBTree, TreeError,
+};
+use solana_program::account_info::AccountInfo;
+use std::{
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+};
+
+/// Key of an [`IntervalTree`]: the closed range `lo..=hi`. Intervals are
+/// ordered by `lo`, then by `hi`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Interval<T> {
+    pub lo: T,
+    pub hi: T,
+}
+
+impl<T: Copy + Ord> Interval<T> {
+    pub fn new(lo: T, hi: T) -> Self {
+        Self { lo, hi }
+    }
+
+    /// Whether the interval shares at least one point with `range`.
+    pub fn overlaps<R: RangeBounds<T>>(&self, range: &R) -> bool {
+        reaches_start(self.hi, range) && before_end(self.lo, range)
+    }
+}
+
+/// Value stored in each node of an [`IntervalTree`]: the link and the
+/// largest `hi` of the intervals in the subtree rooted at it.
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reach<T> {
+    pub link: u32,
+    pub max_hi: T,
+}
+
+impl<T: Copy + Ord> Augment<Interval<T>> for Reach<T> {
+    fn refresh(&mut self, key: &Interval<T>, left: Option<&Self>, right: Option<&Self>) {
+        let mut max_hi = key.hi;
+        for child in [left, right].into_iter().flatten() {
+            max_hi = max_hi.max(child.max_hi);
+        }
+        self.max_hi = max_hi;
+    }
+}
+
+/// Interval tree: keys are [`Interval`]s and every node keeps the largest
+/// `hi` below it, so an interval overlapping a range is found in O(log n)
+/// with [`IntervalTree::find_overlapping`].
+///
+/// The layout (`Node<Interval<T>, Reach<T>>`) differs from trees with `u32`
+/// links, so a region must only ever be used through one of the two APIs.
+pub struct IntervalTree<T> {
+    tree: Augmented<Interval<T>, Reach<T>>,
+    _key: PhantomData<T>,
+}
+
+impl<T: Copy + Ord> IntervalTree<T> {
+    pub fn new(tree: RBTree) -> Self {
+        Self {
+            tree: Augmented::new(tree),
+            _key: PhantomData,
+        }
+    }
+
+    /// Inserts `interval`, after any equal intervals. Returns the sref of the
+    /// new node. Fails with [`TreeError::EmptyInterval`] if `lo > hi`.
+    pub fn insert<'a, 'info>(
+        &self,
+        interval: Interval<T>,
+        link: u32,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<u32, TreeError> {
+        if interval.lo > interval.hi {
+            return Err(TreeError::EmptyInterval);
+        }
+        let value = Reach {
+            link,
+            max_hi: interval.hi,
+        };
+        self.tree
+            .insert(interval, value, tree_acc, payer, system_program)
+            .map(|node| node.sref())
+    }
+
+    /// Removes a node holding `interval` and returns its link, or `None` if
+    /// there is none.
+    pub fn remove(&mut self, interval: Interval<T>) -> Result<Option<u32>, TreeError> {
+        Ok(self.tree.remove(interval)?.map(|value| value.link))
+    }
+
+    /// The stored interval with the smallest `lo` that overlaps `range`,
+    /// with its link, or `None` if no interval does.
+    pub fn find_overlapping<R: RangeBounds<T>>(&self, range: R) -> Option<(Interval<T>, u32)> {
+        let mut node = self.tree.root_node::<Interval<T>, Reach<T>>();
+        while !node.is_null() {
+            // If no interval on the left overlaps although one reaches the
+            // range, that one starts after the range, and so does every
+            // interval from here on.
+            if max_hi(node.left()).is_some_and(|hi| reaches_start(hi, &range)) {
+                node = node.left();
+                continue;
+            }
+            let interval = node.key();
+            if interval.overlaps(&range) {
+                return Some((interval, node.value().link));
+            }
+            if !before_end(interval.lo, &range) {
+                return None;
+            }
+            node = node.right();
+        }
+        None
+    }
+
+    /// Every stored interval that overlaps `range`, in order, with its link.
+    /// Subtrees that cannot hold one are skipped.
+    pub fn overlapping<R: RangeBounds<T>>(&self, range: R) -> Vec<(Interval<T>, u32)> {
+        let mut found = Vec::new();
+        collect_overlapping(
+            self.tree.root_node::<Interval<T>, Reach<T>>(),
+            &range,
+            &mut found,
+        );
+        found
+    }
+}
+
+fn collect_overlapping<T: Copy + Ord, R: RangeBounds<T>>(
+    node: NodePtr<Interval<T>, Reach<T>>,
+    range: &R,
+    found: &mut Vec<(Interval<T>, u32)>,
+) {
+    if !max_hi(node).is_some_and(|hi| reaches_start(hi, range)) {
+        return;
+    }
+    collect_overlapping(node.left(), range, found);
+    let interval = node.key();
+    if !before_end(interval.lo, range) {
+        return;
+    }
+    if interval.overlaps(range) {
+        found.push((interval, node.value().link));
+    }
+    collect_overlapping(node.right(), range, found);
+}
+
+fn max_hi<T: Copy>(node: NodePtr<Interval<T>, Reach<T>>) -> Option<T> {
+    value_of(node).map(|value| value.max_hi)
+}
+
+/// Whether an interval ending at `hi` reaches the start of `range`.
+fn reaches_start<T: Ord, R: RangeBounds<T>>(hi: T, range: &R) -> bool {
+    match range.start_bound() {
+        Bound::Included(start) => hi >= *start,
+        Bound::Excluded(start) => hi > *start,
+        Bound::Unbounded => true,
+    }
+}
+
+/// Whether an interval starting at `lo` starts before the end of `range`.
+fn before_end<T: Ord, R: RangeBounds<T>>(lo: T, range: &R) -> bool {
+    match range.end_bound() {
+        Bound::Included(end) => lo <= *end,
+        Bound::Excluded(end) => lo < *end,
+        Bound::Unbounded => true,
+    }
+}
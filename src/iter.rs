@@ -0,0 +1,504 @@
+use crate::{
+    region_slots, storage::Storage, NodePtr, OpError, Operation, Phase, RBTree, TreeError,
+    NULL_NODE,
+};
+use std::{
+    cell::Cell,
+    iter::Rev,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+};
+
+/// In-order iterator over `(key, link)` pairs, returned by [`RBTree::iter`],
+/// or `(key, value)` pairs for trees storing values inline.
+///
+/// Both ends walk towards each other through parent links, so the tree is
+/// never copied and no stack is kept.
+///
+/// Inserts through the same handle may run while iterating: each end steps
+/// from the node it yielded last only when asked for the next one, and
+/// follows the node region as the account grows, so a node inserted between
+/// the two ends is yielded in its place.
+pub struct Iter<'a, T, V = u32> {
+    /// First and last node of the range, where the ends start.
+    lo: NodePtr<T, V>,
+    hi: NodePtr<T, V>,
+    /// Node each end yielded last, null before it has moved.
+    front: NodePtr<T, V>,
+    back: NodePtr<T, V>,
+    done: bool,
+    /// Length of the growing node region, for trees in an account.
+    region_len: Option<&'a Cell<usize>>,
+    _tree: PhantomData<&'a RBTree>,
+}
+
+impl<'a, T, V> Iter<'a, T, V> {
+    pub(crate) fn new(
+        lo: NodePtr<T, V>,
+        hi: NodePtr<T, V>,
+        region_len: Option<&'a Cell<usize>>,
+    ) -> Self {
+        Self {
+            lo,
+            hi,
+            front: NodePtr::null(),
+            back: NodePtr::null(),
+            done: lo.is_null(),
+            region_len,
+            _tree: PhantomData,
+        }
+    }
+
+    /// Steps one end and returns the node it lands on, finishing once it
+    /// meets the other end or passes the far end of the range.
+    fn step(&mut self, from_back: bool) -> Option<NodePtr<T, V>> {
+        if self.done {
+            return None;
+        }
+        if let Some(region_len) = self.region_len {
+            let region_len = region_len.get();
+            for node in [&mut self.lo, &mut self.hi, &mut self.front, &mut self.back] {
+                *node = node.with_region(region_len);
+            }
+        }
+        let (last, other, start, end) = if from_back {
+            (self.back, self.front, self.hi, self.lo)
+        } else {
+            (self.front, self.back, self.lo, self.hi)
+        };
+        let node = if last.is_null() {
+            start
+        } else if last == end {
+            NodePtr::null()
+        } else if from_back {
+            last.prev()
+        } else {
+            last.next()
+        };
+        if node.is_null() || node == other {
+            self.done = true;
+            return None;
+        }
+        if from_back {
+            self.back = node;
+        } else {
+            self.front = node;
+        }
+        Some(node)
+    }
+}
+
+impl<T: Copy, V: Copy> Iterator for Iter<'_, T, V> {
+    type Item = (T, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.step(false).map(|node| (node.key(), node.value()))
+    }
+}
+
+impl<T: Copy, V: Copy> DoubleEndedIterator for Iter<'_, T, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.step(true).map(|node| (node.key(), node.value()))
+    }
+}
+
+/// Iterator returned by [`RBTree::iter_with`].
+pub struct IterWith<'a, T, F> {
+    iter: Iter<'a, T>,
+    resolve: F,
+}
+
+impl<T: Copy, R, F: FnMut(u32) -> R> Iterator for IterWith<'_, T, F> {
+    type Item = (T, R);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, link) = self.iter.next()?;
+        Some((key, (self.resolve)(link)))
+    }
+}
+
+impl<T: Copy, R, F: FnMut(u32) -> R> DoubleEndedIterator for IterWith<'_, T, F> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let (key, link) = self.iter.next_back()?;
+        Some((key, (self.resolve)(link)))
+    }
+}
+
+/// Tree an entry yielded by [`MergeIter`] comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Origin {
+    First,
+    Second,
+}
+
+/// Sorted stream over two trees, returned by [`merge_iter`].
+pub struct MergeIter<'a, T> {
+    first: Iter<'a, T>,
+    second: Iter<'a, T>,
+    /// Entries taken from either end of `first` and `second` but not yielded
+    /// yet.
+    first_front: Option<(T, u32)>,
+    first_back: Option<(T, u32)>,
+    second_front: Option<(T, u32)>,
+    second_back: Option<(T, u32)>,
+}
+
+/// Iterates over the entries of both trees in ascending key order, yielding
+/// `(key, link, origin)`, e.g. a continuous book and an auction overlay read
+/// as one book. Neither tree is copied. Equal keys come from `first` before
+/// `second`.
+pub fn merge_iter<'a, T: Copy>(first: &'a RBTree, second: &'a RBTree) -> MergeIter<'a, T> {
+    MergeIter {
+        first: first.iter(),
+        second: second.iter(),
+        first_front: None,
+        first_back: None,
+        second_front: None,
+        second_back: None,
+    }
+}
+
+/// Fills `front` from the front of `iter`, falling back to the entry buffered
+/// at the back once `iter` is exhausted.
+fn fill_front<T: Copy>(
+    iter: &mut Iter<'_, T>,
+    front: &mut Option<(T, u32)>,
+    back: &mut Option<(T, u32)>,
+) {
+    if front.is_none() {
+        *front = iter.next().or_else(|| back.take());
+    }
+}
+
+fn fill_back<T: Copy>(
+    iter: &mut Iter<'_, T>,
+    front: &mut Option<(T, u32)>,
+    back: &mut Option<(T, u32)>,
+) {
+    if back.is_none() {
+        *back = iter.next_back().or_else(|| front.take());
+    }
+}
+
+impl<T: Copy + Ord> Iterator for MergeIter<'_, T> {
+    type Item = (T, u32, Origin);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        fill_front(&mut self.first, &mut self.first_front, &mut self.first_back);
+        fill_front(
+            &mut self.second,
+            &mut self.second_front,
+            &mut self.second_back,
+        );
+        let take_first = match (self.first_front, self.second_front) {
+            (Some((first, _)), Some((second, _))) => first <= second,
+            (first, _) => first.is_some(),
+        };
+        if take_first {
+            let (key, link) = self.first_front.take()?;
+            Some((key, link, Origin::First))
+        } else {
+            let (key, link) = self.second_front.take()?;
+            Some((key, link, Origin::Second))
+        }
+    }
+}
+
+impl<T: Copy + Ord> DoubleEndedIterator for MergeIter<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        fill_back(&mut self.first, &mut self.first_front, &mut self.first_back);
+        fill_back(
+            &mut self.second,
+            &mut self.second_front,
+            &mut self.second_back,
+        );
+        let take_second = match (self.first_back, self.second_back) {
+            (Some((first, _)), Some((second, _))) => second >= first,
+            (_, second) => second.is_some(),
+        };
+        if take_second {
+            let (key, link) = self.second_back.take()?;
+            Some((key, link, Origin::Second))
+        } else {
+            let (key, link) = self.first_back.take()?;
+            Some((key, link, Origin::First))
+        }
+    }
+}
+
+/// Iterator over allocated slots in sref order, returned by
+/// [`RBTree::iter_slab`].
+pub struct SlabIter<'a, T> {
+    tree: &'a RBTree,
+    /// Next slot from the front.
+    sref: u32,
+    /// One past the next slot from the back.
+    end: u32,
+    _key: PhantomData<T>,
+}
+
+impl<T> SlabIter<'_, T> {
+    /// Node at `sref` if the slot is allocated and inside the node region.
+    fn node(&self, sref: u32) -> Option<NodePtr<T>> {
+        if !self.tree.pt.is_allocated(sref as usize) {
+            return None;
+        }
+        let node = unsafe {
+            NodePtr::bounded(
+                self.tree.entry,
+                self.tree.region_len.get(),
+                self.tree.stride::<T, u32>(),
+                sref,
+            )
+        };
+        (!node.is_null()).then_some(node)
+    }
+}
+
+impl<T> Iterator for SlabIter<'_, T> {
+    type Item = NodePtr<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.sref < self.end {
+            let sref = self.sref;
+            self.sref += 1;
+            if let Some(node) = self.node(sref) {
+                return Some(node);
+            }
+        }
+        None
+    }
+}
+
+impl<T> DoubleEndedIterator for SlabIter<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while self.sref < self.end {
+            self.end -= 1;
+            if let Some(node) = self.node(self.end) {
+                return Some(node);
+            }
+        }
+        None
+    }
+}
+
+/// Iterator returned by [`RBTree::drain`].
+pub struct Drain<'a, T> {
+    tree: &'a mut RBTree,
+    node: NodePtr<T>,
+}
+
+impl<T> Drain<'_, T> {
+    /// Frees the next node and returns it. A freed slot keeps its links until
+    /// it is reused, so the walk can still step through it. A slot the
+    /// allocator does not hold ends the walk, as its links cannot be trusted.
+    fn free_next(&mut self) -> Option<Result<NodePtr<T>, OpError>> {
+        if self.node.is_null() {
+            return None;
+        }
+        let node = self.node;
+        let sref = node.sref();
+        self.node = node.next();
+        if self.tree.pt.dealloc(sref as usize).is_err() {
+            self.node = NodePtr::null();
+            let error = TreeError::DeallocFailed { sref };
+            return Some(Err(error.at(Operation::Drain, Phase::Alloc, sref)));
+        }
+        self.tree.count_node(false);
+        Some(Ok(node))
+    }
+}
+
+impl<T: Copy> Iterator for Drain<'_, T> {
+    type Item = Result<(T, u32), OpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.free_next()
+            .map(|node| node.map(|node| (node.key(), node.link())))
+    }
+}
+
+impl<T> Drop for Drain<'_, T> {
+    fn drop(&mut self) {
+        while let Some(Ok(_)) = self.free_next() {}
+    }
+}
+
+impl RBTree {
+    /// Empties the tree, yielding every `(key, link)` in ascending key order
+    /// and freeing its slot. Nodes are taken without rebalancing, as the tree
+    /// is gone at once: the root is cleared up front, and dropping the
+    /// iterator early still frees the rest.
+    ///
+    /// Fails without changing the tree if the header does not match this
+    /// handle. A node whose slot the allocator does not hold is yielded as
+    /// [`TreeError::DeallocFailed`] and ends the walk; the slots after it stay
+    /// allocated.
+    pub fn drain<T: Copy>(&mut self) -> Result<Drain<'_, T>, OpError> {
+        self.check_header()
+            .map_err(|e| e.at(Operation::Drain, Phase::Validate, NULL_NODE))?;
+        let node = self.get_root::<T>().min_node();
+        self.set_root_sref(NULL_NODE);
+        self.touch();
+        Ok(Drain { tree: self, node })
+    }
+
+    /// Removes every node in one walk that frees each slot, without the
+    /// rebalancing a `delete` per node would do. Slots of other trees sharing
+    /// the allocator, as in a [`Forest`](crate::Forest), stay allocated.
+    /// Fails as [`RBTree::drain`] does.
+    pub fn clear<T: Copy>(&mut self) -> Result<(), OpError> {
+        for entry in self.drain::<T>()? {
+            entry?;
+        }
+        Ok(())
+    }
+
+    /// Iterates over `(key, link)` in ascending key order.
+    pub fn iter<T>(&self) -> Iter<'_, T> {
+        let root = self.get_root::<T>();
+        Iter::new(root.min_node(), root.max_node(), Some(&self.region_len))
+    }
+
+    /// Iterates over the `(key, link)` entries whose keys fall within `range`,
+    /// in ascending order and double-ended like [`RBTree::iter`]. Only the
+    /// nodes in the range and the two search paths are visited.
+    pub fn range<T: Copy + Ord, R: RangeBounds<T>>(&self, range: R) -> Iter<'_, T> {
+        self.range_of::<T, u32, R>(range)
+    }
+
+    /// [`RBTree::range`] over nodes holding values of type `V`.
+    pub(crate) fn range_of<T: Copy + Ord, V, R: RangeBounds<T>>(&self, range: R) -> Iter<'_, T, V> {
+        let front = self.lower_bound_node::<T, V>(range.start_bound());
+        let back = self.upper_bound_node::<T, V>(range.end_bound());
+        if front.is_null() || back.is_null() || front.key() > back.key() {
+            return Iter::new(NodePtr::null(), NodePtr::null(), None);
+        }
+        Iter::new(front, back, Some(&self.region_len))
+    }
+
+    /// First node in key order whose key satisfies the lower bound.
+    pub(crate) fn lower_bound_node<T: Copy + Ord, V>(&self, bound: Bound<&T>) -> NodePtr<T, V> {
+        let mut node = self.root_node::<T, V>();
+        let mut found = NodePtr::null();
+        while !node.is_null() {
+            let inside = match bound {
+                Bound::Included(key) => node.key() >= *key,
+                Bound::Excluded(key) => node.key() > *key,
+                Bound::Unbounded => true,
+            };
+            if inside {
+                found = node;
+                node = node.left();
+            } else {
+                node = node.right();
+            }
+        }
+        found
+    }
+
+    /// Last node in key order whose key satisfies the upper bound.
+    pub(crate) fn upper_bound_node<T: Copy + Ord, V>(&self, bound: Bound<&T>) -> NodePtr<T, V> {
+        let mut node = self.root_node::<T, V>();
+        let mut found = NodePtr::null();
+        while !node.is_null() {
+            let inside = match bound {
+                Bound::Included(key) => node.key() <= *key,
+                Bound::Excluded(key) => node.key() < *key,
+                Bound::Unbounded => true,
+            };
+            if inside {
+                found = node;
+                node = node.right();
+            } else {
+                node = node.left();
+            }
+        }
+        found
+    }
+
+    /// Iterates over `(key, link)` in descending key order, starting at the
+    /// largest key, e.g. to scan bids from the best price down.
+    pub fn iter_rev<T: Copy>(&self) -> Rev<Iter<'_, T>> {
+        self.iter().rev()
+    }
+
+    /// Up to `k` smallest `(key, link)` pairs in ascending order. Visits only
+    /// the path to the smallest node and the subtrees of the `k` returned,
+    /// never stepping past the last one.
+    pub fn take_n_smallest<T: Copy>(&self, k: usize) -> Vec<(T, u32)> {
+        take_n(self.get_root::<T>(), false, k, self.region_slots::<T>())
+    }
+
+    /// Up to `k` largest `(key, link)` pairs in descending order, as
+    /// [`RBTree::take_n_smallest`].
+    pub fn take_n_largest<T: Copy>(&self, k: usize) -> Vec<(T, u32)> {
+        take_n(self.get_root::<T>(), true, k, self.region_slots::<T>())
+    }
+
+    /// Nodes the node region holds, which bounds any walk of a tree whose
+    /// links may be corrupted.
+    pub(crate) fn region_slots<T>(&self) -> usize {
+        region_slots::<T, u32>(self.region_len.get(), self.stride::<T, u32>())
+    }
+
+    /// Iterates in ascending key order, resolving each link through `resolve`
+    /// (e.g. into the order record it points at) and yielding `(key, record)`.
+    pub fn iter_with<T, R, F: FnMut(u32) -> R>(&self, resolve: F) -> IterWith<'_, T, F> {
+        IterWith {
+            iter: self.iter(),
+            resolve,
+        }
+    }
+
+    /// Yields every slot among the first `slots` that the allocator reports as
+    /// allocated, in sref order. Only the allocator is consulted, never the
+    /// tree links, so this works on trees with corrupted structure. Slots
+    /// outside the node region are skipped.
+    pub fn iter_slab<T>(&self, slots: u32) -> SlabIter<'_, T> {
+        SlabIter {
+            tree: self,
+            sref: 0,
+            end: slots,
+            _key: PhantomData,
+        }
+    }
+}
+
+/// The first `k` entries under `root` in key order, the last ones if `rev`,
+/// walked with an explicit stack. Links of a corrupted tree may form a
+/// cycle, so the walk stops after `limit` nodes, the most the node region
+/// holds.
+pub(crate) fn take_n<T: Copy>(
+    root: NodePtr<T>,
+    rev: bool,
+    k: usize,
+    limit: usize,
+) -> Vec<(T, u32)> {
+    // The child towards the end the entries are taken from, or away from it.
+    let child = |node: &NodePtr<T>, near: bool| {
+        if near != rev {
+            node.left()
+        } else {
+            node.right()
+        }
+    };
+    let mut entries = Vec::with_capacity(k.min(limit));
+    let mut stack = Vec::new();
+    let (mut node, mut visited) = (root, 0);
+    while entries.len() < k {
+        while !node.is_null() && visited < limit {
+            stack.push(node);
+            visited += 1;
+            node = child(&node, true);
+        }
+        let Some(top) = stack.pop() else {
+            break;
+        };
+        entries.push((top.key(), top.link()));
+        if entries.len() < k {
+            node = child(&top, false);
+        }
+    }
+    entries
+}
@@ -0,0 +1,92 @@
+use crate::{storage::Storage, Payer, RBTree, TreeError};
+use solana_program::account_info::AccountInfo;
+use std::{iter, marker::PhantomData};
+
+/// Payload that carries its own ordering key, e.g. an order ordered by
+/// price-time.
+pub trait KeyOf {
+    type Key: Copy + Ord;
+
+    fn key_of(&self) -> Self::Key;
+}
+
+/// Tree storing a payload inline in each node (`Node<V::Key, V>`) and ordering
+/// it by [`KeyOf::key_of`]. The key is only ever derived from the payload, so
+/// the two cannot drift apart.
+///
+/// The layout differs from trees with `u32` links, so a region must only ever
+/// be used through one of the two APIs.
+pub struct KeyedTree<V> {
+    tree: RBTree,
+    _value: PhantomData<V>,
+}
+
+impl<V: KeyOf + Copy> KeyedTree<V> {
+    pub fn new(tree: RBTree) -> Self {
+        Self {
+            tree,
+            _value: PhantomData,
+        }
+    }
+
+    pub fn tree(&self) -> &RBTree {
+        &self.tree
+    }
+
+    /// Inserts `value` under its key, after any values with an equal key.
+    /// Returns the sref of the new node; fails without changing the tree as
+    /// [`RBTree::try_insert`] does.
+    pub fn insert<'a, 'info>(
+        &self,
+        value: V,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<u32, TreeError> {
+        self.tree
+            .try_insert_value(value.key_of(), value, tree_acc, payer, system_program)
+    }
+
+    pub fn get(&self, key: V::Key) -> Option<V> {
+        let node = self.tree.search::<V::Key, V>(key);
+        (!node.is_null()).then(|| node.value())
+    }
+
+    /// Overwrites the stored value with the same key as `value`, returning the
+    /// previous one, or `None` if no value has that key. Fails with the
+    /// errors of [`RBTree::check_header`] before looking.
+    pub fn replace(&self, value: V) -> Result<Option<V>, TreeError> {
+        self.tree.check_header()?;
+        let node = self.tree.search::<V::Key, V>(value.key_of());
+        if node.is_null() {
+            return Ok(None);
+        }
+        let old = node.value();
+        node.set_value(value);
+        self.tree.touch();
+        Ok(Some(old))
+    }
+
+    /// Removes a value with `key` and returns it, or `None` if there is none.
+    /// Fails without changing the tree as [`RBTree::try_delete`] does.
+    pub fn remove(&mut self, key: V::Key) -> Result<Option<V>, TreeError> {
+        self.tree.check_header()?;
+        let node = self.tree.search::<V::Key, V>(key);
+        if node.is_null() {
+            return Ok(None);
+        }
+        let value = node.value();
+        self.tree.try_delete(node)?;
+        Ok(Some(value))
+    }
+
+    /// Values in ascending key order.
+    pub fn values(&self) -> impl Iterator<Item = V> + '_ {
+        let first = self.tree.root_node::<V::Key, V>().min_node();
+        iter::successors((!first.is_null()).then_some(first), |node| {
+            let next = node.next();
+            (!next.is_null()).then_some(next)
+        })
+        .map(|node| node.value())
+    }
+}
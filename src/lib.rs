@@ -1,36 +1,149 @@
+use bytemuck::NoUninit;
 use index_mem_alloc::MemoryMap;
 use solana_program::{
-    account_info::AccountInfo, program::invoke, system_instruction, sysvar::rent::Rent,
+    account_info::AccountInfo, entrypoint::MAX_PERMITTED_DATA_INCREASE, sysvar::rent::Rent,
 };
 use std::{
+    cell::Cell,
     cmp::Ordering,
     fmt::{self, Debug},
     mem::size_of,
+    ops::{Bound, RangeBounds},
     ptr,
 };
+use storage::Storage;
+
+mod account;
+#[cfg(feature = "anchor")]
+pub mod anchor;
+mod audit;
+mod augment;
+#[cfg(feature = "bench")]
+pub mod bench;
+mod bits;
+mod borrowed;
+mod bulk;
+mod capacity;
+mod compare;
+mod cursor;
+mod entry;
+mod error;
+mod export;
+mod fixed;
+mod forest;
+mod guard;
+mod header;
+mod interval;
+mod iter;
+mod keyed;
+mod link;
+mod maintenance;
+mod map;
+mod multi;
+mod normalize;
+mod payer;
+mod query;
+mod rank;
+mod reader;
+mod reuse;
+#[cfg(feature = "serde")]
+mod serialize;
+mod set;
+#[cfg(feature = "borsh")]
+mod snapshot;
+mod sref;
+mod storage;
+mod sum;
+mod summary;
+#[cfg(feature = "test-utils")]
+pub mod testing;
+mod unchecked;
+#[cfg(feature = "test-utils")]
+pub mod vectors;
+mod view;
+
+pub use audit::SlotAudit;
+pub use augment::Augment;
+pub use bits::NodeBits;
+pub use borrowed::{RBTreeRef, RefEntry};
+pub use bulk::build_account_image;
+pub use capacity::{AllocStats, ArenaStats, MAX_NODES};
+pub use compare::KeyComparison;
+pub use cursor::Cursor;
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use error::{ErrorContext, OpError, Operation, Phase, TreeError, CONTEXT_KEY_BYTES};
+pub use export::{Color, Tree};
+pub use fixed::StaticTree;
+pub use forest::Forest;
+pub use guard::TreeGuard;
+pub use header::{
+    TreeHeader, KEY_BOUND_BYTES, LAYOUT_ALIGNED, LAYOUT_COMPACT_SREFS, LAYOUT_EXACT_STRIDE,
+    LAYOUT_PACKED_COLOR, NODE_LAYOUT, REGION_ALIGN, ROOT_UNRECORDED, TREE_MAGIC, TREE_VERSION,
+};
+pub use interval::{Interval, IntervalTree, Reach};
+pub use iter::{merge_iter, Drain, Iter, IterWith, MergeIter, Origin, SlabIter};
+pub use keyed::{KeyOf, KeyedTree};
+pub use link::{Link, LinkConversionError};
+pub use maintenance::Compaction;
+pub use map::RBTreeMap;
+pub use normalize::{KeyNormalizer, Rounding, TickSize};
+pub use payer::Payer;
+pub use query::{
+    invoke_view, process_view, view_instruction, ViewLayout, ViewQuery, ViewReply, ViewRequest,
+};
+pub use rank::{RankTree, Ranked};
+pub use reader::RBTreeReader;
+pub use reuse::ReusableSlot;
+#[cfg(feature = "serde")]
+pub use serialize::StructureView;
+pub use set::RBTreeSet;
+#[cfg(feature = "borsh")]
+pub use snapshot::Snapshot;
+pub use sref::SrefWord;
+pub use sum::{SumTree, Weighted};
+pub use summary::TreeSummary;
+pub use view::{sniff_layout, LayoutVersion, TreeView, ViewIter};
 
 pub const NULL_NODE: u32 = 0xFFFFFFFF;
 pub const NULL_ORDER: u32 = 0xFFFF;
+/// Region length that disables node bounds checks, for handles on accounts
+/// whose size is not known.
+pub const UNBOUNDED_REGION: usize = usize::MAX;
 
-#[repr(C, packed)]
-pub struct Node<T: Sized> {
+/// Tree node as stored in the account. `V` is the per-node link; key-only
+/// trees use `()` and save its four bytes.
+///
+/// Nodes are packed, so fields are read with unaligned loads. With the
+/// `aligned-nodes` feature they follow C layout aligned to 8 bytes instead,
+/// padded to a multiple of 8, and fields are read with direct loads; the
+/// header records the layout so a build with the other one refuses the
+/// account.
+///
+/// With the `packed-color` feature the color is kept in the high bit of
+/// `parent` and the color word is dropped, saving four bytes per node; nodes
+/// then carry no flags, see [`NodeBits`]. With `compact-srefs` the links are
+/// [`SrefWord`]s of two bytes.
+#[cfg_attr(not(feature = "aligned-nodes"), repr(C, packed))]
+#[cfg_attr(feature = "aligned-nodes", repr(C, align(8)))]
+pub struct Node<T: Sized, V = u32> {
     key: T,
-    parent: u32,
-    left: u32,
-    right: u32,
-    sref: u32,
-    color: u32,
-    link: u32,
+    parent: SrefWord,
+    left: SrefWord,
+    right: SrefWord,
+    sref: SrefWord,
+    #[cfg(not(feature = "packed-color"))]
+    bits: NodeBits,
+    link: V,
 }
 
-impl<T: Debug + Copy> Debug for Node<T> {
+impl<T: Debug + Copy, V: Debug + Copy> Debug for Node<T, V> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let key = self.key;
-        let parent = self.parent;
-        let left = self.left;
-        let right = self.right;
-        let sref = self.sref;
-        let color = self.color;
+        let parent = self.parent_sref();
+        let left = self.left_sref();
+        let right = self.right_sref();
+        let sref = self.sref();
+        let bits = self.bits();
         let link = self.link;
 
         f.debug_struct("Node")
@@ -39,16 +152,73 @@ impl<T: Debug + Copy> Debug for Node<T> {
             .field("left", &left)
             .field("right", &right)
             .field("sref", &sref)
-            .field("color", &color)
+            .field("bits", &bits)
             .field("link", &link)
             .finish()
     }
 }
 
-#[derive(Clone, Copy)]
-pub struct NodePtr<T: Sized>(*mut Node<T>, *mut u64);
+/// Bytes from one node of a region to the next: the node size, unless the
+/// nodes are laid out as in v0.1.3 (no layout features, four-byte links) in
+/// an account whose header does not record [`LAYOUT_EXACT_STRIDE`], e.g. one
+/// without a header. Those keep the v0.1.3 stride of the node size rounded
+/// down to whole words, under which nodes whose size is not a multiple of 8
+/// overlap.
+#[inline]
+const fn node_stride<T, V>(exact: bool) -> usize {
+    let size = size_of::<Node<T, V>>();
+    if exact || NODE_LAYOUT != LAYOUT_EXACT_STRIDE || size_of::<V>() != size_of::<u32>() {
+        size
+    } else {
+        size & !7
+    }
+}
+
+/// Bytes of a region holding `slots` nodes `stride` bytes apart.
+#[inline]
+const fn region_bytes<T, V>(slots: usize, stride: usize) -> usize {
+    match slots {
+        0 => 0,
+        slots => (slots - 1) * stride + size_of::<Node<T, V>>(),
+    }
+}
+
+/// Nodes `stride` bytes apart lying entirely within `region_len` bytes.
+#[inline]
+const fn region_slots<T, V>(region_len: usize, stride: usize) -> usize {
+    match region_len.checked_sub(size_of::<Node<T, V>>()) {
+        Some(rest) => rest / stride + 1,
+        None => 0,
+    }
+}
+
+/// Address of the node stored at `sref`, nodes being laid out every `stride`
+/// bytes from `entry`.
+#[inline]
+fn slot<T, V>(entry: *mut u64, sref: u32, stride: usize) -> *mut Node<T, V> {
+    unsafe { (entry as *mut u8).add(sref as usize * stride) as *mut Node<T, V> }
+}
+
+/// Whether node `sref` lies entirely within the first `region_len` bytes.
+#[inline]
+fn in_region<T, V>(sref: u32, region_len: usize, stride: usize) -> bool {
+    sref as usize * stride + size_of::<Node<T, V>>() <= region_len
+}
+
+/// Pointer to a node, carrying the start, length in bytes and node stride of
+/// the node region it was derived from. Every node reached through a link is
+/// checked against that length.
+pub struct NodePtr<T: Sized, V = u32>(*mut Node<T, V>, *mut u64, usize, usize);
+
+impl<T, V> Clone for NodePtr<T, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, V> Copy for NodePtr<T, V> {}
 
-impl<T> PartialEq for NodePtr<T> {
+impl<T, V> PartialEq for NodePtr<T, V> {
     #[inline]
     fn eq(&self, other: &Self) -> bool {
         if self.is_null() && other.is_null() {
@@ -60,186 +230,220 @@ impl<T> PartialEq for NodePtr<T> {
     }
 }
 
-impl<T> NodePtr<T> {
-    fn null() -> NodePtr<T> {
-        NodePtr(ptr::null_mut(), ptr::null_mut())
+impl<T, V> NodePtr<T, V> {
+    fn null() -> Self {
+        NodePtr(ptr::null_mut(), ptr::null_mut(), 0, 0)
     }
     pub fn is_null(&self) -> bool {
         self.0.is_null()
     }
+    /// Node `sref` of a region of `region_len` bytes starting at `entry`, or a
+    /// null pointer if it does not fit in the region. Nodes are laid out as in
+    /// accounts without a header; [`RBTree::node`] reads any account.
+    ///
     /// # Safety
-    /// This function is really safe
-    pub unsafe fn get(entry: *mut u64, sref: u32) -> NodePtr<T> {
-        let node_ptr =
-            entry.offset(sref as isize * (size_of::<Node<T>>() >> 3) as isize) as *mut Node<T>;
-        NodePtr(node_ptr, entry)
+    /// `entry` must point to at least `region_len` bytes.
+    pub unsafe fn get_bounded(entry: *mut u64, region_len: usize, sref: u32) -> Self {
+        Self::bounded(entry, region_len, node_stride::<T, V>(false), sref)
     }
-
-    fn new<'a, 'info>(
-        mut pt: MemoryMap, //MemoryMap,
+    /// Node `sref` of a region of `region_len` bytes starting at `entry` with
+    /// nodes `stride` bytes apart, or a null pointer if it does not fit.
+    pub(crate) unsafe fn bounded(
         entry: *mut u64,
-        non_tree_data_size: usize,
+        region_len: usize,
+        stride: usize,
+        sref: u32,
+    ) -> Self {
+        if sref == NULL_NODE || !in_region::<T, V>(sref, region_len, stride) {
+            return Self::null();
+        }
+        NodePtr(slot(entry, sref, stride), entry, region_len, stride)
+    }
+    /// The same node, bounded by a region of `region_len` bytes, e.g. after
+    /// the account has grown.
+    #[inline]
+    fn with_region(self, region_len: usize) -> Self {
+        if self.is_null() {
+            return self;
+        }
+        NodePtr(self.0, self.1, region_len, self.3)
+    }
+    /// Node `sref` of the same region. A link leading outside the region means
+    /// the tree is corrupted, so it panics instead of reading past the account.
+    #[inline]
+    fn at(&self, sref: u32) -> Self {
+        if sref == NULL_NODE {
+            return Self::null();
+        }
+        assert!(
+            in_region::<T, V>(sref, self.2, self.3),
+            "node {sref} outside the node region"
+        );
+        #[cfg(feature = "bench")]
+        crate::bench::count_visit(sref as u64 * self.3 as u64);
+        NodePtr(slot(self.1, sref, self.3), self.1, self.2, self.3)
+    }
+
+    /// Allocates a slot of `tree` below `max` and writes a node for `key`
+    /// into it. `fit` makes room for the slot, given the data length it
+    /// needs, and returns the length of the node region after.
+    fn new(
+        tree: &RBTree,
         key: T,
-        link: u32,
-        tree_acc: &'a AccountInfo<'info>,
-        signer: &'a AccountInfo<'info>,
-        system_program: &'a AccountInfo<'info>,
-    ) -> NodePtr<T> {
-        let index = match pt.alloc() {
-            Ok(idx) => idx,
-            Err(_) => return NodePtr::null(),
+        link: V,
+        max: usize,
+        fit: impl FnOnce(usize) -> Result<usize, TreeError>,
+    ) -> Result<Self, TreeError> {
+        let mut pt = tree.pt.clone();
+        let region_start = tree.region_offset();
+        let stride = tree.stride::<T, V>();
+        let index = pt.alloc().map_err(|_| TreeError::AllocFailed)?;
+        let freed = |mut pt: MemoryMap, error| {
+            pt.dealloc(index)
+                .map_err(|_| TreeError::DeallocFailed { sref: index as u32 })
+                .and(Err(error))
         };
-        let sref = index;
-        let acc_size = tree_acc.data_len();
-        let min_size = non_tree_data_size + size_of::<Node<T>>() * (sref + 1);
-        if min_size > acc_size {
-            let rent = &Rent::default();
-            let new_minimum_balance = rent.minimum_balance(min_size);
-            let lamports_diff = new_minimum_balance.saturating_sub(tree_acc.lamports());
-            invoke(
-                &system_instruction::transfer(signer.key, tree_acc.key, lamports_diff),
-                &[signer.clone(), tree_acc.clone(), system_program.clone()],
-            )
-            .unwrap();
-            tree_acc.realloc(min_size, true).unwrap();
+        if index >= max {
+            // Past the limit there is no room the node could be given.
+            return freed(pt, TreeError::NodeLimit { max: max as u32 });
         }
+        let sref = index;
+        let region_len = match fit(region_start + region_bytes::<T, V>(sref + 1, stride)) {
+            Ok(region_len) => region_len,
+            Err(error) => return freed(pt, error),
+        };
+        Ok(Self::write(
+            tree.entry,
+            region_len,
+            stride,
+            sref as u32,
+            key,
+            link,
+        ))
+    }
+    /// Writes a fresh unlinked red node into slot `sref` of the region.
+    fn write(
+        entry: *mut u64,
+        region_len: usize,
+        stride: usize,
+        sref: u32,
+        key: T,
+        link: V,
+    ) -> Self {
         unsafe {
-            let node_ptr =
-                entry.offset((sref * (size_of::<Node<T>>() >> 3)) as isize) as *mut Node<T>;
-            *node_ptr = Node {
+            let node_ptr = slot(entry, sref, stride);
+            *node_ptr = Node::new(
                 key,
-                parent: NULL_NODE,
-                left: NULL_NODE,
-                right: NULL_NODE,
-                sref: sref as u32,
-                color: 1,
+                NULL_NODE,
+                NULL_NODE,
+                NULL_NODE,
+                sref,
+                NodeBits::RED,
                 link,
-            };
-            NodePtr(node_ptr, entry)
+            );
+            NodePtr(node_ptr, entry, region_len, stride)
         }
     }
-    pub fn left(&self) -> NodePtr<T> {
+    pub fn left(&self) -> Self {
         if self.is_null() {
             return Self::null();
         }
-        unsafe {
-            if self.get_node().left == NULL_NODE {
-                return Self::null();
-            }
-            NodePtr(
-                self.1.offset(
-                    self.get_node().left as isize * (std::mem::size_of::<Node<T>>() >> 3) as isize,
-                ) as *mut Node<T>,
-                self.1,
-            )
-        }
+        unsafe { self.at((*self.0).left_sref()) }
     }
-    pub fn right(&self) -> NodePtr<T> {
+    pub fn right(&self) -> Self {
         if self.is_null() {
             return Self::null();
         }
-        unsafe {
-            if self.get_node().right == NULL_NODE {
-                return Self::null();
-            }
-            NodePtr(
-                self.1.offset(
-                    self.get_node().right as isize * (std::mem::size_of::<Node<T>>() >> 3) as isize,
-                ) as *mut Node<T>,
-                self.1,
-            )
-        }
+        unsafe { self.at((*self.0).right_sref()) }
     }
-    fn parent(&self) -> NodePtr<T> {
-        unsafe {
-            if self.is_null() || self.get_node().parent == NULL_NODE {
-                return Self::null();
-            }
-            NodePtr(
-                self.1.offset(
-                    self.get_node().parent as isize
-                        * (std::mem::size_of::<Node<T>>() >> 3) as isize,
-                ) as *mut Node<T>,
-                self.1,
-            )
+    fn parent(&self) -> Self {
+        if self.is_null() {
+            return Self::null();
         }
+        unsafe { self.at((*self.0).parent_sref()) }
     }
     pub fn sref(&self) -> u32 {
         if self.is_null() {
             return NULL_NODE;
         }
-        unsafe { self.get_node().sref }
+        unsafe { (*self.0).sref() }
     }
-    pub fn link(&self) -> u32 {
-        if self.is_null() {
-            return NULL_ORDER;
-        }
-        unsafe { self.get_node().link }
+    /// Link of the node; unlike [`NodePtr::link`] it must not be called on a
+    /// null pointer.
+    pub fn value(&self) -> V
+    where
+        V: Copy,
+    {
+        unsafe { (*self.0).link }
     }
     pub fn key(&self) -> T
     where
         T: Copy,
     {
-        unsafe { self.get_node().key }
+        unsafe { (*self.0).key }
     }
 
-    pub unsafe fn get_node(&self) -> Node<T> {
+    pub unsafe fn get_node(&self) -> Node<T, V> {
         unsafe { self.0.read_unaligned() }
     }
 
-    fn set_parent(&mut self, parent: NodePtr<T>) {
+    fn set_parent(&mut self, parent: Self) {
         if self.is_null() {
             return;
         }
         unsafe {
             if parent.is_null() {
-                (*self.0).parent = NULL_NODE
+                (*self.0).set_parent_sref(NULL_NODE)
             } else {
-                (*self.0).parent = (*parent.0).sref
+                (*self.0).set_parent_sref((*parent.0).sref())
             }
         }
     }
-    fn set_left(&self, left: NodePtr<T>) {
+    fn set_left(&self, left: Self) {
         if self.is_null() {
             return;
         }
         unsafe {
             if left.is_null() {
-                (*self.0).left = NULL_NODE
+                (*self.0).set_left_sref(NULL_NODE)
             } else {
-                (*self.0).left = (*left.0).sref
+                (*self.0).set_left_sref((*left.0).sref())
             }
         }
     }
-    fn set_right(&self, right: NodePtr<T>) {
+    fn set_right(&self, right: Self) {
         if self.is_null() {
             return;
         }
         unsafe {
             if right.is_null() {
-                (*self.0).right = NULL_NODE
+                (*self.0).set_right_sref(NULL_NODE)
             } else {
-                (*self.0).right = (*right.0).sref
+                (*self.0).set_right_sref((*right.0).sref())
             }
         }
     }
-    fn set_color(&mut self, color: u32) {
+    fn set_value(&self, value: V) {
         if self.is_null() {
             return;
         }
-        unsafe { (*self.0).color = color }
+        unsafe { (*self.0).link = value }
     }
-    pub fn is_red_color(&self) -> bool {
+    fn set_bits(&self, bits: NodeBits) {
         if self.is_null() {
-            return false;
+            return;
         }
-        unsafe { self.get_node().color == 1 }
+        unsafe { (*self.0).set_bits(bits) }
+    }
+    /// Sets the color (1 for red), keeping the node's flags.
+    fn set_color(&mut self, color: u32) {
+        self.set_bits(self.bits().with_red(color == 1));
+    }
+    pub fn is_red_color(&self) -> bool {
+        self.bits().is_red()
     }
     pub fn is_black_color(&self) -> bool {
-        if self.is_null() {
-            return true;
-        }
-        unsafe { self.get_node().color == 0 }
+        !self.bits().is_red()
     }
     fn set_red_color(&mut self) {
         self.set_color(1);
@@ -248,26 +452,128 @@ impl<T> NodePtr<T> {
         self.set_color(0);
     }
     fn get_color(&self) -> u32 {
-        if self.is_null() {
-            return 0;
-        }
-        unsafe { self.get_node().color }
+        self.bits().is_red() as u32
     }
-    pub fn min_node(self) -> NodePtr<T> {
+    pub fn min_node(self) -> Self {
         let mut temp = self;
         while !temp.left().is_null() {
             temp = temp.left();
         }
         temp
     }
-    pub fn max_node(self) -> NodePtr<T> {
+    pub fn max_node(self) -> Self {
         let mut temp = self;
         while !temp.right().is_null() {
             temp = temp.right();
         }
         temp
     }
+    /// In-order successor, or a null pointer for the largest node.
+    pub fn next(self) -> Self {
+        if self.is_null() {
+            return self;
+        }
+        if !self.right().is_null() {
+            return self.right().min_node();
+        }
+        let mut node = self;
+        let mut parent = node.parent();
+        while !parent.is_null() && parent.right() == node {
+            node = parent;
+            parent = node.parent();
+        }
+        parent
+    }
+    /// In-order predecessor, or a null pointer for the smallest node.
+    pub fn prev(self) -> Self {
+        if self.is_null() {
+            return self;
+        }
+        if !self.left().is_null() {
+            return self.left().max_node();
+        }
+        let mut node = self;
+        let mut parent = node.parent();
+        while !parent.is_null() && parent.left() == node {
+            node = parent;
+            parent = node.parent();
+        }
+        parent
+    }
+}
+impl<T> NodePtr<T> {
+    pub fn link(&self) -> u32 {
+        if self.is_null() {
+            return NULL_ORDER;
+        }
+        unsafe { (*self.0).link }
+    }
+}
+
+/// Grows `tree_acc` to at least `min_size` bytes, topping its lamports up to
+/// the rent-exempt minimum from `payer` first. A growing account already
+/// holding the rent for its new size is reallocated without the (empty)
+/// transfer CPI. A payer short of the rent, or growth past what the runtime
+/// allows in one instruction past `start_len`, is reported before the
+/// transfer, which would otherwise abort the transaction.
+pub(crate) fn grow_account<'a, 'info>(
+    min_size: usize,
+    start_len: usize,
+    tree_acc: &'a AccountInfo<'info>,
+    payer: Payer<'a, 'info>,
+    system_program: &'a AccountInfo<'info>,
+) -> Result<(), TreeError> {
+    if min_size <= tree_acc.data_len() {
+        return Ok(());
+    }
+    if min_size > growth_limit(start_len) {
+        return Err(TreeError::GrowthLimit {
+            instructions: growth_instructions(tree_acc.data_len(), min_size),
+        });
+    }
+    let rent = &Rent::default();
+    let new_minimum_balance = rent.minimum_balance(min_size);
+    let lamports_diff = new_minimum_balance.saturating_sub(tree_acc.lamports());
+    if lamports_diff > 0 {
+        payer.fund(lamports_diff, tree_acc, system_program)?;
+    }
+    tree_acc
+        .realloc(min_size, true)
+        .map_err(|_| TreeError::ReallocFailed)
+}
+
+/// Largest size an account of `start_len` bytes when the instruction started
+/// can be reallocated to in it.
+pub(crate) const fn growth_limit(start_len: usize) -> usize {
+    start_len.saturating_add(MAX_PERMITTED_DATA_INCREASE)
 }
+
+/// Instructions it takes to grow an account from `len` to `min_size` bytes,
+/// each growing it by [`MAX_PERMITTED_DATA_INCREASE`].
+pub(crate) fn growth_instructions(len: usize, min_size: usize) -> u32 {
+    min_size
+        .saturating_sub(len)
+        .div_ceil(MAX_PERMITTED_DATA_INCREASE) as u32
+}
+
+/// Side of the book whose resting orders a tree holds. Asks are best at the
+/// lowest key, bids at the highest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+/// A node taken out by [`RBTree::remove_entry`] or
+/// [`RBTree::delete_by_sref`]. The slot at `sref` is free
+/// again, so side tables indexed by it can be cleared.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Removed<T> {
+    pub key: T,
+    pub link: u32,
+    pub sref: u32,
+}
+
 pub struct RBTree {
     pub pt: MemoryMap,
     pub root: *mut u32,
@@ -277,208 +583,370 @@ pub struct RBTree {
     /// This value represents the number of bytes reserved for metadata, headers,
     /// or other data stored in the account before the tree nodes.
     pub non_tree_data_size: usize,
+    /// Header stored in the non-tree data, checked before every mutation.
+    /// Null for accounts formatted without a header.
+    pub header: *mut TreeHeader,
+    /// Bytes of account data after `non_tree_data_size`. Nodes outside it are
+    /// never read or written; inserts raise it as the account grows.
+    /// [`UNBOUNDED_REGION`] turns the checks off.
+    pub region_len: Cell<usize>,
+    /// Length of the account when this handle was first asked to grow it,
+    /// taken as its length when the instruction started: growth stops
+    /// [`MAX_PERMITTED_DATA_INCREASE`] past it. `None` until then, so a
+    /// handle should not outlive the instruction it was opened in.
+    pub grown_from: Cell<Option<usize>>,
 }
 
-impl RBTree {
+impl Storage for RBTree {
     #[inline]
-    fn get_root_sref(&self) -> u32 {
-        unsafe { *self.root }
+    fn root_word(&self) -> *mut u32 {
+        self.root
     }
     #[inline]
-    fn set_root_sref(&self, new_root: u32) {
-        unsafe { *self.root = new_root }
+    fn node_entry(&self) -> *mut u64 {
+        self.entry
     }
     #[inline]
-    fn left_rotate<T: Copy>(&self, mut node: NodePtr<T>) {
-        let mut temp = node.right();
-        node.set_right(temp.left());
-        if !temp.left().is_null() {
-            temp.left().set_parent(node);
-        }
-        temp.set_parent(node.parent());
-        if node.sref() == self.get_root_sref() {
-            self.set_root_sref(temp.sref());
-        } else if node == node.parent().left() {
-            node.parent().set_left(temp);
-        } else {
-            node.parent().set_right(temp);
-        }
-        temp.set_left(node);
-        node.set_parent(temp);
+    fn node_region_len(&self) -> usize {
+        self.region_len.get()
     }
     #[inline]
-    fn right_rotate<T: Copy>(&self, mut node: NodePtr<T>) {
-        let mut temp = node.left();
-        node.set_left(temp.right());
+    fn stride<T, V>(&self) -> usize {
+        node_stride::<T, V>(header::exact_stride(self.header))
+    }
+}
 
-        if !temp.right().is_null() {
-            temp.right().set_parent(node);
+impl RBTree {
+    /// Node stored at `sref`, or a null pointer unless the slot is allocated
+    /// and holds a node claiming that sref.
+    fn live_node<T, V>(&self, sref: u32) -> NodePtr<T, V> {
+        if sref == NULL_NODE || !self.pt.is_allocated(sref as usize) {
+            return NodePtr::null();
         }
-
-        temp.set_parent(node.parent());
-        if node.sref() == self.get_root_sref() {
-            self.set_root_sref(temp.sref());
-        } else if node == node.parent().right() {
-            node.parent().set_right(temp);
-        } else {
-            node.parent().set_left(temp);
+        let node = unsafe {
+            NodePtr::bounded(
+                self.entry,
+                self.region_len.get(),
+                self.stride::<T, V>(),
+                sref,
+            )
+        };
+        if node.sref() != sref {
+            return NodePtr::null();
         }
-        temp.set_right(node);
-        node.set_parent(temp);
+        node
     }
+    /// Raises `region_len` after the account has grown.
     #[inline]
-    fn insert_fixup<T: Copy>(&self, mut node: NodePtr<T>) {
-        let mut parent;
-        let mut gparent;
-        while node.parent().is_red_color() {
-            parent = node.parent();
-            gparent = parent.parent();
-            if parent == gparent.left() {
-                let mut uncle = gparent.right();
-                if !uncle.is_null() && uncle.is_red_color() {
-                    uncle.set_black_color();
-                    parent.set_black_color();
-                    gparent.set_red_color();
-                    node = gparent;
-                    continue;
-                }
-                if parent.right() == node {
-                    self.left_rotate(parent);
-                    std::mem::swap(&mut parent, &mut node);
-                }
-                parent.set_black_color();
-                gparent.set_red_color();
-                self.right_rotate(gparent);
-            } else {
-                let mut uncle = gparent.left();
-                if !uncle.is_null() && uncle.is_red_color() {
-                    uncle.set_black_color();
-                    parent.set_black_color();
-                    gparent.set_red_color();
-                    node = gparent;
-                    continue;
-                }
-                if parent.left() == node {
-                    self.right_rotate(parent);
-                    std::mem::swap(&mut parent, &mut node);
-                }
-                parent.set_black_color();
-                gparent.set_red_color();
-                self.left_rotate(gparent);
-            }
+    fn grow_region(&self, region_len: usize) {
+        if self.region_len.get() < region_len {
+            self.region_len.set(region_len);
         }
-        self.get_root::<T>().set_black_color();
     }
 
-    pub fn insert_direct<'info, 'a, T: Copy + PartialOrd>(
+    /// Length `tree_acc` started the instruction with, recording its current
+    /// length the first time it is asked for, see [`RBTree::grown_from`].
+    pub(crate) fn growth_start(&self, tree_acc: &AccountInfo) -> usize {
+        let start = self.grown_from.get().unwrap_or_else(|| tree_acc.data_len());
+        self.grown_from.set(Some(start));
+        start
+    }
+
+    /// Validates `key` against the header, then allocates and writes an
+    /// unlinked node for it, growing the account if needed.
+    pub(crate) fn new_node<'info, 'a, T: Copy + PartialOrd, V>(
         &self,
-        y: NodePtr<T>,
         key: T,
-        link: u32,
+        link: V,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<NodePtr<T, V>, TreeError> {
+        let max = Self::node_limit::<T, V>(self.region_offset(), self.stride::<T, V>());
+        self.new_node_with(key, link, max, |min_size| {
+            grow_account(
+                min_size,
+                self.growth_start(tree_acc),
+                tree_acc,
+                payer.into(),
+                system_program,
+            )?;
+            Ok(tree_acc.data_len() - self.region_offset())
+        })
+    }
+    /// Like [`RBTree::new_node`], within the node region as it is: a slot
+    /// past its end fails with [`TreeError::NodeLimit`].
+    pub(crate) fn new_node_in_region<T: Copy + PartialOrd, V>(
+        &self,
+        key: T,
+        link: V,
+    ) -> Result<NodePtr<T, V>, TreeError> {
+        let region_len = self.region_len.get();
+        let stride = self.stride::<T, V>();
+        let max = Self::node_limit::<T, V>(self.region_offset(), stride)
+            .min(region_slots::<T, V>(region_len, stride));
+        self.new_node_with(key, link, max, |_| Ok(region_len))
+    }
+    fn new_node_with<T: Copy + PartialOrd, V>(
+        &self,
+        key: T,
+        link: V,
+        max: usize,
+        fit: impl FnOnce(usize) -> Result<usize, TreeError>,
+    ) -> Result<NodePtr<T, V>, TreeError> {
+        self.check_header()?;
+        self.check_key(&key)?;
+        let node = NodePtr::new(self, key, link, max, fit)?;
+        self.grow_region(node.2);
+        self.count_node(true);
+        Ok(node)
+    }
+    /// Links the unlinked `node` after any nodes already holding its key.
+    pub(crate) fn link_in_order<T: Copy + PartialOrd, V>(&self, node: NodePtr<T, V>) {
+        let key = node.key();
+        let mut parent = NodePtr::null();
+        let mut next = self.root_node::<T, V>();
+        while !next.is_null() {
+            parent = next;
+            next = if key < next.key() {
+                next.left()
+            } else {
+                next.right()
+            };
+        }
+        self.link(parent, node);
+    }
+    /// Inserts `key` as a child of `y`, which must be the node a search for
+    /// `key` ends at (or null for an empty tree).
+    pub fn insert_direct<'info, 'a, T: Copy + PartialOrd, V>(
+        &self,
+        y: NodePtr<T, V>,
+        key: T,
+        link: V,
         tree_acc: &'a AccountInfo<'info>,
-        signer: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
         system_program: &'a AccountInfo<'info>,
     ) -> u32 {
-        let mut node = NodePtr::new(
-            self.pt.clone(),
-            self.entry,
-            self.non_tree_data_size,
-            key,
-            link,
-            tree_acc,
-            signer,
-            system_program,
-        );
-        if node.is_null() {
+        let Ok(node) = self.new_node(key, link, tree_acc, payer, system_program) else {
             return NULL_NODE;
-        }
-        let node_sref = node.sref();
-        node.set_parent(y);
-        if key < y.key() {
-            y.set_left(node);
-        } else {
-            y.set_right(node);
-        }
-        node.set_red_color();
-        self.insert_fixup(node);
-        node_sref
+        };
+        self.link(y, node);
+        self.touch();
+        node.sref()
     }
+    /// Inserts `key` after any nodes already holding it and returns the sref
+    /// of the new node, or `NULL_NODE` if it could not be inserted; see
+    /// [`RBTree::try_insert`] for the reason.
+    #[deprecated(note = "use `RBTree::try_insert`, which returns the error instead of `NULL_NODE`")]
     pub fn insert<'b, 'info, 'a, T: Copy + PartialOrd>(
         &self,
         key: T,
         link: u32,
         tree_acc: &'a AccountInfo<'info>,
-        signer: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
         system_program: &'a AccountInfo<'info>,
     ) -> u32 {
-        let mut node = NodePtr::new(
-            self.pt.clone(),
-            self.entry,
-            self.non_tree_data_size,
-            key,
-            link,
-            tree_acc,
-            signer,
-            system_program,
-        );
-        if node.is_null() {
-            return NULL_NODE;
-        }
-        let node_sref = node.sref();
-        let mut y = NodePtr::null();
-        let mut x = self.get_root();
-        while !x.is_null() {
-            y = x;
-            if key < x.key() {
-                x = x.left();
-            } else {
-                x = x.right();
-            }
-        }
-        node.set_parent(y);
-        if y.is_null() {
-            self.set_root_sref(node.sref());
-        } else if key < y.key() {
-            y.set_left(node);
-        } else {
-            y.set_right(node);
+        self.try_insert(key, link, tree_acc, payer, system_program)
+            .unwrap_or(NULL_NODE)
+    }
+    /// Like [`RBTree::insert`], reporting why a key could not be inserted:
+    /// a rejected key, no free slot, or an account that could not grow, e.g.
+    /// a payer short of the rent. Nothing is changed on failure.
+    pub fn try_insert<'info, 'a, T: Copy + PartialOrd>(
+        &self,
+        key: T,
+        link: u32,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<u32, TreeError> {
+        self.try_insert_value(key, link, tree_acc, payer, system_program)
+    }
+    /// [`RBTree::try_insert`] for nodes holding a `V` instead of a `u32` link,
+    /// e.g. a `u64` order id, read back through [`NodePtr::value`],
+    /// [`RBTreeMap`] or a [`TreeView`] of the same `V`. Every node of the tree
+    /// must hold the same `V`.
+    pub fn try_insert_value<'info, 'a, T: Copy + PartialOrd, V>(
+        &self,
+        key: T,
+        value: V,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<u32, TreeError> {
+        let node = self.new_node(key, value, tree_acc, payer, system_program)?;
+        self.link_in_order(node);
+        self.touch();
+        Ok(node.sref())
+    }
+    /// Inserts `key` unless it is already present, in which case the existing
+    /// node is reported through [`TreeError::DuplicateKey`]. Returns the sref
+    /// of the new node.
+    pub fn insert_unique<'info, 'a, T: Copy + Ord + NoUninit>(
+        &self,
+        key: T,
+        link: u32,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<u32, OpError> {
+        self.check_header()
+            .and_then(|_| self.check_key(&key))
+            .map_err(|e| e.during(Operation::Insert, Phase::Validate, NULL_NODE, &key))?;
+        let mut parent = NodePtr::<T>::null();
+        let mut node = self.get_root();
+        while !node.is_null() {
+            parent = node;
+            node = match key.cmp(&node.key()) {
+                Ordering::Less => node.left(),
+                Ordering::Greater => node.right(),
+                Ordering::Equal => {
+                    let error = TreeError::DuplicateKey {
+                        sref: node.sref(),
+                        link: node.link(),
+                    };
+                    return Err(error.during(Operation::Insert, Phase::Search, node.sref(), &key));
+                }
+            };
         }
-        node.set_red_color();
-        self.insert_fixup(node);
-        node_sref
+        let node = self
+            .new_node(key, link, tree_acc, payer, system_program)
+            .map_err(|e| {
+                e.during(Operation::Insert, e.alloc_phase(), parent.sref(), &key)
+                    .with_stats(self.arena_stats::<T>(tree_acc.data_len()))
+            })?;
+        self.link(parent, node);
+        self.touch();
+        Ok(node.sref())
     }
     #[inline]
     pub fn get_root<T>(&self) -> NodePtr<T> {
-        unsafe {
-            if *self.root == NULL_NODE {
-                return NodePtr::null();
-            }
-            let node_ptr = self
-                .entry
-                .offset(*self.root as isize * (std::mem::size_of::<Node<T>>() >> 3) as isize)
-                as *mut Node<T>;
-            NodePtr(node_ptr, self.entry)
+        self.root_node()
+    }
+    pub fn min_key<T: Copy>(&self) -> Option<T> {
+        self.first().map(|(key, _)| key)
+    }
+    pub fn max_key<T: Copy>(&self) -> Option<T> {
+        self.last().map(|(key, _)| key)
+    }
+    /// Smallest key and its link, or `None` for an empty tree: the best ask
+    /// of an ask book.
+    pub fn first<T: Copy>(&self) -> Option<(T, u32)> {
+        let root = self.get_root::<T>();
+        if root.is_null() {
+            return None;
         }
+        let node = root.min_node();
+        Some((node.key(), node.link()))
     }
-    pub fn find_node<T: Copy + Ord + std::fmt::Display>(&self, key: T) -> NodePtr<T> {
-        if self.get_root_sref() == NULL_NODE {
-            return NodePtr::null();
+    /// Largest key and its link, or `None` for an empty tree: the best bid
+    /// of a bid book.
+    pub fn last<T: Copy>(&self) -> Option<(T, u32)> {
+        let root = self.get_root::<T>();
+        if root.is_null() {
+            return None;
         }
-        let mut temp = self.get_root();
-        loop {
-            let next = match key.cmp(&temp.key()) {
-                Ordering::Less => temp.left(),
-                Ordering::Greater => temp.right(),
-                Ordering::Equal => {
-                    return temp;
-                }
-            };
-            if next.is_null() {
-                break;
+        let node = root.max_node();
+        Some((node.key(), node.link()))
+    }
+    /// Same as [`RBTree::first`].
+    pub fn min_entry<T: Copy>(&self) -> Option<(T, u32)> {
+        self.first()
+    }
+    /// Same as [`RBTree::last`].
+    pub fn max_entry<T: Copy>(&self) -> Option<(T, u32)> {
+        self.last()
+    }
+    /// Best resting order in a `side` book that a taker with `limit` can
+    /// trade against: the lowest ask at or below `limit`, or the highest bid
+    /// at or above it. Returns a null pointer if the best price does not reach
+    /// the limit or the tree is empty.
+    pub fn best_price_at_or_better<T: Copy + PartialOrd>(
+        &self,
+        limit: T,
+        side: Side,
+    ) -> NodePtr<T> {
+        let root = self.get_root::<T>();
+        if root.is_null() {
+            return root;
+        }
+        let (best, reaches) = match side {
+            Side::Ask => {
+                let best = root.min_node();
+                (best, best.key() <= limit)
             }
-            temp = next;
+            Side::Bid => {
+                let best = root.max_node();
+                (best, best.key() >= limit)
+            }
+        };
+        if reaches {
+            best
+        } else {
+            NodePtr::null()
         }
-        NodePtr::null()
+    }
+    pub fn find_node<T: Copy + Ord + std::fmt::Display>(&self, key: T) -> NodePtr<T> {
+        self.search(key)
+    }
+    /// Node stored at `sref`, or `None` unless the slot is allocated, lies in
+    /// the node region and holds a node claiming that sref. Use this for srefs
+    /// kept outside the tree, which may be stale.
+    pub fn node<T>(&self, sref: u32) -> Option<NodePtr<T>> {
+        let node = self.live_node(sref);
+        (!node.is_null()).then_some(node)
+    }
+    /// Node following the one stored at `sref` in key order. Returns a null
+    /// pointer if `sref` is not a live node or it holds the largest key.
+    pub fn next_after_sref<T>(&self, sref: u32) -> NodePtr<T> {
+        self.live_node::<T, u32>(sref).next()
+    }
+    /// Node preceding the one stored at `sref` in key order. Returns a null
+    /// pointer if `sref` is not a live node or it holds the smallest key.
+    pub fn prev_before_sref<T>(&self, sref: u32) -> NodePtr<T> {
+        self.live_node::<T, u32>(sref).prev()
+    }
+    /// First node whose key is greater than `key`, which need not be in the
+    /// tree. Returns a null pointer if there is none.
+    pub fn successor<T: Copy + Ord>(&self, key: T) -> NodePtr<T> {
+        self.lower_bound_node(Bound::Excluded(&key))
+    }
+    /// Last node whose key is less than `key`, which need not be in the tree.
+    /// Returns a null pointer if there is none.
+    pub fn predecessor<T: Copy + Ord>(&self, key: T) -> NodePtr<T> {
+        self.upper_bound_node(Bound::Excluded(&key))
+    }
+    /// Last node whose key is at most `key`, or a null pointer if every key is
+    /// greater.
+    pub fn floor<T: Copy + Ord>(&self, key: T) -> NodePtr<T> {
+        self.upper_bound_node(Bound::Included(&key))
+    }
+    /// First node whose key is at least `key`, or a null pointer if every key
+    /// is smaller.
+    pub fn ceiling<T: Copy + Ord>(&self, key: T) -> NodePtr<T> {
+        self.lower_bound_node(Bound::Included(&key))
+    }
+    /// Groups nodes into price levels by `level_of(key)` and returns up to `n`
+    /// levels from the smallest key upwards as `(level, count, link)`, where
+    /// `link` belongs to the first node of the level. Nodes are visited once,
+    /// in key order, and the walk stops as soon as `n` levels are complete.
+    pub fn aggregate_levels<T: Copy, L: PartialEq>(
+        &self,
+        n: usize,
+        level_of: impl FnMut(T) -> L,
+    ) -> Vec<(L, u32, u32)> {
+        let root = self.get_root::<T>();
+        aggregate(root.min_node(), NodePtr::next, n, level_of)
+    }
+    /// Same as [`RBTree::aggregate_levels`], walking from the largest key
+    /// downwards (e.g. for bids).
+    pub fn aggregate_levels_rev<T: Copy, L: PartialEq>(
+        &self,
+        n: usize,
+        level_of: impl FnMut(T) -> L,
+    ) -> Vec<(L, u32, u32)> {
+        let root = self.get_root::<T>();
+        aggregate(root.max_node(), NodePtr::prev, n, level_of)
     }
     pub fn find_new_parent_or_equal<T: Ord + Copy>(&self, key: T) -> (NodePtr<T>, u32) {
         if self.get_root_sref() == NULL_NODE {
@@ -507,136 +975,287 @@ impl RBTree {
             temp = next;
         }
     }
+    /// Deletes `node`, panicking where [`RBTree::try_delete`] fails.
+    #[deprecated(note = "use `RBTree::try_delete`, which returns the error instead of panicking")]
     #[inline]
-    fn delete_fixup<T: Copy>(&self, mut node: NodePtr<T>, mut parent: NodePtr<T>) {
-        let mut other;
-        while node.sref() != self.get_root_sref() && node.is_black_color() {
-            if parent.left() == node {
-                other = parent.right();
-                if other.is_red_color() {
-                    other.set_black_color();
-                    parent.set_red_color();
-                    self.left_rotate(parent);
-                    other = parent.right();
-                }
-                if other.left().is_black_color() && other.right().is_black_color() {
-                    other.set_red_color();
-                    node = parent;
-                    parent = node.parent();
-                } else {
-                    if other.right().is_black_color() {
-                        other.left().set_black_color();
-                        other.set_red_color();
-                        self.right_rotate(other);
-                        other = parent.right();
+    pub fn delete<T: Copy, V>(&mut self, node: NodePtr<T, V>) {
+        if let Err(error) = self.try_delete(node) {
+            panic!("delete failed: {error}");
+        }
+    }
+    /// Deletes `node` and frees its slot. Fails without changing the tree if
+    /// the header does not match this handle, the allocator does not hold
+    /// the node's slot, or the node's parent and children do not link back
+    /// to it (reported during [`Phase::Fixup`]).
+    pub fn try_delete<T: Copy, V>(&mut self, node: NodePtr<T, V>) -> Result<(), OpError> {
+        let sref = node.sref();
+        let failed = |error: TreeError, phase| error.at(Operation::Delete, phase, sref);
+        self.check_header()
+            .map_err(|e| failed(e, Phase::Validate))?;
+        if !self.pt.is_allocated(sref as usize) {
+            return Err(failed(TreeError::DeallocFailed { sref }, Phase::Validate));
+        }
+        self.check_links(node)
+            .map_err(|e| failed(e, Phase::Fixup))?;
+        self.unlink(node);
+        self.pt
+            .dealloc(sref as usize)
+            .map_err(|_| failed(TreeError::DeallocFailed { sref }, Phase::Alloc))?;
+        self.count_node(false);
+        self.touch();
+        Ok(())
+    }
+
+    /// Removes a node holding `key` and returns its link, or `NULL_NODE` if
+    /// there is none or it could not be removed; see [`RBTree::try_remove`]
+    /// for the reason. A tree whose links can take that value should use
+    /// [`RBTree::remove_entry`].
+    #[deprecated(
+        note = "use `RBTree::remove_entry` or `RBTree::try_remove`, which return the error instead of `NULL_NODE`"
+    )]
+    pub fn remove<T: Copy + Ord + std::fmt::Display>(&mut self, key: T) -> u32 {
+        self.remove_entry(key)
+            .ok()
+            .flatten()
+            .map_or(NULL_NODE, |removed| removed.link)
+    }
+
+    /// Removes a node holding `key` and returns its key, link and the sref it
+    /// occupied, or `None` if there is none. Fails as [`RBTree::try_delete`]
+    /// does.
+    pub fn remove_entry<T: Copy + Ord>(&mut self, key: T) -> Result<Option<Removed<T>>, TreeError> {
+        let node = self.search::<T, u32>(key);
+        self.take(node)
+    }
+
+    /// Like [`RBTree::remove_entry`], reporting a missing key as
+    /// [`TreeError::KeyNotFound`] and failing as [`RBTree::try_delete`] does
+    /// instead of panicking. Errors carry the key.
+    pub fn try_remove<T: Copy + Ord + NoUninit>(&mut self, key: T) -> Result<Removed<T>, OpError> {
+        let node = self.search::<T, u32>(key);
+        if node.is_null() {
+            let error = TreeError::KeyNotFound;
+            return Err(error.during(Operation::Remove, Phase::Search, NULL_NODE, &key));
+        }
+        let removed = Removed {
+            key: node.key(),
+            link: node.link(),
+            sref: node.sref(),
+        };
+        self.try_delete(node).map_err(|e| {
+            e.error
+                .during(Operation::Remove, e.context.phase, e.context.sref, &key)
+        })?;
+        Ok(removed)
+    }
+
+    /// Checks that the parent and children of `node` link back to it.
+    pub(crate) fn check_links<T: Copy, V>(&self, node: NodePtr<T, V>) -> Result<(), TreeError> {
+        let sref = node.sref();
+        let parent = node.parent();
+        let linked = if parent.is_null() {
+            self.get_root_sref() == sref
+        } else {
+            parent.left() == node || parent.right() == node
+        };
+        let children = [node.left(), node.right()]
+            .iter()
+            .all(|child| child.is_null() || child.parent() == node);
+        if !linked || !children {
+            return Err(TreeError::Corrupted { sref });
+        }
+        Ok(())
+    }
+
+    /// Removes the node stored at `sref`, e.g. one kept in an external order
+    /// table, and returns it, or `None` if `sref` is not a live node (see
+    /// [`RBTree::node`]). A slot freed and handed to a newer node since the
+    /// sref was stored holds that node, so tables must drop srefs they
+    /// removed. Fails as [`RBTree::try_delete`] does.
+    pub fn delete_by_sref<T: Copy>(&mut self, sref: u32) -> Result<Option<Removed<T>>, TreeError> {
+        let node = self.live_node::<T, u32>(sref);
+        self.take(node)
+    }
+
+    /// Deletes `node` unless it is null and returns what it held.
+    pub(crate) fn take<T: Copy>(
+        &mut self,
+        node: NodePtr<T>,
+    ) -> Result<Option<Removed<T>>, TreeError> {
+        if node.is_null() {
+            return Ok(None);
+        }
+        let removed = Removed {
+            key: node.key(),
+            link: node.link(),
+            sref: node.sref(),
+        };
+        self.try_delete(node)?;
+        Ok(Some(removed))
+    }
+
+    /// Removes the node with the smallest key and returns its key and link,
+    /// e.g. to take the best ask while matching. Fails as
+    /// [`RBTree::try_delete`] does.
+    pub fn pop_first<T: Copy>(&mut self) -> Result<Option<(T, u32)>, TreeError> {
+        let node = self.get_root::<T>().min_node();
+        self.pop(node)
+    }
+    /// Removes the node with the largest key and returns its key and link.
+    /// Fails as [`RBTree::try_delete`] does.
+    pub fn pop_last<T: Copy>(&mut self) -> Result<Option<(T, u32)>, TreeError> {
+        let node = self.get_root::<T>().max_node();
+        self.pop(node)
+    }
+    fn pop<T: Copy>(&mut self, node: NodePtr<T>) -> Result<Option<(T, u32)>, TreeError> {
+        Ok(self.take(node)?.map(|removed| (removed.key, removed.link)))
+    }
+
+    /// Removes one node for every key in `keys` and returns the removed links in
+    /// the same order as `keys` (`NULL_NODE` for keys that were not found). A
+    /// key given `n` times removes `n` nodes holding it, as many as the tree
+    /// holds with duplicate keys; the repeats past those get `NULL_NODE`.
+    ///
+    /// All nodes are located first, searching the keys in ascending order
+    /// from the node found last so that consecutive searches share the upper
+    /// part of their paths, and then deleted in ascending sref order, so the
+    /// fixup writes walk the account data front to back instead of following
+    /// the caller's (usually random) key order. `delete` relinks nodes instead
+    /// of moving keys between slots, so the located nodes stay valid for the
+    /// whole batch.
+    ///
+    /// The deletes dominate, so the batch reads only a few nodes fewer than
+    /// removing the keys one by one (see `bench::trace_access`). When srefs
+    /// follow key order, as in trees built with
+    /// [`RBTree::build_from_sorted`], it reads nodes less than half as far
+    /// apart.
+    ///
+    /// Stops at the first delete that fails as [`RBTree::try_delete`] does;
+    /// the nodes deleted before it stay deleted.
+    pub fn remove_many<T: Copy + Ord>(&mut self, keys: &[T]) -> Result<Vec<u32>, TreeError> {
+        let mut links = vec![NULL_NODE; keys.len()];
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by_key(|&i| keys[i]);
+        let mut nodes = Vec::with_capacity(keys.len());
+        let mut finger = NodePtr::null();
+        // The first and last of the nodes taken so far for the current key,
+        // adjacent in key order; repeats of the key extend the run.
+        let mut run: Option<(T, NodePtr<T>, NodePtr<T>)> = None;
+        for i in order {
+            let key = keys[i];
+            let node = match run {
+                Some((last, first, _)) if last == key && first.is_null() => continue,
+                Some((last, first, end)) if last == key => {
+                    let (next, prev) = (end.next(), first.prev());
+                    if !next.is_null() && next.key() == key {
+                        run = Some((key, first, next));
+                        next
+                    } else if !prev.is_null() && prev.key() == key {
+                        run = Some((key, prev, end));
+                        prev
+                    } else {
+                        continue;
                     }
-                    other.set_color(parent.get_color());
-                    parent.set_black_color();
-                    other.right().set_black_color();
-                    self.left_rotate(parent);
-                    node = self.get_root();
-                    break;
-                }
-            } else {
-                other = parent.left();
-                if other.is_red_color() {
-                    other.set_black_color();
-                    parent.set_red_color();
-                    self.right_rotate(parent);
-                    other = parent.left();
                 }
-                if other.left().is_black_color() && other.right().is_black_color() {
-                    other.set_red_color();
-                    node = parent;
-                    parent = node.parent();
-                } else {
-                    if other.left().is_black_color() {
-                        other.right().set_black_color();
-                        other.set_red_color();
-                        self.left_rotate(other);
-                        other = parent.left();
-                    }
-                    other.set_color(parent.get_color());
-                    parent.set_black_color();
-                    other.left().set_black_color();
-                    self.right_rotate(parent);
-                    node = self.get_root();
-                    break;
+                _ => {
+                    let node = self.search_from(finger, key);
+                    run = Some((key, node, node));
+                    node
                 }
+            };
+            if !node.is_null() {
+                finger = node;
+                nodes.push((node.sref(), i, node));
             }
         }
-        node.set_black_color();
+        nodes.sort_unstable_by_key(|&(sref, _, _)| sref);
+        for (_, i, node) in nodes {
+            links[i] = node.link();
+            self.try_delete(node)?;
+        }
+        Ok(links)
     }
-    #[inline]
-    pub fn delete<T: Copy>(&mut self, node: NodePtr<T>) {
-        let mut child;
-        let mut parent;
-        let color;
-        if !node.left().is_null() && !node.right().is_null() {
-            let mut replace = node.right().min_node();
-            if node.sref() == self.get_root_sref() {
-                self.set_root_sref(replace.sref());
-            } else if node.parent().left() == node {
-                node.parent().set_left(replace);
-            } else {
-                node.parent().set_right(replace);
-            }
 
-            child = replace.right();
-            parent = replace.parent();
-            color = replace.get_color();
-            if parent == node {
-                parent = replace;
-            } else {
-                if !child.is_null() {
-                    child.set_parent(parent);
+    /// Node holding `key`, searched from `finger`, a node whose key is at
+    /// most `key`, or from the root if it is null. The search climbs to the
+    /// lowest ancestor whose subtree spans `key` and descends from there, so
+    /// searches for ascending keys share the upper part of their paths.
+    fn search_from<T: Copy + Ord>(&self, finger: NodePtr<T>, key: T) -> NodePtr<T> {
+        let mut top = finger;
+        let mut above = NodePtr::null();
+        if top.is_null() {
+            top = self.get_root();
+        } else {
+            // Stops at the first ancestor with a key of at least `key`; the
+            // subtree below it holds every node up to it in key order.
+            loop {
+                let parent = top.parent();
+                if parent.is_null() || parent.key() >= key {
+                    above = parent;
+                    break;
                 }
-                parent.set_left(child);
-                replace.set_right(node.right());
-                node.right().set_parent(replace);
-            }
-            replace.set_parent(node.parent());
-            replace.set_color(node.get_color());
-            replace.set_left(node.left());
-            node.left().set_parent(replace);
-            if color == 0 {
-                self.delete_fixup(child, parent);
+                top = parent;
             }
-            self.pt.dealloc(node.sref() as usize).unwrap();
-            return;
         }
-        if !node.left().is_null() {
-            child = node.left();
-        } else {
-            child = node.right();
-        }
-        parent = node.parent();
-        color = node.get_color();
-        if !child.is_null() {
-            child.set_parent(parent);
+        let mut node = top;
+        while !node.is_null() {
+            node = match key.cmp(&node.key()) {
+                Ordering::Less => node.left(),
+                Ordering::Greater => node.right(),
+                Ordering::Equal => return node,
+            };
         }
-        if self.get_root_sref() == node.sref() {
-            self.set_root_sref(child.sref())
-        } else if parent.left() == node {
-            parent.set_left(child);
+        // The next node in key order after that subtree.
+        if !above.is_null() && above.key() == key {
+            above
         } else {
-            parent.set_right(child);
+            NodePtr::null()
         }
+    }
 
-        if color == 0 {
-            self.delete_fixup(child, parent);
+    /// Removes every node whose key falls within `range`, handing each to
+    /// `removed` in ascending key order, and returns how many there were,
+    /// e.g. to expire a band of price levels. The range is searched once: the
+    /// walk goes on from the successor of each removed node. Stops at the
+    /// first delete that fails as [`RBTree::try_delete`] does.
+    pub fn remove_range<T: Copy + Ord, R: RangeBounds<T>>(
+        &mut self,
+        range: R,
+        mut removed: impl FnMut(Removed<T>),
+    ) -> Result<usize, TreeError> {
+        let mut node = self.lower_bound_node::<T, u32>(range.start_bound());
+        let mut count = 0;
+        while !node.is_null() && range.contains(&node.key()) {
+            let next = node.next();
+            if let Some(entry) = self.take(node)? {
+                removed(entry);
+                count += 1;
+            }
+            node = next;
         }
-        self.pt.dealloc(node.sref() as usize).unwrap();
+        Ok(count)
     }
+}
 
-    pub fn remove<T: Copy + Ord + std::fmt::Display>(&mut self, key: T) -> u32 {
-        let node = self.find_node(key);
-        if node.is_null() {
-            return NULL_NODE;
+fn aggregate<T: Copy, L: PartialEq>(
+    mut node: NodePtr<T>,
+    step: fn(NodePtr<T>) -> NodePtr<T>,
+    n: usize,
+    mut level_of: impl FnMut(T) -> L,
+) -> Vec<(L, u32, u32)> {
+    let mut levels: Vec<(L, u32, u32)> = Vec::with_capacity(n);
+    while !node.is_null() {
+        let level = level_of(node.key());
+        match levels.last_mut() {
+            Some((last, count, _)) if *last == level => *count += 1,
+            _ => {
+                if levels.len() == n {
+                    break;
+                }
+                levels.push((level, 1, node.link()));
+            }
         }
-        let link = node.link();
-        self.delete(node);
-
-        link
+        node = step(node);
     }
+    levels
 }
@@ -0,0 +1,105 @@
+use crate::{NodePtr, NULL_ORDER};
+use std::fmt;
+
+/// Node link with checked conversions to and from the external indices it
+/// usually holds.
+///
+/// [`Link::NULL`] is the `NULL_ORDER` sentinel that [`NodePtr::link`] returns
+/// for a null pointer; conversions into indices refuse it instead of turning it
+/// into a valid-looking order index.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Link(pub u32);
+
+impl Link {
+    pub const NULL: Self = Self(NULL_ORDER);
+
+    pub const fn is_null(self) -> bool {
+        self.0 == NULL_ORDER
+    }
+}
+
+impl From<u32> for Link {
+    fn from(link: u32) -> Self {
+        Self(link)
+    }
+}
+
+impl From<Link> for u32 {
+    fn from(link: Link) -> Self {
+        link.0
+    }
+}
+
+/// Fails for `NULL_ORDER`, which is not a valid order index.
+impl TryFrom<u16> for Link {
+    type Error = LinkConversionError;
+
+    fn try_from(index: u16) -> Result<Self, Self::Error> {
+        let link = Self(index as u32);
+        if link.is_null() {
+            return Err(LinkConversionError);
+        }
+        Ok(link)
+    }
+}
+
+/// Fails for the sentinel and for links above `u16::MAX`.
+impl TryFrom<Link> for u16 {
+    type Error = LinkConversionError;
+
+    fn try_from(link: Link) -> Result<Self, Self::Error> {
+        if link.is_null() {
+            return Err(LinkConversionError);
+        }
+        u16::try_from(link.0).map_err(|_| LinkConversionError)
+    }
+}
+
+/// Fails for the sentinel and for indices that do not fit in a `u32`.
+impl TryFrom<usize> for Link {
+    type Error = LinkConversionError;
+
+    fn try_from(index: usize) -> Result<Self, Self::Error> {
+        let link = Self(u32::try_from(index).map_err(|_| LinkConversionError)?);
+        if link.is_null() {
+            return Err(LinkConversionError);
+        }
+        Ok(link)
+    }
+}
+
+/// Fails for the sentinel.
+impl TryFrom<Link> for usize {
+    type Error = LinkConversionError;
+
+    fn try_from(link: Link) -> Result<Self, Self::Error> {
+        if link.is_null() {
+            return Err(LinkConversionError);
+        }
+        Ok(link.0 as usize)
+    }
+}
+
+/// A link or index was the `NULL_ORDER` sentinel or out of range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LinkConversionError;
+
+impl fmt::Display for LinkConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "link is the null sentinel or out of range")
+    }
+}
+
+impl std::error::Error for LinkConversionError {}
+
+impl<T> NodePtr<T> {
+    /// Link of the node, or `None` for a null pointer instead of the
+    /// `NULL_ORDER` sentinel returned by [`NodePtr::link`].
+    pub fn try_link(&self) -> Option<Link> {
+        if self.is_null() {
+            return None;
+        }
+        Some(Link(self.link()))
+    }
+}
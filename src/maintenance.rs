@@ -0,0 +1,172 @@
+use crate::{
+    region_bytes, region_slots, slot, storage::Storage, NodePtr, OpError, Operation, Phase, RBTree,
+    TreeError, NULL_NODE, UNBOUNDED_REGION,
+};
+use solana_program::{account_info::AccountInfo, sysvar::rent::Rent};
+use std::ptr;
+
+/// Progress reported by [`RBTree::compact_step`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Compaction {
+    /// `(from, to)` srefs of the nodes moved, in order. Anything holding srefs
+    /// outside the tree must be remapped.
+    pub moves: Vec<(u32, u32)>,
+    /// Whether the allocator has no free slot below the last allocated one.
+    pub done: bool,
+}
+
+impl RBTree {
+    /// Removes up to `max` nodes whose record is older than `threshold`, as
+    /// reported by `slot_of` for the node's link (e.g. the slot an order was
+    /// placed in). Returns the links of the removed nodes in key order.
+    ///
+    /// Meant for a maintenance crank expiring stale quotes: the bound keeps
+    /// each call within the compute budget, and repeated calls drain the
+    /// backlog. Nodes are scanned in key order and deleted in sref order, as
+    /// in [`RBTree::remove_many`], stopping at the first delete that fails as
+    /// [`RBTree::try_delete`] does.
+    pub fn prune_links_below<T: Copy>(
+        &mut self,
+        threshold: u64,
+        mut slot_of: impl FnMut(u32) -> u64,
+        max: usize,
+    ) -> Result<Vec<u32>, TreeError> {
+        let mut nodes: Vec<NodePtr<T>> = Vec::new();
+        let mut node = self.get_root::<T>().min_node();
+        while !node.is_null() && nodes.len() < max {
+            if slot_of(node.link()) < threshold {
+                nodes.push(node);
+            }
+            node = node.next();
+        }
+        let links = nodes.iter().map(|node| node.link()).collect();
+        nodes.sort_unstable_by_key(|node| node.sref());
+        for node in nodes {
+            self.try_delete(node)?;
+        }
+        Ok(links)
+    }
+
+    /// Moves up to `max_moves` nodes from the highest allocated slots into the
+    /// lowest free ones, so a permissionless crank can defragment a large tree
+    /// a little per instruction until [`Compaction::done`]. Keys, links and
+    /// the tree shape are unchanged; only srefs change.
+    ///
+    /// Needs a bounded `region_len` to find the last slot.
+    pub fn compact_step<T: Copy>(&mut self, max_moves: usize) -> Result<Compaction, OpError> {
+        let failed = |error: TreeError, phase, sref| error.at(Operation::Compact, phase, sref);
+        self.check_header()
+            .map_err(|e| failed(e, Phase::Validate, NULL_NODE))?;
+        let region_len = self.region_len.get();
+        if region_len == UNBOUNDED_REGION {
+            return Err(failed(TreeError::InvalidLayout, Phase::Validate, NULL_NODE));
+        }
+        let mut high = region_slots::<T, u32>(region_len, self.stride::<T, u32>()) as u32;
+        let mut compaction = Compaction::default();
+        while compaction.moves.len() < max_moves {
+            while high > 0 && !self.pt.is_allocated(high as usize - 1) {
+                high -= 1;
+            }
+            let Some(from) = high.checked_sub(1) else {
+                compaction.done = true;
+                break;
+            };
+            let Ok(to) = self.pt.alloc() else {
+                compaction.done = true;
+                break;
+            };
+            let to = to as u32;
+            let node = self.live_node::<T, u32>(from);
+            if to > from || node.is_null() {
+                // Nothing free below the last node, or the slot does not hold
+                // a node that could be moved.
+                self.pt
+                    .dealloc(to as usize)
+                    .map_err(|_| failed(TreeError::DeallocFailed { sref: to }, Phase::Alloc, to))?;
+                compaction.done = to > from;
+                break;
+            }
+            self.relocate(node, to);
+            self.pt
+                .dealloc(from as usize)
+                .map_err(|_| failed(TreeError::DeallocFailed { sref: from }, Phase::Alloc, from))?;
+            compaction.moves.push((from, to));
+            high = from;
+        }
+        if !compaction.moves.is_empty() {
+            self.touch();
+        }
+        Ok(compaction)
+    }
+
+    /// Reallocates `tree_acc` down to the last allocated slot and moves the
+    /// lamports above the rent-exempt minimum of the new size to `recipient`,
+    /// returning how many. Run [`RBTree::compact_step`] to
+    /// [`Compaction::done`] first to shrink past free slots in the middle.
+    /// Slots of other trees sharing the allocator count as allocated.
+    ///
+    /// Needs a bounded `region_len` to find the last slot, and `tree_acc` to
+    /// be owned by the calling program so its lamports can be debited.
+    pub fn shrink_to_fit<T>(
+        &self,
+        tree_acc: &AccountInfo,
+        recipient: &AccountInfo,
+    ) -> Result<u64, TreeError> {
+        self.check_header()?;
+        let region_len = self.region_len.get();
+        if region_len == UNBOUNDED_REGION {
+            return Err(TreeError::InvalidLayout);
+        }
+        let stride = self.stride::<T, u32>();
+        let mut high = region_slots::<T, u32>(region_len, stride);
+        while high > 0 && !self.pt.is_allocated(high - 1) {
+            high -= 1;
+        }
+        let region_start = self.region_offset();
+        let len = region_start + region_bytes::<T, u32>(high, stride);
+        if len < tree_acc.data_len() {
+            tree_acc
+                .realloc(len, false)
+                .map_err(|_| TreeError::ReallocFailed)?;
+            self.region_len.set(len - region_start);
+        }
+        let excess = tree_acc
+            .lamports()
+            .saturating_sub(Rent::default().minimum_balance(tree_acc.data_len()));
+        if excess > 0 {
+            let mut from = tree_acc
+                .try_borrow_mut_lamports()
+                .map_err(|_| TreeError::RentTransferFailed)?;
+            let mut to = recipient
+                .try_borrow_mut_lamports()
+                .map_err(|_| TreeError::RentTransferFailed)?;
+            **from -= excess;
+            **to += excess;
+        }
+        Ok(excess)
+    }
+
+    /// Copies `node` into the free slot `to` and points its parent (or the
+    /// root) and children at the copy.
+    fn relocate<T: Copy>(&self, node: NodePtr<T>, to: u32) {
+        let from = node.sref();
+        let moved = unsafe {
+            let target = slot::<T, u32>(self.entry, to, node.3);
+            // Adjacent slots overlap at the v0.1.3 stride.
+            ptr::copy(node.0, target, 1);
+            (*target).set_sref(to);
+            NodePtr(target, self.entry, self.region_len.get(), node.3)
+        };
+        let parent = moved.parent();
+        if parent.is_null() {
+            self.set_root_sref(to);
+        } else if unsafe { (*parent.0).left_sref() } == from {
+            parent.set_left(moved);
+        } else {
+            parent.set_right(moved);
+        }
+        for mut child in [moved.left(), moved.right()] {
+            child.set_parent(moved);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{
+    storage::Storage, Iter, NodePtr, OpError, Operation, Payer, Phase, RBTree, TreeError, NULL_NODE,
+};
+use bytemuck::NoUninit;
+use solana_program::account_info::AccountInfo;
+use std::{cmp::Ordering, marker::PhantomData, ops::RangeBounds};
+
+/// Ordered map with unique keys over a tree storing each value inline
+/// (`Node<K, V>`), read and written like `std::collections::BTreeMap`. It
+/// holds the accounts an insert may need to grow the tree, so call sites deal
+/// in keys and values only.
+///
+/// With `V = u32` the layout is that of a tree with links, so the map can
+/// open any such tree whose keys are unique.
+pub struct RBTreeMap<'a, 'info, K, V> {
+    tree: RBTree,
+    tree_acc: &'a AccountInfo<'info>,
+    payer: Payer<'a, 'info>,
+    system_program: &'a AccountInfo<'info>,
+    _entry: PhantomData<(K, V)>,
+}
+
+impl<'a, 'info, K: Copy + Ord, V: Copy> RBTreeMap<'a, 'info, K, V> {
+    /// Opens the map over `tree`, stored in `tree_acc`. Inserts that grow the
+    /// account are paid for by `payer` through `system_program`.
+    pub fn new(
+        tree: RBTree,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Self {
+        Self {
+            tree,
+            tree_acc,
+            payer: payer.into(),
+            system_program,
+            _entry: PhantomData,
+        }
+    }
+
+    pub fn tree(&self) -> &RBTree {
+        &self.tree
+    }
+
+    pub fn into_tree(self) -> RBTree {
+        self.tree
+    }
+
+    /// Number of entries, in O(1) when the header records this tree's root;
+    /// see [`RBTree::len`].
+    pub fn len(&self) -> usize {
+        self.tree
+            .recorded_len()
+            .unwrap_or_else(|| self.iter().count())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Stores `value` under `key`, returning the value it replaces. Unlike
+    /// `BTreeMap::insert` it can fail, when the key is rejected by the header
+    /// or the account cannot grow to hold a new node.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, OpError>
+    where
+        K: NoUninit,
+    {
+        self.tree
+            .check_header()
+            .map_err(|e| e.during(Operation::Insert, Phase::Validate, NULL_NODE, &key))?;
+        let mut parent = NodePtr::<K, V>::null();
+        let mut node = self.tree.root_node::<K, V>();
+        while !node.is_null() {
+            parent = node;
+            node = match key.cmp(&node.key()) {
+                Ordering::Less => node.left(),
+                Ordering::Greater => node.right(),
+                Ordering::Equal => {
+                    let old = node.value();
+                    node.set_value(value);
+                    self.tree.touch();
+                    return Ok(Some(old));
+                }
+            };
+        }
+        let node = self
+            .tree
+            .new_node(key, value, self.tree_acc, self.payer, self.system_program)
+            .map_err(|e| {
+                e.during(Operation::Insert, e.alloc_phase(), parent.sref(), &key)
+                    .with_stats(self.tree.arena_stats_of::<K, V>(self.tree_acc.data_len()))
+            })?;
+        self.tree.link(parent, node);
+        self.tree.touch();
+        Ok(None)
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let node = self.tree.search::<K, V>(*key);
+        (!node.is_null()).then(|| node.value())
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        !self.tree.search::<K, V>(*key).is_null()
+    }
+
+    /// Removes `key`, returning its value if it was present. Fails as
+    /// [`RBTree::try_delete`] does.
+    pub fn remove(&mut self, key: &K) -> Result<Option<V>, TreeError> {
+        let node = self.tree.search::<K, V>(*key);
+        if node.is_null() {
+            return Ok(None);
+        }
+        let value = node.value();
+        self.tree.try_delete(node)?;
+        Ok(Some(value))
+    }
+
+    /// Entries in ascending key order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.tree.range_of::<K, V, _>(..)
+    }
+
+    /// Entries whose keys fall within `range`, in ascending order.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Iter<'_, K, V> {
+        self.tree.range_of::<K, V, R>(range)
+    }
+
+    pub fn first_key_value(&self) -> Option<(K, V)> {
+        self.iter().next()
+    }
+
+    pub fn last_key_value(&self) -> Option<(K, V)> {
+        self.iter().next_back()
+    }
+}
@@ -0,0 +1,53 @@
+//! Trees holding several nodes per key. [`RBTree::insert`] places a key after
+//! the nodes already holding it and rotations keep the in-order sequence, so
+//! the nodes of one key always read in insertion order: each key is a FIFO
+//! queue, as price-time priority needs.
+
+use crate::{Iter, NodePtr, RBTree, TreeError};
+use std::ops::Bound;
+
+impl RBTree {
+    /// Oldest node holding `key`, the head of its queue. Returns a null
+    /// pointer if there is none.
+    pub fn find_first<T: Copy + Ord>(&self, key: T) -> NodePtr<T> {
+        let node = self.lower_bound_node(Bound::Included(&key));
+        if node.is_null() || node.key() != key {
+            return NodePtr::null();
+        }
+        node
+    }
+
+    /// Newest node holding `key`, the tail of its queue. Returns a null
+    /// pointer if there is none.
+    pub fn find_last<T: Copy + Ord>(&self, key: T) -> NodePtr<T> {
+        let node = self.upper_bound_node(Bound::Included(&key));
+        if node.is_null() || node.key() != key {
+            return NodePtr::null();
+        }
+        node
+    }
+
+    /// Every `(key, link)` stored under `key`, oldest first.
+    pub fn find_all<T: Copy + Ord>(&self, key: T) -> Iter<'_, T> {
+        self.range(key..=key)
+    }
+
+    /// Number of nodes holding `key`.
+    pub fn count_key<T: Copy + Ord>(&self, key: T) -> usize {
+        self.find_all(key).count()
+    }
+
+    /// Removes the oldest node holding `key` and returns its link, or `None`
+    /// if there is none. Unlike [`RBTree::remove_entry`], which takes
+    /// whichever equal node the search meets, this drains a key in FIFO
+    /// order. Fails as [`RBTree::try_delete`] does.
+    pub fn remove_first<T: Copy + Ord>(&mut self, key: T) -> Result<Option<u32>, TreeError> {
+        let node = self.find_first(key);
+        if node.is_null() {
+            return Ok(None);
+        }
+        let link = node.link();
+        self.try_delete(node)?;
+        Ok(Some(link))
+    }
+}
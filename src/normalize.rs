@@ -0,0 +1,97 @@
+use crate::{
+    storage::Storage, NodePtr, OpError, Operation, Payer, Phase, RBTree, TreeError, NULL_NODE,
+};
+use bytemuck::NoUninit;
+use solana_program::account_info::AccountInfo;
+
+/// Maps a caller-supplied key to the key stored in the tree, or rejects it.
+/// Passing the same normalizer to [`RBTree::insert_normalized`] and
+/// [`RBTree::find_normalized`] keeps keys that only differ below the tick size
+/// from ending up as separate, unmatchable entries.
+pub trait KeyNormalizer<T> {
+    fn normalize(&self, key: T) -> Result<T, TreeError>;
+}
+
+/// Closures returning `None` reject the key with [`TreeError::NonConformingKey`].
+impl<T, F: Fn(T) -> Option<T>> KeyNormalizer<T> for F {
+    fn normalize(&self, key: T) -> Result<T, TreeError> {
+        self(key).ok_or(TreeError::NonConformingKey)
+    }
+}
+
+/// How [`TickSize`] treats keys between two ticks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    /// Reject them.
+    Exact,
+    /// Round towards zero, e.g. for bids.
+    Down,
+    /// Round away from zero, e.g. for asks.
+    Up,
+}
+
+/// Normalizes `u64` price keys to multiples of `tick`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TickSize {
+    pub tick: u64,
+    pub rounding: Rounding,
+}
+
+impl TickSize {
+    pub const fn new(tick: u64, rounding: Rounding) -> Self {
+        Self { tick, rounding }
+    }
+}
+
+impl KeyNormalizer<u64> for TickSize {
+    fn normalize(&self, key: u64) -> Result<u64, TreeError> {
+        if self.tick == 0 {
+            return Err(TreeError::NonConformingKey);
+        }
+        let rem = key % self.tick;
+        match self.rounding {
+            _ if rem == 0 => Ok(key),
+            Rounding::Exact => Err(TreeError::NonConformingKey),
+            Rounding::Down => Ok(key - rem),
+            Rounding::Up => key
+                .checked_add(self.tick - rem)
+                .ok_or(TreeError::NonConformingKey),
+        }
+    }
+}
+
+impl RBTree {
+    /// [`RBTree::try_insert`] of the normalized key. Returns the sref of the
+    /// new node.
+    pub fn insert_normalized<'info, 'a, T: Copy + PartialOrd + NoUninit, N: KeyNormalizer<T>>(
+        &self,
+        key: T,
+        link: u32,
+        normalizer: &N,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<u32, OpError> {
+        let normalized = normalizer
+            .normalize(key)
+            .and_then(|key| self.check_header().and(self.check_key(&key)).map(|_| key))
+            .map_err(|e| e.during(Operation::Insert, Phase::Validate, NULL_NODE, &key))?;
+        self.try_insert(normalized, link, tree_acc, payer, system_program)
+            .map_err(|e| {
+                e.during(Operation::Insert, e.alloc_phase(), NULL_NODE, &normalized)
+                    .with_stats(self.arena_stats::<T>(tree_acc.data_len()))
+            })
+    }
+
+    /// Node holding the normalized key, searched as [`RBTree::find_node`]
+    /// does but without requiring `T: Display`; null if there is none. Fails
+    /// with the normalizer's error, e.g. [`TreeError::NonConformingKey`], if
+    /// it rejects `key`.
+    pub fn find_normalized<T: Copy + Ord, N: KeyNormalizer<T>>(
+        &self,
+        key: T,
+        normalizer: &N,
+    ) -> Result<NodePtr<T>, TreeError> {
+        normalizer.normalize(key).map(|key| self.search(key))
+    }
+}
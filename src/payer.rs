@@ -0,0 +1,112 @@
+use crate::TreeError;
+use solana_program::{
+    account_info::AccountInfo, program::invoke_signed, system_instruction, sysvar::rent::Rent,
+};
+
+/// Account funding the rent of a growing tree account. It need not be the
+/// account signing the business action: a program can pay from a fee vault
+/// while the order owner only signs.
+///
+/// Every `payer` argument takes `&AccountInfo` as well, meaning
+/// [`Payer::Signer`].
+#[derive(Clone, Copy)]
+pub enum Payer<'a, 'info> {
+    /// A system account signing the transaction, paying through a system
+    /// transfer.
+    Signer(&'a AccountInfo<'info>),
+    /// A system account at a program address, paying through a system
+    /// transfer signed with `seeds` (including the bump).
+    Pda {
+        account: &'a AccountInfo<'info>,
+        seeds: &'a [&'a [u8]],
+    },
+    /// An account owned by the calling program, debited directly without a
+    /// transfer CPI. It is never drawn below its own rent-exempt minimum.
+    ProgramOwned(&'a AccountInfo<'info>),
+    /// Nobody: the tree account already holds the rent for its new size,
+    /// e.g. topped up by a crank, so growing it only reallocates and the
+    /// system program account is not used. Growth it cannot cover fails with
+    /// [`TreeError::NotRentExempt`].
+    PreFunded,
+}
+
+impl<'a, 'info> From<&'a AccountInfo<'info>> for Payer<'a, 'info> {
+    fn from(account: &'a AccountInfo<'info>) -> Self {
+        Self::Signer(account)
+    }
+}
+
+impl<'a, 'info> Payer<'a, 'info> {
+    /// The paying account, `None` when pre-funded.
+    pub fn account(&self) -> Option<&'a AccountInfo<'info>> {
+        match *self {
+            Self::Signer(account) | Self::Pda { account, .. } | Self::ProgramOwned(account) => {
+                Some(account)
+            }
+            Self::PreFunded => None,
+        }
+    }
+
+    /// Lamports the payer can give without failing the transfer.
+    pub fn available(&self) -> u64 {
+        match *self {
+            Self::Signer(account) | Self::Pda { account, .. } => account.lamports(),
+            Self::ProgramOwned(account) => account
+                .lamports()
+                .saturating_sub(Rent::default().minimum_balance(account.data_len())),
+            Self::PreFunded => 0,
+        }
+    }
+
+    /// Moves `lamports` to `tree_acc`. A payer short of them is reported
+    /// before any transfer, which would otherwise abort the transaction.
+    pub(crate) fn fund(
+        &self,
+        lamports: u64,
+        tree_acc: &'a AccountInfo<'info>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<(), TreeError> {
+        let (account, seeds): (_, &[&[u8]]) = match *self {
+            Self::Signer(account) => (account, &[]),
+            Self::Pda { account, seeds } => (account, seeds),
+            Self::ProgramOwned(account) => {
+                self.check_available(lamports)?;
+                let mut from = account
+                    .try_borrow_mut_lamports()
+                    .map_err(|_| TreeError::RentTransferFailed)?;
+                let mut to = tree_acc
+                    .try_borrow_mut_lamports()
+                    .map_err(|_| TreeError::RentTransferFailed)?;
+                **from -= lamports;
+                **to += lamports;
+                return Ok(());
+            }
+            Self::PreFunded => {
+                let balance = tree_acc.lamports();
+                return Err(TreeError::NotRentExempt {
+                    needed: balance + lamports,
+                    balance,
+                });
+            }
+        };
+        self.check_available(lamports)?;
+        let signer_seeds: &[&[&[u8]]] = if seeds.is_empty() { &[] } else { &[seeds] };
+        invoke_signed(
+            &system_instruction::transfer(account.key, tree_acc.key, lamports),
+            &[account.clone(), tree_acc.clone(), system_program.clone()],
+            signer_seeds,
+        )
+        .map_err(|_| TreeError::RentTransferFailed)
+    }
+
+    fn check_available(&self, lamports: u64) -> Result<(), TreeError> {
+        let available = self.available();
+        if available < lamports {
+            return Err(TreeError::InsufficientFunds {
+                needed: lamports,
+                available,
+            });
+        }
+        Ok(())
+    }
+}
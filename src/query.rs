@@ -0,0 +1,295 @@
+//! Read-only queries answered over CPI. A "view program" passes
+//! [`process_view`] its instruction data and the tree account it was given,
+//! and programs that do not own the tree call it with [`invoke_view`] to get
+//! a lookup, the best orders or a key range back as return data.
+//!
+//! A request carries the layout of the account, so one view program serves
+//! trees of any program as long as it is built for their key type. Keys
+//! travel as their in-memory bytes, like they are stored in the nodes.
+
+use crate::{Side, TreeError, TreeView};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    program::{get_return_data, invoke, set_return_data, MAX_RETURN_DATA},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use std::{mem::size_of, ptr};
+
+/// Where the tree lives in the account, as passed to [`TreeView::new`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ViewLayout {
+    pub non_tree_data_size: u32,
+    pub root_offset: u32,
+    pub header_offset: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViewQuery<T> {
+    /// The entry stored under a key.
+    Get(T),
+    /// Up to `limit` entries of a `side` book, best first: asks from the
+    /// lowest key, bids from the highest.
+    Best { side: Side, limit: u16 },
+    /// Up to `limit` entries with a key in `lo..=hi`, in ascending order.
+    Range { lo: T, hi: T, limit: u16 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ViewRequest<T> {
+    pub layout: ViewLayout,
+    pub query: ViewQuery<T>,
+}
+
+/// Answer to a [`ViewRequest`]: `(key, link)` entries in the order of the
+/// query. `truncated` is set when more entries matched than the limit or the
+/// return data could hold.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ViewReply<T> {
+    pub entries: Vec<(T, u32)>,
+    pub truncated: bool,
+}
+
+const NO_HEADER: u32 = u32::MAX;
+/// Key size, truncation flag and entry count.
+const REPLY_PREFIX: usize = size_of::<u16>() + size_of::<u8>() + size_of::<u16>();
+
+impl<T: Copy> ViewRequest<T> {
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(self.layout.non_tree_data_size.to_le_bytes());
+        data.extend(self.layout.root_offset.to_le_bytes());
+        data.extend(self.layout.header_offset.unwrap_or(NO_HEADER).to_le_bytes());
+        data.extend((size_of::<T>() as u16).to_le_bytes());
+        match self.query {
+            ViewQuery::Get(key) => {
+                data.push(0);
+                push_key(&mut data, key);
+            }
+            ViewQuery::Best { side, limit } => {
+                data.extend([1, side_byte(side)]);
+                data.extend(limit.to_le_bytes());
+            }
+            ViewQuery::Range { lo, hi, limit } => {
+                data.push(2);
+                push_key(&mut data, lo);
+                push_key(&mut data, hi);
+                data.extend(limit.to_le_bytes());
+            }
+        }
+        data
+    }
+
+    /// Decodes a request packed for keys of type `T`; a request for keys of
+    /// another size is rejected.
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        let mut reader = Reader { data, at: 0 };
+        let non_tree_data_size = reader.u32()?;
+        let root_offset = reader.u32()?;
+        let header_offset = Some(reader.u32()?).filter(|&offset| offset != NO_HEADER);
+        if reader.u16()? as usize != size_of::<T>() {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        let query = match reader.u8()? {
+            0 => ViewQuery::Get(reader.key()?),
+            1 => ViewQuery::Best {
+                side: match reader.u8()? {
+                    0 => Side::Bid,
+                    1 => Side::Ask,
+                    _ => return Err(ProgramError::InvalidInstructionData),
+                },
+                limit: reader.u16()?,
+            },
+            2 => ViewQuery::Range {
+                lo: reader.key()?,
+                hi: reader.key()?,
+                limit: reader.u16()?,
+            },
+            _ => return Err(ProgramError::InvalidInstructionData),
+        };
+        Ok(Self {
+            layout: ViewLayout {
+                non_tree_data_size,
+                root_offset,
+                header_offset,
+            },
+            query,
+        })
+    }
+
+    /// Runs the query over the tree in account `data`.
+    pub fn answer(&self, data: &[u8]) -> Result<ViewReply<T>, TreeError>
+    where
+        T: Ord,
+    {
+        let view = TreeView::<T>::new(
+            data,
+            self.layout.non_tree_data_size as usize,
+            self.layout.root_offset as usize,
+            self.layout.header_offset.map(|offset| offset as usize),
+        )?;
+        let fit = (MAX_RETURN_DATA - REPLY_PREFIX) / (size_of::<T>() + size_of::<u32>());
+        let reply = match self.query {
+            ViewQuery::Get(key) => ViewReply {
+                entries: view.get(key).map(|link| (key, link)).into_iter().collect(),
+                truncated: false,
+            },
+            ViewQuery::Best {
+                side: Side::Ask,
+                limit,
+            } => collect(view.iter(), (limit as usize).min(fit)),
+            ViewQuery::Best {
+                side: Side::Bid,
+                limit,
+            } => collect(view.iter_rev(), (limit as usize).min(fit)),
+            ViewQuery::Range { lo, hi, limit } => collect(
+                view.iter_from(lo).take_while(|&(key, _)| key <= hi),
+                (limit as usize).min(fit),
+            ),
+        };
+        Ok(reply)
+    }
+}
+
+impl<T: Copy> ViewReply<T> {
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(
+            REPLY_PREFIX + self.entries.len() * (size_of::<T>() + size_of::<u32>()),
+        );
+        data.extend((size_of::<T>() as u16).to_le_bytes());
+        data.push(self.truncated as u8);
+        data.extend((self.entries.len() as u16).to_le_bytes());
+        for &(key, link) in &self.entries {
+            push_key(&mut data, key);
+            data.extend(link.to_le_bytes());
+        }
+        data
+    }
+
+    /// Decodes a reply. Return data comes back without its trailing zero
+    /// bytes, so missing bytes at the end read as zero.
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        let mut padded = data.to_vec();
+        padded.resize(padded.len().max(REPLY_PREFIX), 0);
+        let mut reader = Reader {
+            data: &padded,
+            at: 0,
+        };
+        if reader.u16()? as usize != size_of::<T>() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let truncated = reader.u8()? != 0;
+        let count = reader.u16()? as usize;
+        let len = REPLY_PREFIX + count * (size_of::<T>() + size_of::<u32>());
+        if padded.len() > len {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        padded.resize(len, 0);
+        let mut reader = Reader {
+            data: &padded,
+            at: REPLY_PREFIX,
+        };
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            entries.push((reader.key()?, reader.u32()?));
+        }
+        Ok(Self { entries, truncated })
+    }
+}
+
+/// Entry point body of a view program for trees with keys of type `T`: takes
+/// the tree account as its only account, answers the request in `data` and
+/// sets the packed [`ViewReply`] as return data.
+pub fn process_view<T: Copy + Ord>(accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let tree = next_account_info(&mut accounts.iter())?;
+    let request = ViewRequest::<T>::unpack(data)?;
+    let reply = request.answer(&tree.try_borrow_data()?)?;
+    set_return_data(&reply.pack());
+    Ok(())
+}
+
+/// Instruction asking `view_program` to answer `request` over `tree`, which
+/// is passed read-only.
+pub fn view_instruction<T: Copy>(
+    view_program: &Pubkey,
+    tree: &Pubkey,
+    request: &ViewRequest<T>,
+) -> Instruction {
+    Instruction::new_with_bytes(
+        *view_program,
+        &request.pack(),
+        vec![AccountMeta::new_readonly(*tree, false)],
+    )
+}
+
+/// Calls `view_program` over `tree` with `request` and decodes its reply.
+pub fn invoke_view<'a, 'info, T: Copy>(
+    view_program: &'a AccountInfo<'info>,
+    tree: &'a AccountInfo<'info>,
+    request: &ViewRequest<T>,
+) -> Result<ViewReply<T>, ProgramError> {
+    invoke(
+        &view_instruction(view_program.key, tree.key, request),
+        &[tree.clone(), view_program.clone()],
+    )?;
+    match get_return_data() {
+        Some((program_id, data)) if program_id == *view_program.key => ViewReply::unpack(&data),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+fn collect<T>(mut entries: impl Iterator<Item = (T, u32)>, limit: usize) -> ViewReply<T> {
+    let taken: Vec<_> = entries.by_ref().take(limit).collect();
+    ViewReply {
+        entries: taken,
+        truncated: entries.next().is_some(),
+    }
+}
+
+fn side_byte(side: Side) -> u8 {
+    match side {
+        Side::Bid => 0,
+        Side::Ask => 1,
+    }
+}
+
+fn push_key<T: Copy>(data: &mut Vec<u8>, key: T) {
+    let at = data.len();
+    data.resize(at + size_of::<T>(), 0);
+    unsafe { ptr::write_unaligned(data[at..].as_mut_ptr() as *mut T, key) }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> Result<&[u8], ProgramError> {
+        let bytes = self
+            .data
+            .get(self.at..self.at + len)
+            .ok_or(ProgramError::InvalidInstructionData)?;
+        self.at += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ProgramError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ProgramError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, ProgramError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn key<T: Copy>(&mut self) -> Result<T, ProgramError> {
+        let bytes = self.bytes(size_of::<T>())?;
+        Ok(unsafe { ptr::read_unaligned(bytes.as_ptr() as *const T) })
+    }
+}
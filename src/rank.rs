@@ -0,0 +1,119 @@
+use crate::{
+    augment::{value_of, Augment, Augmented},
+    storage::Storage,
+    NodePtr, Payer, RBTree, TreeError,
+};
+use solana_program::account_info::AccountInfo;
+use std::marker::PhantomData;
+
+/// Value stored in each node of a [`RankTree`]: the link and the number of
+/// nodes in the subtree rooted at it.
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ranked {
+    pub link: u32,
+    pub size: u32,
+}
+
+impl<K> Augment<K> for Ranked {
+    fn refresh(&mut self, _key: &K, left: Option<&Self>, right: Option<&Self>) {
+        self.size = 1 + left.map_or(0, |left| left.size) + right.map_or(0, |right| right.size);
+    }
+}
+
+/// Order-statistics tree: every node keeps the size of its subtree, so the
+/// position of a key ([`RankTree::rank`]) and the key at a position
+/// ([`RankTree::select`]) are found in O(log n).
+///
+/// The layout (`Node<T, Ranked>`) differs from trees with `u32` links, so a
+/// region must only ever be used through one of the two APIs.
+pub struct RankTree<T> {
+    tree: Augmented<T, Ranked>,
+    _key: PhantomData<T>,
+}
+
+impl<T: Copy + Ord> RankTree<T> {
+    pub fn new(tree: RBTree) -> Self {
+        Self {
+            tree: Augmented::new(tree),
+            _key: PhantomData,
+        }
+    }
+
+    /// Inserts `key`, after any equal keys. Returns the sref of the new node.
+    pub fn insert<'a, 'info>(
+        &self,
+        key: T,
+        link: u32,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<u32, TreeError> {
+        let value = Ranked { link, size: 1 };
+        self.tree
+            .insert(key, value, tree_acc, payer, system_program)
+            .map(|node| node.sref())
+    }
+
+    /// Removes a node holding `key` and returns its link, or `None` if there
+    /// is none.
+    pub fn remove(&mut self, key: T) -> Result<Option<u32>, TreeError> {
+        Ok(self.tree.remove(key)?.map(|value| value.link))
+    }
+
+    /// Number of nodes, read from the root.
+    pub fn len(&self) -> usize {
+        size_of(self.tree.root_node::<T, Ranked>())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of nodes with a key below `key`, which is the position `key`
+    /// has, or would have, in ascending order.
+    pub fn rank(&self, key: T) -> usize {
+        let mut node = self.tree.root_node::<T, Ranked>();
+        let mut rank = 0;
+        while !node.is_null() {
+            if node.key() < key {
+                rank += size_of(node.left()) + 1;
+                node = node.right();
+            } else {
+                node = node.left();
+            }
+        }
+        rank
+    }
+
+    /// `(key, link)` of the node at position `n` (from zero) in ascending
+    /// order, or `None` if the tree has at most `n` nodes.
+    pub fn select(&self, n: usize) -> Option<(T, u32)> {
+        let node = self.select_node(n);
+        if node.is_null() {
+            return None;
+        }
+        Some((node.key(), node.value().link))
+    }
+
+    /// Node at position `n` (from zero) in ascending order, or a null one.
+    pub fn select_node(&self, mut n: usize) -> NodePtr<T, Ranked> {
+        let mut node = self.tree.root_node::<T, Ranked>();
+        while !node.is_null() {
+            let left = size_of(node.left());
+            if n < left {
+                node = node.left();
+            } else if n == left {
+                return node;
+            } else {
+                n -= left + 1;
+                node = node.right();
+            }
+        }
+        node
+    }
+}
+
+fn size_of<K>(node: NodePtr<K, Ranked>) -> usize {
+    value_of(node).map_or(0, |value| value.size as usize)
+}
@@ -0,0 +1,117 @@
+use crate::{TreeError, TreeHeader, TreeView, ViewIter, ROOT_UNRECORDED, TREE_MAGIC, TREE_VERSION};
+use std::{mem::size_of, ptr};
+
+/// Lookups over a tree in account data fetched over RPC, with no
+/// `AccountInfo`, allocator or runtime involved. It reads through a
+/// [`TreeView`], so every node read is bounds checked.
+pub struct RBTreeReader<'a, T, V = u32> {
+    view: TreeView<'a, T, V>,
+}
+
+impl<'a, T: Copy, V: Copy> RBTreeReader<'a, T, V> {
+    /// Opens the tree in `data` from its header, found in the first
+    /// `non_tree_data_size` bytes by its magic and recorded non-tree data
+    /// size. The header must record the root offset, as [`RBTree::init`]
+    /// and [`build_account_image`] write it; other layouts are opened with
+    /// [`TreeView::new`] and [`RBTreeReader::from_view`].
+    ///
+    /// [`RBTree::init`]: crate::RBTree::init
+    /// [`build_account_image`]: crate::build_account_image
+    pub fn from_bytes(data: &'a [u8], non_tree_data_size: usize) -> Result<Self, TreeError> {
+        let prefix = data
+            .get(..non_tree_data_size)
+            .ok_or(TreeError::InvalidLayout)?;
+        let (header_offset, header) = prefix
+            .windows(size_of::<TreeHeader>())
+            .enumerate()
+            .map(|(offset, bytes)| {
+                (offset, unsafe {
+                    ptr::read_unaligned(bytes.as_ptr() as *const TreeHeader)
+                })
+            })
+            .find(|(_, header)| {
+                header.magic == TREE_MAGIC
+                    && header.version == TREE_VERSION
+                    && header.non_tree_data_size as usize == non_tree_data_size
+                    && header.root_offset != ROOT_UNRECORDED
+            })
+            .ok_or(TreeError::InvalidHeader)?;
+        let view = TreeView::new(
+            data,
+            non_tree_data_size,
+            header.root_offset as usize,
+            Some(header_offset),
+        )?;
+        Ok(Self { view })
+    }
+
+    /// Reader over a view decoded with an explicit layout.
+    pub fn from_view(view: TreeView<'a, T, V>) -> Self {
+        Self { view }
+    }
+
+    pub fn view(&self) -> &TreeView<'a, T, V> {
+        &self.view
+    }
+
+    /// Link stored under `key`, searched as [`TreeView::get`] does.
+    pub fn find(&self, key: T) -> Option<V>
+    where
+        T: Ord,
+    {
+        self.view.get(key)
+    }
+
+    pub fn contains_key(&self, key: T) -> bool
+    where
+        T: Ord,
+    {
+        self.view.get(key).is_some()
+    }
+
+    /// Entry with the smallest key.
+    pub fn min(&self) -> Option<(T, V)> {
+        self.view.iter().next()
+    }
+
+    /// Entry with the largest key.
+    pub fn max(&self) -> Option<(T, V)> {
+        self.view.iter_rev().next()
+    }
+
+    /// Number of entries, read from the header when it records this tree's
+    /// root and counted by iterating otherwise, as [`RBTree::len`] does.
+    ///
+    /// [`RBTree::len`]: crate::RBTree::len
+    pub fn len(&self) -> usize {
+        self.view
+            .header()
+            .filter(|header| header.root_offset != ROOT_UNRECORDED)
+            .map_or_else(
+                || self.view.iter().count(),
+                |header| header.node_count as usize,
+            )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.view.node(self.view.root_sref()).is_none()
+    }
+
+    /// Iterates over `(key, link)` in ascending key order.
+    pub fn iter(&self) -> ViewIter<'_, 'a, T, V> {
+        self.view.iter()
+    }
+
+    /// Iterates over `(key, link)` in descending key order.
+    pub fn iter_rev(&self) -> ViewIter<'_, 'a, T, V> {
+        self.view.iter_rev()
+    }
+
+    /// Iterates in ascending key order from the first key at least `key`.
+    pub fn iter_from(&self, key: T) -> ViewIter<'_, 'a, T, V>
+    where
+        T: Ord,
+    {
+        self.view.iter_from(key)
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{in_region, storage::Storage, NodePtr, RBTree, TreeError};
+
+/// Slot of a node taken out by [`RBTree::remove_and_reuse`] and still held in
+/// the allocator. Pass it to [`RBTree::insert_reusing`] to store the next node
+/// there, or to [`RBTree::release`] to free it; dropping it leaks the slot.
+/// It records the tree and node stride it was taken with, so it cannot be
+/// spent on another tree.
+#[must_use = "the slot stays allocated until it is reused or released"]
+#[derive(Debug, PartialEq, Eq)]
+pub struct ReusableSlot {
+    sref: u32,
+    link: u32,
+    root: *mut u32,
+    stride: usize,
+}
+
+impl ReusableSlot {
+    pub fn sref(&self) -> u32 {
+        self.sref
+    }
+
+    /// Link of the removed node.
+    pub fn link(&self) -> u32 {
+        self.link
+    }
+}
+
+impl RBTree {
+    /// Removes a node holding `key` but keeps its slot allocated, so a
+    /// following insert, e.g. the replace half of a cancel-replace, skips the
+    /// allocator and never grows the account. Returns `None` if there is no
+    /// such node. Fails without changing the tree if the header does not
+    /// match this handle or the node's links are broken, as
+    /// [`RBTree::try_delete`] does.
+    pub fn remove_and_reuse<T: Copy + Ord>(
+        &mut self,
+        key: T,
+    ) -> Result<Option<ReusableSlot>, TreeError> {
+        self.check_header()?;
+        let node = self.search::<T, u32>(key);
+        if node.is_null() {
+            return Ok(None);
+        }
+        self.check_links(node)?;
+        let slot = ReusableSlot {
+            sref: node.sref(),
+            link: node.link(),
+            root: self.root,
+            stride: self.stride::<T, u32>(),
+        };
+        self.unlink(node);
+        self.count_node(false);
+        self.touch();
+        Ok(Some(slot))
+    }
+
+    /// Inserts `key` into the slot handed out by
+    /// [`RBTree::remove_and_reuse`] on this tree and returns its sref. If the
+    /// key is rejected by the header, the slot is freed and the error returned.
+    ///
+    /// Fails with [`TreeError::ForeignSlot`], leaving the slot to the tree it
+    /// came from, if it was taken from another tree or with another key
+    /// type, or it is not an allocated slot of this tree's node region.
+    pub fn insert_reusing<T: Copy + Ord>(
+        &mut self,
+        slot: ReusableSlot,
+        key: T,
+        link: u32,
+    ) -> Result<u32, TreeError> {
+        let region_len = self.region_len.get();
+        let stride = self.stride::<T, u32>();
+        if slot.root != self.root
+            || slot.stride != stride
+            || !in_region::<T, u32>(slot.sref, region_len, stride)
+            || !self.pt.is_allocated(slot.sref as usize)
+        {
+            return Err(TreeError::ForeignSlot { sref: slot.sref });
+        }
+        if let Err(error) = self.check_header().and_then(|_| self.check_key(&key)) {
+            return self.release(slot).and(Err(error));
+        }
+        let node = NodePtr::write(self.entry, region_len, stride, slot.sref, key, link);
+        self.link_in_order(node);
+        self.count_node(true);
+        self.touch();
+        Ok(node.sref())
+    }
+
+    /// Frees a slot handed out by [`RBTree::remove_and_reuse`] that is no
+    /// longer needed. Fails with [`TreeError::ForeignSlot`] if it was taken
+    /// from another tree and with [`TreeError::DeallocFailed`] if the
+    /// allocator does not hold it.
+    pub fn release(&mut self, slot: ReusableSlot) -> Result<(), TreeError> {
+        let sref = slot.sref;
+        if slot.root != self.root {
+            return Err(TreeError::ForeignSlot { sref });
+        }
+        self.pt
+            .dealloc(sref as usize)
+            .map_err(|_| TreeError::DeallocFailed { sref })
+    }
+}
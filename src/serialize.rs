@@ -0,0 +1,82 @@
+use crate::{Color, TreeView, NULL_NODE};
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+/// A [`TreeView`] serialized with its structure, from
+/// [`TreeView::with_structure`].
+pub struct StructureView<'v, 'a, T, V = u32> {
+    view: &'v TreeView<'a, T, V>,
+}
+
+/// One node of a [`StructureView`], children as srefs.
+#[derive(Serialize)]
+struct NodeEntry<T, V> {
+    sref: u32,
+    color: Color,
+    key: T,
+    value: V,
+    left: Option<u32>,
+    right: Option<u32>,
+}
+
+impl<'a, T: Copy, V: Copy> TreeView<'a, T, V> {
+    /// The view serializing the nodes too, each with its sref, color and
+    /// children, for debugging the shape of a tree rather than its contents.
+    pub fn with_structure(&self) -> StructureView<'_, 'a, T, V> {
+        StructureView { view: self }
+    }
+
+    /// Nodes reachable from the root in preorder, stopping at the node count
+    /// the data can hold so that a cycle in corrupted links ends the walk.
+    fn preorder(&self) -> Vec<NodeEntry<T, V>> {
+        let mut nodes = Vec::new();
+        let mut stack = vec![self.root_sref()];
+        let limit = self.capacity();
+        while let Some(sref) = stack.pop() {
+            let Some(node) = self.node(sref) else {
+                continue;
+            };
+            if nodes.len() >= limit {
+                break;
+            }
+            nodes.push(NodeEntry {
+                sref,
+                color: if node.bits().is_red() {
+                    Color::Red
+                } else {
+                    Color::Black
+                },
+                key: node.key,
+                value: node.link,
+                left: Some(node.left_sref()).filter(|&left| left != NULL_NODE),
+                right: Some(node.right_sref()).filter(|&right| right != NULL_NODE),
+            });
+            stack.push(node.right_sref());
+            stack.push(node.left_sref());
+        }
+        nodes
+    }
+}
+
+/// Serializes the root sref and the `(key, link)` pairs in key order, e.g.
+/// as JSON for dashboards: `{"root": 0, "entries": [[key, link], ...]}`.
+impl<T: Copy + Serialize, V: Copy + Serialize> Serialize for TreeView<'_, T, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("TreeView", 2)?;
+        state.serialize_field("root", &self.root_sref())?;
+        state.serialize_field("entries", &self.iter().collect::<Vec<_>>())?;
+        state.end()
+    }
+}
+
+/// Serializes as the [`TreeView`] does, plus `nodes`: the nodes in preorder
+/// as `{"sref", "color", "key", "value", "left", "right"}`, null children
+/// as `null`.
+impl<T: Copy + Serialize, V: Copy + Serialize> Serialize for StructureView<'_, '_, T, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("TreeView", 3)?;
+        state.serialize_field("root", &self.view.root_sref())?;
+        state.serialize_field("entries", &self.view.iter().collect::<Vec<_>>())?;
+        state.serialize_field("nodes", &self.view.preorder())?;
+        state.end()
+    }
+}
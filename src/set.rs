@@ -0,0 +1,83 @@
+use crate::{OpError, Payer, RBTree, RBTreeMap, TreeError};
+use bytemuck::NoUninit;
+use solana_program::account_info::AccountInfo;
+use std::ops::RangeBounds;
+
+/// Ordered set of unique keys over the key-only node layout (`Node<K, ()>`),
+/// for membership and dedup registries that have nothing to link to, read
+/// and written like `std::collections::BTreeSet`. Like [`RBTreeMap`] it holds
+/// the accounts an insert may need to grow the tree.
+///
+/// The layout differs from trees with links, so a region must only ever be
+/// used through one of the two APIs.
+pub struct RBTreeSet<'a, 'info, K> {
+    map: RBTreeMap<'a, 'info, K, ()>,
+}
+
+impl<'a, 'info, K: Copy + Ord> RBTreeSet<'a, 'info, K> {
+    /// Opens the set over `tree`, stored in `tree_acc`. Inserts that grow the
+    /// account are paid for by `payer` through `system_program`.
+    pub fn new(
+        tree: RBTree,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Self {
+        Self {
+            map: RBTreeMap::new(tree, tree_acc, payer, system_program),
+        }
+    }
+
+    pub fn tree(&self) -> &RBTree {
+        self.map.tree()
+    }
+
+    pub fn into_tree(self) -> RBTree {
+        self.map.into_tree()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Adds `key`, returning `false` if it was already present. Fails when
+    /// the key is rejected by the header or the account cannot grow.
+    pub fn insert(&mut self, key: K) -> Result<bool, OpError>
+    where
+        K: NoUninit,
+    {
+        self.map.insert(key, ()).map(|old| old.is_none())
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// Removes `key`, returning whether it was present. Fails as
+    /// [`RBTree::try_delete`] does.
+    pub fn remove(&mut self, key: &K) -> Result<bool, TreeError> {
+        Ok(self.map.remove(key)?.is_some())
+    }
+
+    /// Keys in ascending order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = K> + '_ {
+        self.map.iter().map(|(key, ())| key)
+    }
+
+    /// Keys within `range`, in ascending order.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> impl DoubleEndedIterator<Item = K> + '_ {
+        self.map.range(range).map(|(key, ())| key)
+    }
+
+    pub fn first(&self) -> Option<K> {
+        self.iter().next()
+    }
+
+    pub fn last(&self) -> Option<K> {
+        self.iter().next_back()
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{build_account_image, Payer, RBTree, TreeError, TreeHeader, KEY_BOUND_BYTES};
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::account_info::AccountInfo;
+use std::{mem::size_of, ops::Range, ptr};
+
+/// Live contents of a tree, free of its node layout: the `(key, link)` pairs
+/// in key order and the header metadata worth carrying over. Serialized with
+/// borsh, it moves a tree between accounts of different layouts or builds,
+/// e.g. for migrations and test fixtures. Trees without a header snapshot
+/// zero counters and no key range.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot<T> {
+    /// Mutation counter, see [`RBTree::sequence`].
+    pub sequence: u64,
+    /// Slot of the last mutation, see [`RBTree::mark_slot`].
+    pub last_slot: u64,
+    /// Allowed key range, see [`RBTree::set_key_range`].
+    pub key_range: Option<(T, T)>,
+    pub entries: Vec<(T, u32)>,
+}
+
+impl RBTree {
+    /// Copies the live contents of the tree into a [`Snapshot`]. Intended for
+    /// off-chain use: it allocates the whole entry list.
+    pub fn snapshot<T: Copy>(&self) -> Result<Snapshot<T>, TreeError> {
+        self.check_header()?;
+        let header = self.header();
+        Ok(Snapshot {
+            sequence: header.map_or(0, |header| header.sequence),
+            last_slot: header.map_or(0, |header| header.last_slot),
+            key_range: self.key_range()?,
+            entries: self.iter().collect(),
+        })
+    }
+
+    /// Fills an empty tree with a [`Snapshot`] through
+    /// [`RBTree::build_from_sorted`], then restores its header metadata.
+    /// Snapshots whose entries are out of order, e.g. decoded from untrusted
+    /// bytes, fail with [`TreeError::Unsorted`].
+    pub fn restore<'a, 'info, T: Copy + Ord>(
+        &self,
+        snapshot: &Snapshot<T>,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<(), TreeError> {
+        snapshot.check_key_range()?;
+        self.build_from_sorted(&snapshot.entries, tree_acc, payer, system_program)?;
+        if self.header.is_null() {
+            return Ok(());
+        }
+        if let Some((min, max)) = snapshot.key_range {
+            self.set_key_range(min, max)?;
+        }
+        let mut header = unsafe { self.header.read_unaligned() };
+        header.sequence = snapshot.sequence;
+        header.last_slot = snapshot.last_slot;
+        unsafe { self.header.write_unaligned(header) };
+        Ok(())
+    }
+}
+
+impl<T: Copy + Ord> Snapshot<T> {
+    /// Fails if the key range cannot be stored in a header or leaves out an
+    /// entry, before anything is written.
+    fn check_key_range(&self) -> Result<(), TreeError> {
+        let Some((min, max)) = self.key_range else {
+            return Ok(());
+        };
+        if size_of::<T>() > KEY_BOUND_BYTES {
+            return Err(TreeError::InvalidLayout);
+        }
+        if min > max {
+            return Err(TreeError::EmptyKeyRange);
+        }
+        if self.entries.iter().any(|&(key, _)| key < min || key > max) {
+            return Err(TreeError::KeyOutOfRange);
+        }
+        Ok(())
+    }
+
+    /// Account bytes holding the snapshot as a tree, with its allocator
+    /// storage at `bitmap`, as [`build_account_image`] lays them out, with
+    /// the header metadata restored when there is a header.
+    pub fn to_account_image(
+        &self,
+        non_tree_data_size: usize,
+        capacity: usize,
+        root_offset: usize,
+        header_offset: Option<usize>,
+        bitmap: Range<usize>,
+    ) -> Result<Vec<u8>, TreeError> {
+        self.check_key_range()?;
+        let mut image = build_account_image(
+            &self.entries,
+            non_tree_data_size,
+            capacity,
+            root_offset,
+            header_offset,
+            bitmap,
+        )?;
+        if let Some(offset) = header_offset {
+            unsafe {
+                let header = image.as_mut_ptr().add(offset) as *mut TreeHeader;
+                let mut fresh = header.read_unaligned();
+                fresh.sequence = self.sequence;
+                fresh.last_slot = self.last_slot;
+                if let Some((min, max)) = self.key_range {
+                    fresh.key_bound_len = size_of::<T>() as u32;
+                    ptr::write_unaligned(fresh.key_min.as_mut_ptr() as *mut T, min);
+                    ptr::write_unaligned(fresh.key_max.as_mut_ptr() as *mut T, max);
+                }
+                header.write_unaligned(fresh);
+            }
+        }
+        Ok(image)
+    }
+}
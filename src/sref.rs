@@ -0,0 +1,61 @@
+use crate::Node;
+
+/// Word holding an sref inside a node. With the `compact-srefs` feature it is
+/// a `u16`, saving eight bytes per node and capping trees at
+/// [`MAX_NODES`](crate::MAX_NODES) nodes; the header records the choice like
+/// the other layout features.
+#[cfg(not(feature = "compact-srefs"))]
+pub type SrefWord = u32;
+#[cfg(feature = "compact-srefs")]
+pub type SrefWord = u16;
+
+/// Sref held in a stored word, the all-ones word being the null link.
+#[cfg(not(feature = "compact-srefs"))]
+#[inline]
+pub(crate) const fn widen(word: SrefWord) -> u32 {
+    word
+}
+
+/// Sref held in a stored word, the all-ones word being the null link.
+#[cfg(feature = "compact-srefs")]
+#[inline]
+pub(crate) const fn widen(word: SrefWord) -> u32 {
+    if word == SrefWord::MAX {
+        crate::NULL_NODE
+    } else {
+        word as u32
+    }
+}
+
+/// Stored word of `sref`. [`NULL_NODE`](crate::NULL_NODE) truncates to the all-ones word; other
+/// srefs are below [`MAX_NODES`](crate::MAX_NODES) and fit.
+#[inline]
+pub(crate) const fn narrow(sref: u32) -> SrefWord {
+    sref as SrefWord
+}
+
+impl<T, V> Node<T, V> {
+    pub(crate) fn sref(&self) -> u32 {
+        widen(self.sref)
+    }
+
+    pub(crate) fn set_sref(&mut self, sref: u32) {
+        self.sref = narrow(sref);
+    }
+
+    pub(crate) fn left_sref(&self) -> u32 {
+        widen(self.left)
+    }
+
+    pub(crate) fn set_left_sref(&mut self, sref: u32) {
+        self.left = narrow(sref);
+    }
+
+    pub(crate) fn right_sref(&self) -> u32 {
+        widen(self.right)
+    }
+
+    pub(crate) fn set_right_sref(&mut self, sref: u32) {
+        self.right = narrow(sref);
+    }
+}
@@ -0,0 +1,297 @@
+//! Storage the balancing algorithms run on. A tree only needs a root word and
+//! a node region to be rotated, fixed up, linked into and unlinked from, so
+//! the account-backed [`RBTree`](crate::RBTree) and the array-backed
+//! [`StaticTree`](crate::StaticTree) share one implementation of them.
+
+use crate::{Node, NodePtr, NULL_NODE};
+use std::{cmp::Ordering, mem::size_of, ptr};
+
+pub(crate) trait Storage {
+    /// Word holding the sref of the root node.
+    fn root_word(&self) -> *mut u32;
+    /// Start of the node region.
+    fn node_entry(&self) -> *mut u64;
+    /// Bytes of the node region.
+    fn node_region_len(&self) -> usize;
+    /// Bytes from one node of the region to the next.
+    #[inline]
+    fn stride<T, V>(&self) -> usize {
+        size_of::<Node<T, V>>()
+    }
+
+    /// Called after every rotation with the node moved down and the one that
+    /// took its place, so trees keeping per-subtree aggregates can refresh
+    /// them. Both subtrees below `lower` are unchanged by the rotation.
+    #[inline]
+    fn rotated<T: Copy, V>(&self, _lower: NodePtr<T, V>, _upper: NodePtr<T, V>) {}
+    /// Called once a node has been linked in or spliced out, before any
+    /// rotation, with the lowest node whose subtree changed. Aggregates are
+    /// refreshed from there to the root so that rotations start from
+    /// consistent values.
+    #[inline]
+    fn propagate<T: Copy, V>(&self, _from: NodePtr<T, V>) {}
+
+    #[inline]
+    fn get_root_sref(&self) -> u32 {
+        unsafe { *self.root_word() }
+    }
+    #[inline]
+    fn set_root_sref(&self, new_root: u32) {
+        unsafe { *self.root_word() = new_root }
+    }
+    #[inline]
+    fn root_node<T, V>(&self) -> NodePtr<T, V> {
+        NodePtr(
+            ptr::null_mut(),
+            self.node_entry(),
+            self.node_region_len(),
+            self.stride::<T, V>(),
+        )
+        .at(self.get_root_sref())
+    }
+    fn search<T: Copy + Ord, V>(&self, key: T) -> NodePtr<T, V> {
+        if self.get_root_sref() == NULL_NODE {
+            return NodePtr::null();
+        }
+        let mut temp = self.root_node();
+        loop {
+            let next = match key.cmp(&temp.key()) {
+                Ordering::Less => temp.left(),
+                Ordering::Greater => temp.right(),
+                Ordering::Equal => {
+                    return temp;
+                }
+            };
+            if next.is_null() {
+                break;
+            }
+            temp = next;
+        }
+        NodePtr::null()
+    }
+    #[inline]
+    fn left_rotate<T: Copy, V>(&self, mut node: NodePtr<T, V>) {
+        #[cfg(feature = "bench")]
+        crate::bench::count_rotation();
+        let mut temp = node.right();
+        node.set_right(temp.left());
+        if !temp.left().is_null() {
+            temp.left().set_parent(node);
+        }
+        temp.set_parent(node.parent());
+        if node.sref() == self.get_root_sref() {
+            self.set_root_sref(temp.sref());
+        } else if node == node.parent().left() {
+            node.parent().set_left(temp);
+        } else {
+            node.parent().set_right(temp);
+        }
+        temp.set_left(node);
+        node.set_parent(temp);
+        self.rotated(node, temp);
+    }
+    #[inline]
+    fn right_rotate<T: Copy, V>(&self, mut node: NodePtr<T, V>) {
+        #[cfg(feature = "bench")]
+        crate::bench::count_rotation();
+        let mut temp = node.left();
+        node.set_left(temp.right());
+
+        if !temp.right().is_null() {
+            temp.right().set_parent(node);
+        }
+
+        temp.set_parent(node.parent());
+        if node.sref() == self.get_root_sref() {
+            self.set_root_sref(temp.sref());
+        } else if node == node.parent().right() {
+            node.parent().set_right(temp);
+        } else {
+            node.parent().set_left(temp);
+        }
+        temp.set_right(node);
+        node.set_parent(temp);
+        self.rotated(node, temp);
+    }
+    #[inline]
+    fn insert_fixup<T: Copy, V>(&self, mut node: NodePtr<T, V>) {
+        let mut parent;
+        let mut gparent;
+        while node.parent().is_red_color() {
+            parent = node.parent();
+            gparent = parent.parent();
+            if parent == gparent.left() {
+                let mut uncle = gparent.right();
+                if !uncle.is_null() && uncle.is_red_color() {
+                    uncle.set_black_color();
+                    parent.set_black_color();
+                    gparent.set_red_color();
+                    node = gparent;
+                    continue;
+                }
+                if parent.right() == node {
+                    self.left_rotate(parent);
+                    std::mem::swap(&mut parent, &mut node);
+                }
+                parent.set_black_color();
+                gparent.set_red_color();
+                self.right_rotate(gparent);
+            } else {
+                let mut uncle = gparent.left();
+                if !uncle.is_null() && uncle.is_red_color() {
+                    uncle.set_black_color();
+                    parent.set_black_color();
+                    gparent.set_red_color();
+                    node = gparent;
+                    continue;
+                }
+                if parent.left() == node {
+                    self.right_rotate(parent);
+                    std::mem::swap(&mut parent, &mut node);
+                }
+                parent.set_black_color();
+                gparent.set_red_color();
+                self.left_rotate(gparent);
+            }
+        }
+        self.root_node::<T, V>().set_black_color();
+    }
+    #[inline]
+    fn delete_fixup<T: Copy, V>(&self, mut node: NodePtr<T, V>, mut parent: NodePtr<T, V>) {
+        let mut other;
+        while node.sref() != self.get_root_sref() && node.is_black_color() {
+            if parent.left() == node {
+                other = parent.right();
+                if other.is_red_color() {
+                    other.set_black_color();
+                    parent.set_red_color();
+                    self.left_rotate(parent);
+                    other = parent.right();
+                }
+                if other.left().is_black_color() && other.right().is_black_color() {
+                    other.set_red_color();
+                    node = parent;
+                    parent = node.parent();
+                } else {
+                    if other.right().is_black_color() {
+                        other.left().set_black_color();
+                        other.set_red_color();
+                        self.right_rotate(other);
+                        other = parent.right();
+                    }
+                    other.set_color(parent.get_color());
+                    parent.set_black_color();
+                    other.right().set_black_color();
+                    self.left_rotate(parent);
+                    node = self.root_node();
+                    break;
+                }
+            } else {
+                other = parent.left();
+                if other.is_red_color() {
+                    other.set_black_color();
+                    parent.set_red_color();
+                    self.right_rotate(parent);
+                    other = parent.left();
+                }
+                if other.left().is_black_color() && other.right().is_black_color() {
+                    other.set_red_color();
+                    node = parent;
+                    parent = node.parent();
+                } else {
+                    if other.left().is_black_color() {
+                        other.right().set_black_color();
+                        other.set_red_color();
+                        self.left_rotate(other);
+                        other = parent.left();
+                    }
+                    other.set_color(parent.get_color());
+                    parent.set_black_color();
+                    other.left().set_black_color();
+                    self.right_rotate(parent);
+                    node = self.root_node();
+                    break;
+                }
+            }
+        }
+        node.set_black_color();
+    }
+    /// Links the freshly written `node` in as a child of `y`, which must be
+    /// the node a search for its key ends at (or null for an empty tree), and
+    /// rebalances.
+    fn link<T: Copy + PartialOrd, V>(&self, y: NodePtr<T, V>, mut node: NodePtr<T, V>) {
+        node.set_parent(y);
+        if y.is_null() {
+            self.set_root_sref(node.sref());
+        } else if node.key() < y.key() {
+            y.set_left(node);
+        } else {
+            y.set_right(node);
+        }
+        node.set_red_color();
+        self.propagate(node);
+        self.insert_fixup(node);
+    }
+    /// Takes `node` out of the tree and rebalances. The slot is left for the
+    /// caller to free.
+    fn unlink<T: Copy, V>(&self, node: NodePtr<T, V>) {
+        let mut child;
+        let mut parent;
+        let color;
+        if !node.left().is_null() && !node.right().is_null() {
+            let mut replace = node.right().min_node();
+            if node.sref() == self.get_root_sref() {
+                self.set_root_sref(replace.sref());
+            } else if node.parent().left() == node {
+                node.parent().set_left(replace);
+            } else {
+                node.parent().set_right(replace);
+            }
+
+            child = replace.right();
+            parent = replace.parent();
+            color = replace.get_color();
+            if parent == node {
+                parent = replace;
+            } else {
+                if !child.is_null() {
+                    child.set_parent(parent);
+                }
+                parent.set_left(child);
+                replace.set_right(node.right());
+                node.right().set_parent(replace);
+            }
+            replace.set_parent(node.parent());
+            replace.set_color(node.get_color());
+            replace.set_left(node.left());
+            node.left().set_parent(replace);
+            self.propagate(parent);
+            if color == 0 {
+                self.delete_fixup(child, parent);
+            }
+            return;
+        }
+        if !node.left().is_null() {
+            child = node.left();
+        } else {
+            child = node.right();
+        }
+        parent = node.parent();
+        color = node.get_color();
+        if !child.is_null() {
+            child.set_parent(parent);
+        }
+        if self.get_root_sref() == node.sref() {
+            self.set_root_sref(child.sref())
+        } else if parent.left() == node {
+            parent.set_left(child);
+        } else {
+            parent.set_right(child);
+        }
+        self.propagate(parent);
+
+        if color == 0 {
+            self.delete_fixup(child, parent);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use crate::{
+    augment::{value_of, Augment, Augmented},
+    storage::Storage,
+    NodePtr, Payer, RBTree, TreeError,
+};
+use solana_program::account_info::AccountInfo;
+use std::{
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+};
+
+/// Value stored in each node of a [`SumTree`]: the link, the node's own
+/// weight and the total weight of the subtree rooted at it.
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Weighted {
+    pub link: u32,
+    pub weight: u64,
+    pub sum: u64,
+}
+
+impl<K> Augment<K> for Weighted {
+    fn refresh(&mut self, _key: &K, left: Option<&Self>, right: Option<&Self>) {
+        self.sum = self
+            .weight
+            .saturating_add(left.map_or(0, |left| left.sum))
+            .saturating_add(right.map_or(0, |right| right.sum));
+    }
+}
+
+/// Tree whose nodes carry a weight (e.g. the quantity resting at a price) and
+/// keep the total weight of their subtree, so the weight between two keys is
+/// found in O(log n) with [`SumTree::sum_in_range`].
+///
+/// Totals saturate at `u64::MAX` rather than wrap. Weights are meant to add
+/// up to less, e.g. token amounts; once a subtree saturates, the totals,
+/// range sums and depths computed through it are no longer exact.
+///
+/// The layout (`Node<T, Weighted>`) differs from trees with `u32` links, so a
+/// region must only ever be used through one of the two APIs.
+pub struct SumTree<T> {
+    tree: Augmented<T, Weighted>,
+    _key: PhantomData<T>,
+}
+
+impl<T: Copy + Ord> SumTree<T> {
+    pub fn new(tree: RBTree) -> Self {
+        Self {
+            tree: Augmented::new(tree),
+            _key: PhantomData,
+        }
+    }
+
+    /// Inserts `key` with `weight`, after any equal keys. Returns the sref of
+    /// the new node.
+    pub fn insert<'a, 'info>(
+        &self,
+        key: T,
+        link: u32,
+        weight: u64,
+        tree_acc: &'a AccountInfo<'info>,
+        payer: impl Into<Payer<'a, 'info>>,
+        system_program: &'a AccountInfo<'info>,
+    ) -> Result<u32, TreeError> {
+        let value = Weighted {
+            link,
+            weight,
+            sum: weight,
+        };
+        self.tree
+            .insert(key, value, tree_acc, payer, system_program)
+            .map(|node| node.sref())
+    }
+
+    /// Removes a node holding `key` and returns its link, or `None` if there
+    /// is none.
+    pub fn remove(&mut self, key: T) -> Result<Option<u32>, TreeError> {
+        Ok(self.tree.remove(key)?.map(|value| value.link))
+    }
+
+    /// Sets the weight of the node at `sref`, e.g. after the order it stands
+    /// for was partly filled, and updates the totals above it. Returns
+    /// `false` if `sref` is not a live node.
+    pub fn set_weight(&self, sref: u32, weight: u64) -> bool {
+        self.tree.update(sref, |value| value.weight = weight)
+    }
+
+    /// Total weight of all nodes.
+    pub fn total(&self) -> u64 {
+        sum_of(self.tree.root_node::<T, Weighted>())
+    }
+
+    /// Total weight of the nodes whose key lies in `range`.
+    pub fn sum_in_range<R: RangeBounds<T>>(&self, range: R) -> u64 {
+        let below_start = match range.start_bound() {
+            Bound::Included(key) => self.weight_before(key, false),
+            Bound::Excluded(key) => self.weight_before(key, true),
+            Bound::Unbounded => 0,
+        };
+        let below_end = match range.end_bound() {
+            Bound::Included(key) => self.weight_before(key, true),
+            Bound::Excluded(key) => self.weight_before(key, false),
+            Bound::Unbounded => self.total(),
+        };
+        below_end.saturating_sub(below_start)
+    }
+
+    /// First node, in key order, at which the running weight total (the
+    /// node's own weight included) reaches `depth`, e.g. the last price level
+    /// an order for `depth` units would take from. Returns a null pointer if
+    /// the total weight is below `depth`.
+    pub fn node_at_depth(&self, mut depth: u64) -> NodePtr<T, Weighted> {
+        let mut node = self.tree.root_node::<T, Weighted>();
+        while !node.is_null() {
+            let left = sum_of(node.left());
+            let through = left.saturating_add(node.value().weight);
+            if depth <= left && !node.left().is_null() {
+                node = node.left();
+            } else if depth <= through {
+                return node;
+            } else {
+                depth -= through;
+                node = node.right();
+            }
+        }
+        node
+    }
+
+    /// Total weight of the nodes with a key below `key`, or at most `key`
+    /// when `inclusive`.
+    fn weight_before(&self, key: &T, inclusive: bool) -> u64 {
+        let mut node = self.tree.root_node::<T, Weighted>();
+        let mut total = 0u64;
+        while !node.is_null() {
+            let before = if inclusive {
+                node.key() <= *key
+            } else {
+                node.key() < *key
+            };
+            if before {
+                total = total
+                    .saturating_add(sum_of(node.left()))
+                    .saturating_add(node.value().weight);
+                node = node.right();
+            } else {
+                node = node.left();
+            }
+        }
+        total
+    }
+}
+
+fn sum_of<K>(node: NodePtr<K, Weighted>) -> u64 {
+    value_of(node).map_or(0, |value| value.sum)
+}
@@ -0,0 +1,94 @@
+use crate::{iter::take_n, storage::Storage, NodePtr, RBTree};
+use std::{
+    fmt::{self, Debug},
+    marker::PhantomData,
+};
+
+/// Number of entries printed from each end of the tree by [`TreeSummary`].
+const SUMMARY_ENTRIES: usize = 3;
+
+/// Prints what the handle knows without the key type: its pointers and the
+/// header fields, `len` being the header's node count. [`RBTree::summary`]
+/// adds the height and boundary entries.
+impl Debug for RBTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = self.header();
+        let key_range = header
+            .filter(|header| header.key_bound_len != 0)
+            .map(|header| {
+                let len = (header.key_bound_len as usize).min(header.key_min.len());
+                (
+                    header.key_min[..len].to_vec(),
+                    header.key_max[..len].to_vec(),
+                )
+            });
+        f.debug_struct("RBTree")
+            .field("root", &self.get_root_sref())
+            .field("entry", &self.entry)
+            .field("non_tree_data_size", &self.non_tree_data_size)
+            .field("region_len", &self.region_len.get())
+            .field("len", &header.map(|header| header.node_count))
+            .field("sequence", &header.map(|header| header.sequence))
+            .field("last_slot", &header.map(|header| header.last_slot))
+            .field("key_size", &header.map(|header| header.key_size))
+            .field("key_range", &key_range)
+            .field("node_layout", &header.map(|header| header.node_layout))
+            .finish()
+    }
+}
+
+/// Debug view of a tree with its key type, printing the node count, height and
+/// the first and last few entries. Obtained from [`RBTree::summary`].
+pub struct TreeSummary<'a, T> {
+    tree: &'a RBTree,
+    _key: PhantomData<T>,
+}
+
+impl RBTree {
+    /// Debug view of the tree read with keys of `T`, adding the height and
+    /// the first and last entries to what `{:?}` prints for the handle.
+    pub fn summary<T>(&self) -> TreeSummary<'_, T> {
+        TreeSummary {
+            tree: self,
+            _key: PhantomData,
+        }
+    }
+}
+
+impl<T: Debug + Copy> Debug for TreeSummary<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let root = self.tree.get_root::<T>();
+        let limit = self.tree.region_slots::<T>();
+        let (len, height) = measure(root, limit);
+        f.debug_struct("RBTree")
+            .field("root", &root.sref())
+            .field("non_tree_data_size", &self.tree.non_tree_data_size)
+            .field("header", &self.tree.header())
+            .field("len", &len)
+            .field("height", &height)
+            .field("first", &take_n(root, false, SUMMARY_ENTRIES, limit))
+            .field("last", &take_n(root, true, SUMMARY_ENTRIES, limit))
+            .finish()
+    }
+}
+
+/// Node count and height of the subtree under `root`, walked with an explicit
+/// stack. Links of a corrupted tree may form a cycle, so the walk stops after
+/// `limit` nodes, the most the node region holds.
+fn measure<T>(root: NodePtr<T>, limit: usize) -> (usize, usize) {
+    let (mut len, mut height) = (0, 0);
+    let mut stack = vec![(root, 1)];
+    while let Some((node, depth)) = stack.pop() {
+        if node.is_null() {
+            continue;
+        }
+        if len == limit {
+            break;
+        }
+        len += 1;
+        height = height.max(depth);
+        stack.push((node.right(), depth + 1));
+        stack.push((node.left(), depth + 1));
+    }
+    (len, height)
+}
@@ -0,0 +1,491 @@
+//! Helpers for building trees with an exact shape in tests.
+//!
+//! Shapes are written as s-expressions: a node is its color (`R` or `B`)
+//! followed by its key, a node with children is `(node left right)` and `_` is
+//! an empty child. `"(B10 (R5 B3 B7) B15)"` is a black root `10` with a red
+//! left child `5` that has two black children, and a black right child `15`.
+//!
+//! The shape is written as given: neither the key order nor the red-black
+//! rules are checked, so fixup routines can be driven from any configuration.
+//!
+//! [`corrupt`] damages single nodes of any tree on purpose (a flipped color, a
+//! broken parent link, a dangling child) to test that it is detected.
+//!
+//! [`GrowableAccount`] lays an account out the way the runtime passes it to a
+//! program, so inserts can grow it outside a validator, and
+//! [`check_realloc_stability`] uses it to iterate across that growth.
+
+use crate::{
+    in_region, slot, storage::Storage, Node, NodeBits, NodePtr, RBTree, StaticTree, TreeError,
+    NULL_NODE,
+};
+use index_mem_alloc::MemoryMap;
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::{self, MAX_PERMITTED_DATA_INCREASE, NON_DUP_MARKER},
+    pubkey::Pubkey,
+};
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    fmt::{Debug, Display},
+    mem::size_of,
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+    str::FromStr,
+};
+
+struct ShapeNode<T> {
+    key: T,
+    red: bool,
+    parent: Option<usize>,
+    left: Option<usize>,
+    right: Option<usize>,
+}
+
+/// A tree built from a shape description, owning its node region and root
+/// word.
+pub struct TestTree {
+    pub tree: RBTree,
+    _nodes: Vec<u64>,
+    _root: Box<u32>,
+}
+
+impl TestTree {
+    /// Builds the tree described by `shape`, allocating one slot per node from
+    /// `pt`, which must be empty. The node region is sized for `capacity`
+    /// slots so the tree can keep growing through inserts. Every node's link
+    /// is set to its sref.
+    ///
+    /// Panics on a malformed description.
+    pub fn from_shape<T>(pt: MemoryMap, capacity: usize, shape: &str) -> Self
+    where
+        T: Copy + FromStr,
+        T::Err: Debug,
+    {
+        let spaced = shape.replace('(', " ( ").replace(')', " ) ");
+        let tokens: Vec<&str> = spaced.split_whitespace().collect();
+        let mut nodes = Vec::new();
+        let mut pos = 0;
+        let root = parse(&tokens, &mut pos, None, &mut nodes);
+        assert!(pos == tokens.len(), "trailing input in shape: {shape}");
+
+        let mut tree = RBTree {
+            pt,
+            root: ptr::null_mut(),
+            entry: ptr::null_mut(),
+            non_tree_data_size: 0,
+            header: ptr::null_mut(),
+            region_len: Cell::new(0),
+            grown_from: Cell::new(None),
+        };
+        let mut srefs = Vec::with_capacity(nodes.len());
+        for _ in &nodes {
+            srefs.push(tree.pt.alloc().expect("allocator too small for shape") as u32);
+        }
+        assert!(
+            srefs.iter().all(|&sref| (sref as usize) < capacity),
+            "shape does not fit in {capacity} slots"
+        );
+        let mut buffer = vec![0u64; (capacity * size_of::<Node<T>>()).div_ceil(8)];
+        let mut root_sref = Box::new(root.map_or(NULL_NODE, |i| srefs[i]));
+        tree.entry = buffer.as_mut_ptr();
+        tree.region_len.set(buffer.len() * size_of::<u64>());
+        tree.root = &mut *root_sref;
+
+        let sref_of = |i: Option<usize>| i.map_or(NULL_NODE, |i| srefs[i]);
+        for (i, node) in nodes.iter().enumerate() {
+            unsafe {
+                slot::<T, u32>(tree.entry, srefs[i], tree.stride::<T, u32>()).write_unaligned(
+                    Node::new(
+                        node.key,
+                        sref_of(node.parent),
+                        sref_of(node.left),
+                        sref_of(node.right),
+                        srefs[i],
+                        NodeBits::BLACK.with_red(node.red),
+                        srefs[i],
+                    ),
+                );
+            }
+        }
+        Self {
+            tree,
+            _nodes: buffer,
+            _root: root_sref,
+        }
+    }
+
+    /// Inserts `key` with `link` and returns its sref, failing as
+    /// [`RBTree::try_insert`] does. The node region is sized up front, so the
+    /// insert never grows an account.
+    pub fn insert<T: Copy + PartialOrd>(&mut self, key: T, link: u32) -> Result<u32, TreeError> {
+        let owner = Pubkey::default();
+        let mut lamports = 0;
+        // The account only stands in for the node region: it is already large
+        // enough, so inserts never reallocate or transfer.
+        let data = unsafe {
+            slice::from_raw_parts_mut(self.tree.entry as *mut u8, self.tree.region_len.get())
+        };
+        let account = AccountInfo::new(&owner, true, true, &mut lamports, data, &owner, false, 0);
+        self.tree
+            .try_insert(key, link, &account, &account, &account)
+    }
+
+    /// Panics unless the tree is a valid red-black tree: keys in order, parent
+    /// links consistent, a black root, no red node with a red child and the
+    /// same black height on every path. Returns the number of nodes.
+    pub fn assert_valid<T: Copy + PartialOrd + Debug>(&self) -> usize {
+        assert_valid_root(self.tree.get_root::<T>())
+    }
+
+    /// Renders the current tree back into the shape notation accepted by
+    /// [`TestTree::from_shape`].
+    pub fn to_shape<T: Copy + Display>(&self) -> String {
+        render(self.tree.get_root::<T>())
+    }
+
+    /// [`corrupt`] on this tree.
+    pub fn corrupt<T: Copy>(&self, sref: u32, corruption: Corruption) {
+        corrupt::<T>(&self.tree, sref, corruption)
+    }
+}
+
+/// Deliberate damage to one node, for checking that validators and
+/// corruption handling catch it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Corruption {
+    /// Turns a red node black or a black one red.
+    FlipColor,
+    /// Points the parent link at another sref.
+    Parent(u32),
+    /// Points the left child link at another sref. A free slot leaves the
+    /// child dangling; `NULL_NODE` cuts the subtree off.
+    Left(u32),
+    /// Points the right child link at another sref, like [`Corruption::Left`].
+    Right(u32),
+}
+
+/// Applies `corruption` to the node at `sref` of `tree`, bypassing every
+/// check. Only that node's slot is written, so the links of its neighbours
+/// are left pointing as before.
+///
+/// Panics if `sref` is outside the node region.
+pub fn corrupt<T: Copy>(tree: &RBTree, sref: u32, corruption: Corruption) {
+    assert!(
+        in_region::<T, u32>(sref, tree.region_len.get(), tree.stride::<T, u32>()),
+        "sref {sref} is outside the node region"
+    );
+    unsafe {
+        let node = slot::<T, u32>(tree.entry, sref, tree.stride::<T, u32>());
+        let mut value = node.read_unaligned();
+        match corruption {
+            Corruption::FlipColor => value.set_bits(value.bits().with_red(!value.bits().is_red())),
+            Corruption::Parent(parent) => value.set_parent_sref(parent),
+            Corruption::Left(left) => value.set_left_sref(left),
+            Corruption::Right(right) => value.set_right_sref(right),
+        }
+        node.write_unaligned(value);
+    }
+}
+
+/// [`TestTree::assert_valid`] for the tree under `root`, whatever its backing.
+pub fn assert_valid_root<T: Copy + PartialOrd + Debug>(root: NodePtr<T>) -> usize {
+    assert!(root.is_black_color(), "root is red");
+    assert!(root.parent().is_null(), "root has a parent");
+    check(root, None, None).1
+}
+
+/// Inserts the keys `0..N` into a [`StaticTree`] in every order, then removes
+/// them in every order, checking the red-black rules and the contents against
+/// a sorted model after each step. Panics on the first violation, naming the
+/// sequence that led to it. Returns the number of sequences run, `N!` squared.
+///
+/// Rebalancing bugs tend to need a particular shape to show up; at small `N`
+/// this reaches every shape the operations can produce.
+pub fn check_exhaustive<const N: usize>() -> usize {
+    let orders = permutations(N);
+    for inserts in &orders {
+        for removes in &orders {
+            let mut tree = StaticTree::<u64, N>::new();
+            let mut model: Vec<(u64, u32)> = Vec::new();
+            let step = |tree: &StaticTree<u64, N>, model: &[(u64, u32)], op: &str| {
+                let count =
+                    panic::catch_unwind(AssertUnwindSafe(|| assert_valid_root(tree.get_root())));
+                let contents: Vec<_> = tree.iter().collect();
+                assert!(
+                    count.as_ref().is_ok_and(|&count| count == model.len()) && contents == model,
+                    "after {op} inserting {inserts:?} and removing {removes:?}: got {contents:?}, expected {model:?}"
+                );
+            };
+            for (link, &key) in inserts.iter().enumerate() {
+                tree.insert(key, link as u32);
+                let at = model.partition_point(|&(k, _)| k < key);
+                model.insert(at, (key, link as u32));
+                step(&tree, &model, &format!("insert {key}"));
+            }
+            for &key in removes {
+                let at = model.partition_point(|&(k, _)| k < key);
+                let (_, link) = model.remove(at);
+                assert!(
+                    tree.remove(key) == link,
+                    "removing {key} after inserting {inserts:?} returned the wrong link"
+                );
+                step(&tree, &model, &format!("remove {key}"));
+            }
+        }
+    }
+    orders.len() * orders.len()
+}
+
+/// A single account serialized the way the runtime passes it to a program,
+/// with the spare bytes after the data that [`AccountInfo::realloc`] grows
+/// into. It holds more lamports than any size needs, so growing never
+/// transfers.
+pub struct GrowableAccount {
+    input: Vec<u64>,
+}
+
+impl GrowableAccount {
+    pub fn new(data_len: usize) -> Self {
+        let mut input = Vec::new();
+        input.extend(1u64.to_le_bytes());
+        input.extend([NON_DUP_MARKER, 1, 1, 0]);
+        // Original data length, filled in when the input is deserialized.
+        input.extend([0; size_of::<u32>()]);
+        input.extend(Pubkey::new_unique().to_bytes());
+        input.extend(Pubkey::new_unique().to_bytes());
+        input.extend((u64::MAX / 2).to_le_bytes());
+        input.extend((data_len as u64).to_le_bytes());
+        input.resize(input.len() + data_len + MAX_PERMITTED_DATA_INCREASE, 0);
+        input.resize(input.len().next_multiple_of(size_of::<u64>()), 0);
+        // Rent epoch and empty instruction data.
+        input.extend([0; 2 * size_of::<u64>()]);
+        input.extend(Pubkey::new_unique().to_bytes());
+
+        let mut words = vec![0u64; input.len().div_ceil(size_of::<u64>())];
+        unsafe {
+            ptr::copy_nonoverlapping(input.as_ptr(), words.as_mut_ptr() as *mut u8, input.len());
+        }
+        Self { input: words }
+    }
+
+    /// The account, able to grow by up to `MAX_PERMITTED_DATA_INCREASE` bytes
+    /// past its current length.
+    pub fn info(&mut self) -> AccountInfo<'_> {
+        let (_, mut accounts, _) =
+            unsafe { entrypoint::deserialize(self.input.as_mut_ptr() as *mut u8) };
+        accounts.remove(0)
+    }
+}
+
+/// Inserts into a tree while iterating over it, each insert growing the
+/// account it lives in, and checks that forward, reverse and range iterators
+/// yield every node in order, new ones included when they land between the
+/// two ends. `first`, `last` and cursors are checked against a model after
+/// the growth. Panics on the first mismatch; returns the number of inserts,
+/// all of which grew the account.
+pub fn check_realloc_stability(pt: MemoryMap) -> usize {
+    const ROOT: usize = size_of::<u64>();
+    let mut account = GrowableAccount::new(ROOT);
+    let info = account.info();
+    let base = info.data.borrow_mut().as_mut_ptr();
+    let tree = RBTree {
+        pt,
+        root: base as *mut u32,
+        entry: unsafe { base.add(ROOT) } as *mut u64,
+        non_tree_data_size: ROOT,
+        header: ptr::null_mut(),
+        region_len: Cell::new(0),
+        grown_from: Cell::new(None),
+    };
+    unsafe { *tree.root = NULL_NODE };
+    let mut model = BTreeMap::new();
+    let mut inserts = 0;
+    let mut insert = |tree: &RBTree, model: &mut BTreeMap<u64, u32>, key: u64| {
+        let len = info.data_len();
+        tree.try_insert(key, inserts, &info, &info, &info)
+            .expect("inserting into a growable account");
+        assert!(
+            info.data_len() > len,
+            "inserting {key} did not grow the account"
+        );
+        model.insert(key, inserts);
+        inserts += 1;
+        assert_eq!(tree.first(), model.first_key_value().map(|(&k, &l)| (k, l)));
+        assert_eq!(tree.last(), model.last_key_value().map(|(&k, &l)| (k, l)));
+    };
+
+    for key in (0..32).map(|i| 100 + 10 * i) {
+        insert(&tree, &mut model, key);
+    }
+    // Forward: a node is added just ahead of every other one yielded.
+    let mut seen = Vec::new();
+    for (key, link) in tree.iter::<u64>() {
+        seen.push((key, link));
+        if key % 20 == 0 {
+            insert(&tree, &mut model, key + 1);
+        }
+    }
+    assert_eq!(
+        seen,
+        model.iter().map(|(&k, &l)| (k, l)).collect::<Vec<_>>()
+    );
+    // Reverse: a node is added just behind every round key. The ones below
+    // 100, where the iterator started its far end, are not yielded.
+    let mut seen = Vec::new();
+    for (key, link) in tree.iter_rev::<u64>() {
+        if seen.is_empty() {
+            insert(&tree, &mut model, 0);
+        }
+        seen.push((key, link));
+        if key % 10 == 0 && key > 0 {
+            insert(&tree, &mut model, key - 5);
+        }
+    }
+    let expected: Vec<_> = model.range(100..).rev().map(|(&k, &l)| (k, l)).collect();
+    assert_eq!(seen, expected);
+    // Range: nodes added inside the bounds are yielded, ones outside are not.
+    let mut seen = Vec::new();
+    for (key, link) in tree.range::<u64, _>(200..300) {
+        seen.push((key, link));
+        if key % 10 == 0 {
+            insert(&tree, &mut model, key + 2);
+            insert(&tree, &mut model, key + 1000);
+        }
+    }
+    let expected: Vec<_> = model.range(200..300).map(|(&k, &l)| (k, l)).collect();
+    assert_eq!(seen, expected);
+
+    assert_eq!(assert_valid_root(tree.get_root::<u64>()), model.len());
+    let mut tree = tree;
+    let mut cursor = tree.cursor_front::<u64>();
+    let mut seen = Vec::new();
+    while let Some((key, link, _)) = cursor.current() {
+        seen.push((key, link));
+        cursor.move_next();
+    }
+    assert_eq!(
+        seen,
+        model.iter().map(|(&k, &l)| (k, l)).collect::<Vec<_>>()
+    );
+    inserts as usize
+}
+
+/// Every ordering of `0..n`.
+fn permutations(n: usize) -> Vec<Vec<u64>> {
+    if n == 0 {
+        return vec![Vec::new()];
+    }
+    let mut orders = Vec::new();
+    for shorter in permutations(n - 1) {
+        for at in 0..n {
+            let mut order = shorter.clone();
+            order.insert(at, n as u64 - 1);
+            orders.push(order);
+        }
+    }
+    orders
+}
+
+fn parse<T>(
+    tokens: &[&str],
+    pos: &mut usize,
+    parent: Option<usize>,
+    nodes: &mut Vec<ShapeNode<T>>,
+) -> Option<usize>
+where
+    T: FromStr,
+    T::Err: Debug,
+{
+    let token = *tokens.get(*pos).expect("unexpected end of shape");
+    *pos += 1;
+    match token {
+        "_" => None,
+        "(" => {
+            let index = parse_node(tokens.get(*pos).copied(), parent, nodes);
+            *pos += 1;
+            nodes[index].left = parse(tokens, pos, Some(index), nodes);
+            nodes[index].right = parse(tokens, pos, Some(index), nodes);
+            assert!(tokens.get(*pos) == Some(&")"), "expected `)` in shape");
+            *pos += 1;
+            Some(index)
+        }
+        _ => Some(parse_node(Some(token), parent, nodes)),
+    }
+}
+
+fn parse_node<T>(token: Option<&str>, parent: Option<usize>, nodes: &mut Vec<ShapeNode<T>>) -> usize
+where
+    T: FromStr,
+    T::Err: Debug,
+{
+    let token = token.expect("unexpected end of shape");
+    let red = match token.as_bytes().first() {
+        Some(b'R') => true,
+        Some(b'B') => false,
+        _ => panic!("node `{token}` must start with R or B"),
+    };
+    let key = token[1..].parse().expect("invalid key in shape");
+    nodes.push(ShapeNode {
+        key,
+        red,
+        parent,
+        left: None,
+        right: None,
+    });
+    nodes.len() - 1
+}
+
+fn check<T: Copy + PartialOrd + Debug>(
+    node: NodePtr<T>,
+    lo: Option<T>,
+    hi: Option<T>,
+) -> (usize, usize) {
+    if node.is_null() {
+        return (1, 0);
+    }
+    let key = node.key();
+    assert!(
+        lo.is_none_or(|lo| lo <= key),
+        "{key:?} is below its subtree bound"
+    );
+    assert!(
+        hi.is_none_or(|hi| key <= hi),
+        "{key:?} is above its subtree bound"
+    );
+    for child in [node.left(), node.right()] {
+        if !child.is_null() {
+            assert!(
+                child.parent() == node,
+                "{key:?} has a child with a wrong parent link"
+            );
+            assert!(
+                !(node.is_red_color() && child.is_red_color()),
+                "red {key:?} has a red child"
+            );
+        }
+    }
+    let (left_height, left_count) = check(node.left(), lo, Some(key));
+    let (right_height, right_count) = check(node.right(), Some(key), hi);
+    assert!(
+        left_height == right_height,
+        "black height differs below {key:?}"
+    );
+    (
+        left_height + node.is_black_color() as usize,
+        left_count + right_count + 1,
+    )
+}
+
+fn render<T: Copy + Display>(node: NodePtr<T>) -> String {
+    if node.is_null() {
+        return "_".to_string();
+    }
+    let color = if node.is_red_color() { 'R' } else { 'B' };
+    let label = format!("{color}{}", node.key());
+    if node.left().is_null() && node.right().is_null() {
+        return label;
+    }
+    format!("({label} {} {})", render(node.left()), render(node.right()))
+}
@@ -0,0 +1,187 @@
+//! Unchecked accessors for callers that have already validated the account
+//! (header, region length, allocator) and want the hot paths without the
+//! per-step null and bounds checks. They read single fields in place rather
+//! than copying whole nodes. The checked methods remain the default.
+
+use crate::{
+    bits::parent_sref_of, node_stride, slot, sref::widen, storage::Storage, NodePtr, RBTree,
+    NULL_NODE, UNBOUNDED_REGION,
+};
+use std::{cmp::Ordering, ptr};
+
+impl<T, V> NodePtr<T, V> {
+    /// Node `sref` of the region starting at `entry`, laid out as in accounts
+    /// without a header.
+    ///
+    /// # Safety
+    /// `sref` must be an allocated slot of a region starting at `entry`.
+    /// Neither it nor the nodes reached from it are bounds checked; see
+    /// [`RBTree::node`] for the checked lookup and [`NodePtr::get_bounded`]
+    /// for a region of known length.
+    #[deprecated(note = "use `RBTree::node`, or `NodePtr::get_bounded` for a bare region")]
+    pub unsafe fn get(entry: *mut u64, sref: u32) -> Self {
+        let stride = node_stride::<T, V>(false);
+        NodePtr(slot(entry, sref, stride), entry, UNBOUNDED_REGION, stride)
+    }
+
+    /// Node `sref` of the same region, without the bounds check.
+    #[inline]
+    unsafe fn at_unchecked(&self, sref: u32) -> Self {
+        if sref == NULL_NODE {
+            return Self::null();
+        }
+        NodePtr(slot(self.1, sref, self.3), self.1, self.2, self.3)
+    }
+
+    /// # Safety
+    /// `self` must point at a node inside the node region.
+    #[inline]
+    pub unsafe fn key_unchecked(&self) -> T
+    where
+        T: Copy,
+    {
+        ptr::addr_of!((*self.0).key).read_unaligned()
+    }
+
+    /// # Safety
+    /// `self` must point at a node inside the node region.
+    #[inline]
+    pub unsafe fn value_unchecked(&self) -> V
+    where
+        V: Copy,
+    {
+        ptr::addr_of!((*self.0).link).read_unaligned()
+    }
+
+    /// # Safety
+    /// `self` must point at a node inside the node region whose left link is
+    /// null or inside the region too.
+    #[inline]
+    pub unsafe fn left_unchecked(&self) -> Self {
+        self.at_unchecked(widen(ptr::addr_of!((*self.0).left).read_unaligned()))
+    }
+
+    /// # Safety
+    /// As [`NodePtr::left_unchecked`], for the right link.
+    #[inline]
+    pub unsafe fn right_unchecked(&self) -> Self {
+        self.at_unchecked(widen(ptr::addr_of!((*self.0).right).read_unaligned()))
+    }
+
+    /// # Safety
+    /// As [`NodePtr::left_unchecked`], for the parent link.
+    #[inline]
+    pub unsafe fn parent_unchecked(&self) -> Self {
+        self.at_unchecked(parent_sref_of(
+            ptr::addr_of!((*self.0).parent).read_unaligned(),
+        ))
+    }
+
+    /// In-order successor, or a null pointer for the largest node.
+    ///
+    /// # Safety
+    /// `self` must point at a node of a well-formed tree inside the node
+    /// region.
+    pub unsafe fn next_unchecked(self) -> Self {
+        let mut node = self.right_unchecked();
+        if !node.is_null() {
+            loop {
+                let left = node.left_unchecked();
+                if left.is_null() {
+                    return node;
+                }
+                node = left;
+            }
+        }
+        node = self;
+        let mut parent = node.parent_unchecked();
+        while !parent.is_null() && parent.right_unchecked().0 == node.0 {
+            node = parent;
+            parent = node.parent_unchecked();
+        }
+        parent
+    }
+
+    /// In-order predecessor, or a null pointer for the smallest node.
+    ///
+    /// # Safety
+    /// As [`NodePtr::next_unchecked`].
+    pub unsafe fn prev_unchecked(self) -> Self {
+        let mut node = self.left_unchecked();
+        if !node.is_null() {
+            loop {
+                let right = node.right_unchecked();
+                if right.is_null() {
+                    return node;
+                }
+                node = right;
+            }
+        }
+        node = self;
+        let mut parent = node.parent_unchecked();
+        while !parent.is_null() && parent.left_unchecked().0 == node.0 {
+            node = parent;
+            parent = node.parent_unchecked();
+        }
+        parent
+    }
+}
+
+impl<T> NodePtr<T> {
+    /// # Safety
+    /// `self` must point at a node inside the node region.
+    #[inline]
+    pub unsafe fn link_unchecked(&self) -> u32 {
+        self.value_unchecked()
+    }
+}
+
+impl RBTree {
+    /// [`RBTree::find_node`] without header, null or bounds checks.
+    ///
+    /// # Safety
+    /// The root word and every link on the search path must be null or
+    /// address a node inside the node region, e.g. because the account was
+    /// validated earlier in the instruction.
+    pub unsafe fn find_unchecked<T: Copy + Ord>(&self, key: T) -> NodePtr<T> {
+        let mut node = self.root_unchecked::<T>();
+        while !node.is_null() {
+            node = match key.cmp(&node.key_unchecked()) {
+                Ordering::Less => node.left_unchecked(),
+                Ordering::Greater => node.right_unchecked(),
+                Ordering::Equal => return node,
+            };
+        }
+        node
+    }
+
+    /// Node with the smallest key greater than `key`, or a null pointer if
+    /// there is none.
+    ///
+    /// # Safety
+    /// As [`RBTree::find_unchecked`].
+    pub unsafe fn successor_unchecked<T: Copy + Ord>(&self, key: T) -> NodePtr<T> {
+        let mut node = self.root_unchecked::<T>();
+        let mut successor = NodePtr::null();
+        while !node.is_null() {
+            if key < node.key_unchecked() {
+                successor = node;
+                node = node.left_unchecked();
+            } else {
+                node = node.right_unchecked();
+            }
+        }
+        successor
+    }
+
+    #[inline]
+    unsafe fn root_unchecked<T>(&self) -> NodePtr<T> {
+        NodePtr(
+            ptr::null_mut(),
+            self.entry,
+            self.region_len.get(),
+            self.stride::<T, u32>(),
+        )
+        .at_unchecked(*self.root)
+    }
+}
@@ -0,0 +1,167 @@
+//! Operation/result test vectors shared with other implementations of the
+//! tree (TypeScript client, indexers), and a runner for them.
+//!
+//! The corpus in [`TREE_VECTORS`] is plain text, one command per line:
+//!
+//! ```text
+//! # comment
+//! vector <name>
+//! insert <key>...
+//! remove <key>...
+//! expect <key>...
+//! hash <16 hex digits>
+//! ```
+//!
+//! Keys are `u64`. `insert` adds the keys in order, the n-th inserted key of a
+//! vector getting link n (duplicates are kept and go right of equal keys);
+//! `remove` deletes, for each key, the first node holding it that a search
+//! from the root reaches (nothing if absent). `expect` lists the keys left,
+//! in order. `hash` is the [`structural_hash`] of the final tree, which pins
+//! the exact shape and colors, not just the contents.
+
+use crate::{testing::TestTree, RBTree};
+use index_mem_alloc::MemoryMap;
+
+/// The vector corpus shipped with the crate.
+pub const TREE_VECTORS: &str = include_str!("../vectors/tree_ops.txt");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VectorOp {
+    Insert(u64),
+    Remove(u64),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Vector {
+    pub name: String,
+    pub ops: Vec<VectorOp>,
+    pub expect: Vec<u64>,
+    pub hash: u64,
+}
+
+/// Contents and hash of a tree after running a vector.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outcome {
+    pub keys: Vec<u64>,
+    pub hash: u64,
+}
+
+/// FNV-1a over a pre-order walk of the tree: every node contributes `0x01`,
+/// its color (`1` red, `0` black) and its key as 8 little-endian bytes, every
+/// empty child a single `0x00`.
+pub fn structural_hash(tree: &RBTree) -> u64 {
+    let mut bytes = Vec::new();
+    let mut stack = vec![tree.get_root::<u64>()];
+    while let Some(node) = stack.pop() {
+        if node.is_null() {
+            bytes.push(0);
+            continue;
+        }
+        bytes.push(1);
+        bytes.push(node.is_red_color() as u8);
+        bytes.extend_from_slice(&node.key().to_le_bytes());
+        stack.push(node.right());
+        stack.push(node.left());
+    }
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Parses a corpus in the format described in the module documentation.
+pub fn parse(text: &str) -> Result<Vec<Vector>, String> {
+    let mut vectors: Vec<Vector> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let error = |message: &str| format!("line {}: {message}", number + 1);
+        let keys = |words: std::str::SplitWhitespace| {
+            words
+                .map(|word| word.parse::<u64>().map_err(|_| error("invalid key")))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        if command == "vector" {
+            vectors.push(Vector {
+                name: words
+                    .next()
+                    .ok_or_else(|| error("missing name"))?
+                    .to_string(),
+                ops: Vec::new(),
+                expect: Vec::new(),
+                hash: 0,
+            });
+            continue;
+        }
+        let vector = vectors
+            .last_mut()
+            .ok_or_else(|| error("command outside a vector"))?;
+        match command {
+            "insert" => vector
+                .ops
+                .extend(keys(words)?.into_iter().map(VectorOp::Insert)),
+            "remove" => vector
+                .ops
+                .extend(keys(words)?.into_iter().map(VectorOp::Remove)),
+            "expect" => vector.expect = keys(words)?,
+            "hash" => {
+                let hash = words.next().ok_or_else(|| error("missing hash"))?;
+                vector.hash = u64::from_str_radix(hash, 16).map_err(|_| error("invalid hash"))?;
+            }
+            _ => return Err(error("unknown command")),
+        }
+    }
+    Ok(vectors)
+}
+
+/// Runs the operations of `vector` on an empty tree using allocator `pt`.
+pub fn execute(vector: &Vector, pt: MemoryMap) -> Outcome {
+    let capacity = vector.ops.len().max(1);
+    let mut test = TestTree::from_shape::<u64>(pt, capacity, "_");
+    let mut link = 0;
+    for op in &vector.ops {
+        match *op {
+            VectorOp::Insert(key) => {
+                test.insert(key, link)
+                    .expect("node region sized for every insert");
+                link += 1;
+            }
+            VectorOp::Remove(key) => {
+                test.tree
+                    .remove_entry(key)
+                    .expect("removal from a valid tree");
+            }
+        }
+    }
+    test.assert_valid::<u64>();
+    Outcome {
+        keys: test.tree.iter::<u64>().map(|(key, _)| key).collect(),
+        hash: structural_hash(&test.tree),
+    }
+}
+
+/// Parses and runs every vector in `text`, taking a fresh allocator for each
+/// from `new_map`. Returns the number of vectors run, or a description of
+/// the first mismatch.
+pub fn run(text: &str, mut new_map: impl FnMut() -> MemoryMap) -> Result<usize, String> {
+    let vectors = parse(text)?;
+    for vector in &vectors {
+        let outcome = execute(vector, new_map());
+        if outcome.keys != vector.expect {
+            return Err(format!(
+                "{}: expected keys {:?}, got {:?}",
+                vector.name, vector.expect, outcome.keys
+            ));
+        }
+        if outcome.hash != vector.hash {
+            return Err(format!(
+                "{}: expected hash {:016x}, got {:016x}",
+                vector.name, vector.hash, outcome.hash
+            ));
+        }
+    }
+    Ok(vectors.len())
+}
@@ -0,0 +1,246 @@
+use crate::{
+    node_stride, region_slots, Node, TreeError, TreeHeader, LAYOUT_EXACT_STRIDE, NULL_NODE,
+    ROOT_UNRECORDED, TREE_MAGIC, TREE_VERSION,
+};
+use std::{cmp::Ordering, marker::PhantomData, mem::size_of, ptr};
+
+/// Account layouts an off-chain [`TreeView`] can decode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayoutVersion {
+    /// Written before the tree header existed; the header bytes hold whatever
+    /// the program kept there.
+    PreHeader,
+    /// [`TreeHeader`] version 1.
+    V1,
+}
+
+/// Tells the layout of account `data` from the bytes at `header_offset`:
+/// without the header magic the account predates the header. A magic with an
+/// unknown version is an error rather than a guess.
+pub fn sniff_layout(data: &[u8], header_offset: usize) -> Result<LayoutVersion, TreeError> {
+    let header = data
+        .get(header_offset..header_offset + size_of::<TreeHeader>())
+        .ok_or(TreeError::InvalidLayout)?;
+    let header = unsafe { ptr::read_unaligned(header.as_ptr() as *const TreeHeader) };
+    match (header.magic, header.version) {
+        (TREE_MAGIC, TREE_VERSION) => Ok(LayoutVersion::V1),
+        (TREE_MAGIC, _) => Err(TreeError::InvalidHeader),
+        _ => Ok(LayoutVersion::PreHeader),
+    }
+}
+
+/// Read-only view of a tree in a copy of the account data, for indexers and
+/// other off-chain readers. It needs neither the allocator nor mutable
+/// access, accepts accounts written before the header was introduced, and
+/// bounds checks every node it reads, so a torn or corrupted snapshot ends
+/// iteration instead of reading out of bounds.
+///
+/// `V` is the per-node link, as in [`Node`]: trees storing values inline are
+/// read with their value type.
+pub struct TreeView<'a, T, V = u32> {
+    nodes: &'a [u8],
+    root: u32,
+    version: LayoutVersion,
+    header: Option<TreeHeader>,
+    /// Bytes from one node to the next.
+    stride: usize,
+    _entry: PhantomData<(T, V)>,
+}
+
+impl<'a, T: Copy, V: Copy> TreeView<'a, T, V> {
+    /// Decodes `data` laid out like the on-chain handle: the root sref at
+    /// `root_offset` and, for accounts that may carry one, the header at
+    /// `header_offset`, both within the first `non_tree_data_size` bytes.
+    pub fn new(
+        data: &'a [u8],
+        non_tree_data_size: usize,
+        root_offset: usize,
+        header_offset: Option<usize>,
+    ) -> Result<Self, TreeError> {
+        if data.len() < non_tree_data_size
+            || root_offset + size_of::<u32>() > non_tree_data_size
+            || header_offset
+                .is_some_and(|offset| offset + size_of::<TreeHeader>() > non_tree_data_size)
+        {
+            return Err(TreeError::InvalidLayout);
+        }
+        let version = match header_offset {
+            Some(offset) => sniff_layout(data, offset)?,
+            None => LayoutVersion::PreHeader,
+        };
+        let header = match (version, header_offset) {
+            (LayoutVersion::V1, Some(offset)) => {
+                Some(unsafe { ptr::read_unaligned(data[offset..].as_ptr() as *const TreeHeader) })
+            }
+            _ => None,
+        };
+        if let Some(header) = header {
+            if header.non_tree_data_size as usize != non_tree_data_size {
+                return Err(TreeError::NonTreeDataSizeMismatch {
+                    initialized: header.non_tree_data_size,
+                    given: non_tree_data_size,
+                });
+            }
+            header.check_layout()?;
+            header.check_key_size::<T>()?;
+            if header.root_offset != ROOT_UNRECORDED && header.root_offset as usize != root_offset {
+                return Err(TreeError::RootMismatch {
+                    initialized: header.root_offset,
+                    given: root_offset,
+                });
+            }
+        }
+        let region_start = header.map_or(non_tree_data_size, |header| header.region_start());
+        let nodes = data.get(region_start..).unwrap_or_default();
+        let root = unsafe { ptr::read_unaligned(data[root_offset..].as_ptr() as *const u32) };
+        Ok(Self {
+            nodes,
+            root,
+            version,
+            header,
+            stride: node_stride::<T, V>(
+                header.is_some_and(|header| header.node_layout & LAYOUT_EXACT_STRIDE != 0),
+            ),
+            _entry: PhantomData,
+        })
+    }
+
+    pub fn version(&self) -> LayoutVersion {
+        self.version
+    }
+
+    /// Header of the account, `None` for pre-header layouts.
+    pub fn header(&self) -> Option<TreeHeader> {
+        self.header
+    }
+
+    pub fn root_sref(&self) -> u32 {
+        self.root
+    }
+
+    /// Node stored at `sref`, or `None` if it lies outside the data.
+    pub fn node(&self, sref: u32) -> Option<Node<T, V>> {
+        if sref == NULL_NODE {
+            return None;
+        }
+        let start = sref as usize * self.stride;
+        let bytes = self.nodes.get(start..start + size_of::<Node<T, V>>())?;
+        Some(unsafe { ptr::read_unaligned(bytes.as_ptr() as *const Node<T, V>) })
+    }
+
+    /// Link stored under `key`. The search takes at most as many steps as the
+    /// data holds nodes, so links corrupted into a cycle end it with `None`.
+    pub fn get(&self, key: T) -> Option<V>
+    where
+        T: Ord,
+    {
+        let mut sref = self.root;
+        for _ in 0..self.capacity() {
+            let node = self.node(sref)?;
+            let node_key = node.key;
+            sref = match key.cmp(&node_key) {
+                Ordering::Less => node.left_sref(),
+                Ordering::Greater => node.right_sref(),
+                Ordering::Equal => return Some(node.link),
+            };
+        }
+        None
+    }
+
+    /// Iterates over `(key, link)` in ascending key order.
+    pub fn iter(&self) -> ViewIter<'_, 'a, T, V> {
+        let mut iter = self.start(false);
+        iter.descend(self.root);
+        iter
+    }
+
+    /// Iterates over `(key, link)` in descending key order.
+    pub fn iter_rev(&self) -> ViewIter<'_, 'a, T, V> {
+        let mut iter = self.start(true);
+        iter.descend(self.root);
+        iter
+    }
+
+    /// Iterates in ascending key order over the entries whose key is at least
+    /// `key`, without visiting the smaller ones.
+    pub fn iter_from(&self, key: T) -> ViewIter<'_, 'a, T, V>
+    where
+        T: Ord,
+    {
+        let mut iter = self.start(false);
+        let mut sref = self.root;
+        while let Some(node) = self.node(sref) {
+            if iter.stack.len() >= iter.remaining {
+                break;
+            }
+            let node_key = node.key;
+            if node_key >= key {
+                sref = node.left_sref();
+                iter.stack.push(node);
+            } else {
+                sref = node.right_sref();
+            }
+        }
+        iter
+    }
+
+    /// Nodes the data can hold.
+    pub(crate) fn capacity(&self) -> usize {
+        region_slots::<T, V>(self.nodes.len(), self.stride)
+    }
+
+    fn start(&self, rev: bool) -> ViewIter<'_, 'a, T, V> {
+        ViewIter {
+            view: self,
+            stack: Vec::new(),
+            remaining: self.capacity(),
+            rev,
+        }
+    }
+}
+
+/// In-order iterator returned by [`TreeView::iter`] and its variants. Keeps
+/// the path to the current node on a stack, as the view does not trust parent
+/// links.
+pub struct ViewIter<'v, 'a, T, V = u32> {
+    view: &'v TreeView<'a, T, V>,
+    stack: Vec<Node<T, V>>,
+    /// Nodes the data can hold; a cycle in corrupted links stops there.
+    remaining: usize,
+    /// Descending order.
+    rev: bool,
+}
+
+impl<T: Copy, V: Copy> ViewIter<'_, '_, T, V> {
+    fn descend(&mut self, mut sref: u32) {
+        while let Some(node) = self.view.node(sref) {
+            if self.stack.len() >= self.remaining {
+                return;
+            }
+            sref = if self.rev {
+                node.right_sref()
+            } else {
+                node.left_sref()
+            };
+            self.stack.push(node);
+        }
+    }
+}
+
+impl<T: Copy, V: Copy> Iterator for ViewIter<'_, '_, T, V> {
+    type Item = (T, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let node = self.stack.pop()?;
+        self.descend(if self.rev {
+            node.left_sref()
+        } else {
+            node.right_sref()
+        });
+        Some((node.key, node.link))
+    }
+}
@@ -0,0 +1,71 @@
+//! Node reads of batch and bounded operations, measured with
+//! `bench::trace_access`.
+
+use index_mem_alloc::MemoryMap;
+use rb_tree::{bench, testing::TestTree};
+
+/// Nodes of the measured trees.
+const NODES: usize = 1000;
+
+/// A tree of `NODES` random keys inserted in ascending order, so srefs follow
+/// key order as in a tree built in bulk, and the keys.
+fn ascending_tree(bitmap: &mut Vec<u8>) -> (TestTree, Vec<u64>) {
+    *bitmap = vec![0; NODES / 8];
+    let mut tree =
+        TestTree::from_shape::<u64>(MemoryMap::new(bitmap.as_mut_ptr(), NODES), NODES, "_");
+    let mut state = 11u64;
+    let mut keys: Vec<u64> = (0..NODES).map(|_| xorshift(&mut state)).collect();
+    keys.sort_unstable();
+    for (link, &key) in keys.iter().enumerate() {
+        tree.insert(key, link as u32).unwrap();
+    }
+    (tree, keys)
+}
+
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+#[test]
+fn remove_many_reads_fewer_nodes_than_single_removes() {
+    let (mut bitmap, mut other_bitmap) = (Vec::new(), Vec::new());
+    let (mut batch, keys) = ascending_tree(&mut bitmap);
+    let (mut single, _) = ascending_tree(&mut other_bitmap);
+    let mut state = 5u64;
+    let picked: Vec<u64> = (0..NODES / 10)
+        .map(|_| keys[xorshift(&mut state) as usize % NODES])
+        .collect();
+
+    let (_, one_by_one) = bench::trace_access(|| {
+        for &key in &picked {
+            single.tree.remove_entry(key).unwrap();
+        }
+    });
+    let (_, batched) = bench::trace_access(|| batch.tree.remove_many(&picked));
+    batch.assert_valid::<u64>();
+    assert!(batch.tree.iter::<u64>().eq(single.tree.iter::<u64>()));
+    assert!(batched.visits < one_by_one.visits);
+    assert!(batched.spread * 3 < one_by_one.spread * 2);
+}
+
+#[test]
+fn take_n_reads_the_path_and_the_entries_taken() {
+    let mut bitmap = Vec::new();
+    let (tree, keys) = ascending_tree(&mut bitmap);
+    let height = 2 * (NODES + 1).ilog2() as u64;
+    for k in [0, 1, 2, 5, 10] {
+        let (smallest, reads) = bench::trace_access(|| tree.tree.take_n_smallest::<u64>(k));
+        assert_eq!(smallest.len(), k);
+        assert_eq!(
+            smallest.last().map(|&(key, _)| key),
+            k.checked_sub(1).map(|i| keys[i])
+        );
+        assert!(reads.visits <= height + k as u64);
+        let (largest, reads) = bench::trace_access(|| tree.tree.take_n_largest::<u64>(k));
+        assert_eq!(largest.len(), k);
+        assert!(reads.visits <= height + k as u64);
+    }
+}
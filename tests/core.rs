@@ -0,0 +1,2071 @@
+//! Core checks of the tree, run under each node layout by `feature_matrix`.
+
+#[cfg(feature = "borsh")]
+use borsh::BorshDeserialize;
+use index_mem_alloc::MemoryMap;
+#[cfg(feature = "borsh")]
+use rb_tree::Snapshot;
+use rb_tree::{
+    build_account_image, merge_iter,
+    testing::{self, Corruption, GrowableAccount, TestTree},
+    vectors, AllocStats, Color, Interval, IntervalTree, KeyOf, KeyedTree, Link,
+    LinkConversionError, Node, Operation, Origin, Payer, Phase, RBTree, RBTreeMap, RBTreeReader,
+    RBTreeRef, RankTree, Removed, Rounding, Side, SumTree, TickSize, Tree, TreeError, TreeHeader,
+    TreeView, LAYOUT_EXACT_STRIDE, NODE_LAYOUT, NULL_ORDER, UNBOUNDED_REGION,
+};
+#[cfg(feature = "serde")]
+use serde_json::json;
+use solana_program::{
+    account_info::AccountInfo, entrypoint::MAX_PERMITTED_DATA_INCREASE, sysvar::rent::Rent,
+};
+use std::{
+    mem::size_of,
+    ops::{Bound, Range},
+};
+
+/// Slots of each allocator, more than any vector inserts.
+const SLOTS: usize = 1024;
+
+/// Zeroed storage for an allocator of `SLOTS` slots.
+fn bitmap() -> Vec<u8> {
+    vec![0; SLOTS / 8]
+}
+
+/// Allocator of `SLOTS` slots over `bitmap`, from the bitmap constructor of
+/// index-mem-alloc v0.1.4. The tests open every allocator here, so no other
+/// test depends on the bitmap layout.
+fn allocator(bitmap: &mut [u8]) -> MemoryMap {
+    MemoryMap::new(bitmap.as_mut_ptr(), SLOTS)
+}
+
+/// Non-tree data of an account formatted by `RBTree::init`: the header, the
+/// root word and the allocator storage of `SLOTS` slots, in that order.
+/// Returns its size and the allocator storage range.
+fn framed_layout() -> (usize, Range<usize>) {
+    let bitmap = size_of::<TreeHeader>() + size_of::<u32>();
+    let non_tree_data_size = bitmap + SLOTS / 8;
+    (non_tree_data_size, bitmap..non_tree_data_size)
+}
+
+/// Formats the data of `account` for a tree of `T` keys laid out as
+/// [`framed_layout`] says and opens it.
+fn framed_tree<T>(account: &AccountInfo) -> RBTree {
+    let (non_tree_data_size, bitmap) = framed_layout();
+    RBTree::init::<T>(
+        &mut account.data.borrow_mut(),
+        non_tree_data_size,
+        size_of::<TreeHeader>(),
+        Some(0),
+        bitmap,
+        SLOTS,
+    )
+    .unwrap()
+}
+
+/// A second handle on the tree of `tree`, opened with the wrong
+/// `non_tree_data_size`.
+fn misframed(tree: &RBTree) -> RBTree {
+    RBTree {
+        pt: tree.pt.clone(),
+        root: tree.root,
+        entry: tree.entry,
+        non_tree_data_size: tree.non_tree_data_size + 1,
+        header: tree.header,
+        region_len: tree.region_len.clone(),
+        grown_from: tree.grown_from.clone(),
+    }
+}
+
+/// A tree with its own root word `root` in the node region of `tree`,
+/// sharing its header and allocator as the trees of a `Forest` do.
+fn sibling(tree: &RBTree, root: &mut u32) -> RBTree {
+    *root = rb_tree::NULL_NODE;
+    RBTree {
+        pt: tree.pt.clone(),
+        root,
+        entry: tree.entry,
+        non_tree_data_size: tree.non_tree_data_size,
+        header: tree.header,
+        region_len: tree.region_len.clone(),
+        grown_from: tree.grown_from.clone(),
+    }
+}
+
+#[test]
+fn tree_vectors() {
+    let mut bitmaps: Vec<Vec<u8>> = Vec::new();
+    let ran = vectors::run(vectors::TREE_VECTORS, || {
+        bitmaps.push(bitmap());
+        allocator(bitmaps.last_mut().unwrap())
+    })
+    .unwrap();
+    assert!(ran > 0);
+}
+
+/// A shape, the key whose removal or insertion drives one fixup branch, and the
+/// shape expected afterwards.
+struct FixupCase {
+    name: &'static str,
+    shape: &'static str,
+    key: u64,
+    expected: &'static str,
+}
+
+/// Removing a black leaf for every sibling/nephew color combination under a
+/// black and a red parent, plus the red-sibling rotation, on both sides of
+/// `delete_fixup`.
+const DELETE_FIXUP_CASES: &[FixupCase] = &[
+    FixupCase {
+        name: "left: black parent, black nephews",
+        shape: "(B20 B10 B30)",
+        key: 10,
+        expected: "(B20 _ R30)",
+    },
+    FixupCase {
+        name: "left: red parent, black nephews",
+        shape: "(B50 (R20 B10 B30) B60)",
+        key: 10,
+        expected: "(B50 (B20 _ R30) B60)",
+    },
+    FixupCase {
+        name: "left: black parent, red near nephew",
+        shape: "(B20 B10 (B30 R25 _))",
+        key: 10,
+        expected: "(B25 B20 B30)",
+    },
+    FixupCase {
+        name: "left: red parent, red near nephew",
+        shape: "(B50 (R20 B10 (B30 R25 _)) B60)",
+        key: 10,
+        expected: "(B50 (R25 B20 B30) B60)",
+    },
+    FixupCase {
+        name: "left: black parent, red far nephew",
+        shape: "(B20 B10 (B30 _ R35))",
+        key: 10,
+        expected: "(B30 B20 B35)",
+    },
+    FixupCase {
+        name: "left: red parent, red far nephew",
+        shape: "(B50 (R20 B10 (B30 _ R35)) B60)",
+        key: 10,
+        expected: "(B50 (R30 B20 B35) B60)",
+    },
+    FixupCase {
+        name: "left: black parent, red nephews",
+        shape: "(B20 B10 (B30 R25 R35))",
+        key: 10,
+        expected: "(B30 (B20 _ R25) B35)",
+    },
+    FixupCase {
+        name: "left: red parent, red nephews",
+        shape: "(B50 (R20 B10 (B30 R25 R35)) B60)",
+        key: 10,
+        expected: "(B50 (R30 (B20 _ R25) B35) B60)",
+    },
+    FixupCase {
+        name: "left: red sibling",
+        shape: "(B20 B10 (R30 B25 B35))",
+        key: 10,
+        expected: "(B30 (B20 _ R25) B35)",
+    },
+    FixupCase {
+        name: "right: black parent, black nephews",
+        shape: "(B20 B10 B30)",
+        key: 30,
+        expected: "(B20 R10 _)",
+    },
+    FixupCase {
+        name: "right: red parent, black nephews",
+        shape: "(B50 (R20 B10 B30) B60)",
+        key: 30,
+        expected: "(B50 (B20 R10 _) B60)",
+    },
+    FixupCase {
+        name: "right: black parent, red near nephew",
+        shape: "(B20 (B10 _ R15) B30)",
+        key: 30,
+        expected: "(B15 B10 B20)",
+    },
+    FixupCase {
+        name: "right: red parent, red near nephew",
+        shape: "(B50 (R20 (B10 _ R15) B30) B60)",
+        key: 30,
+        expected: "(B50 (R15 B10 B20) B60)",
+    },
+    FixupCase {
+        name: "right: black parent, red far nephew",
+        shape: "(B20 (B10 R5 _) B30)",
+        key: 30,
+        expected: "(B10 B5 B20)",
+    },
+    FixupCase {
+        name: "right: red parent, red far nephew",
+        shape: "(B50 (R20 (B10 R5 _) B30) B60)",
+        key: 30,
+        expected: "(B50 (R10 B5 B20) B60)",
+    },
+    FixupCase {
+        name: "right: black parent, red nephews",
+        shape: "(B20 (B10 R5 R15) B30)",
+        key: 30,
+        expected: "(B10 B5 (B20 R15 _))",
+    },
+    FixupCase {
+        name: "right: red parent, red nephews",
+        shape: "(B50 (R20 (B10 R5 R15) B30) B60)",
+        key: 30,
+        expected: "(B50 (R10 B5 (B20 R15 _)) B60)",
+    },
+    FixupCase {
+        name: "right: red sibling",
+        shape: "(B20 (R10 B5 B15) B30)",
+        key: 30,
+        expected: "(B10 B5 (B20 R15 _))",
+    },
+];
+
+/// Red and black uncles with inner and outer children on both sides of
+/// `insert_fixup`, plus an insert under a black parent that needs no fixup.
+const INSERT_FIXUP_CASES: &[FixupCase] = &[
+    FixupCase {
+        name: "left: red uncle",
+        shape: "(B20 R10 R30)",
+        key: 5,
+        expected: "(B20 (B10 R5 _) B30)",
+    },
+    FixupCase {
+        name: "left: black uncle, outer child",
+        shape: "(B20 R10 _)",
+        key: 5,
+        expected: "(B10 R5 R20)",
+    },
+    FixupCase {
+        name: "left: black uncle, inner child",
+        shape: "(B20 R10 _)",
+        key: 15,
+        expected: "(B15 R10 R20)",
+    },
+    FixupCase {
+        name: "right: red uncle",
+        shape: "(B20 R10 R30)",
+        key: 35,
+        expected: "(B20 B10 (B30 _ R35))",
+    },
+    FixupCase {
+        name: "right: black uncle, outer child",
+        shape: "(B20 _ R30)",
+        key: 35,
+        expected: "(B30 R20 R35)",
+    },
+    FixupCase {
+        name: "right: black uncle, inner child",
+        shape: "(B20 _ R30)",
+        key: 25,
+        expected: "(B25 R20 R30)",
+    },
+    FixupCase {
+        name: "black parent",
+        shape: "(B20 B10 B30)",
+        key: 5,
+        expected: "(B20 (B10 R5 _) B30)",
+    },
+];
+
+/// Builds each case's shape, applies `op` and checks the result against the
+/// expected shape.
+fn run_fixup_cases(cases: &[FixupCase], op: impl Fn(&mut TestTree, u64)) {
+    for case in cases {
+        let mut bitmap = bitmap();
+        let mut test = TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, case.shape);
+        op(&mut test, case.key);
+        assert_eq!(test.to_shape::<u64>(), case.expected, "{}", case.name);
+        test.assert_valid::<u64>();
+    }
+}
+
+#[test]
+fn delete_fixup_cases() {
+    run_fixup_cases(DELETE_FIXUP_CASES, |test, key| {
+        assert!(test.tree.remove_entry(key).unwrap().is_some());
+    });
+}
+
+#[test]
+fn insert_fixup_cases() {
+    run_fixup_cases(INSERT_FIXUP_CASES, |test, key| {
+        test.insert(key, 0).unwrap();
+    });
+}
+
+#[test]
+fn summary_of_a_cyclic_tree_ends() {
+    let mut bitmap = bitmap();
+    let test = TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "(B20 B10 B30)");
+    let root = test.tree.get_root::<u64>();
+    test.corrupt::<u64>(root.left().sref(), Corruption::Left(root.sref()));
+    let summary = format!("{:?}", test.tree.summary::<u64>());
+    assert!(summary.contains("len: 16"), "{summary}");
+}
+
+#[test]
+fn view_search_through_a_cycle_ends() {
+    let (non_tree_data_size, bitmap) = framed_layout();
+    let root_offset = size_of::<TreeHeader>();
+    let entries = [(1u32, 10), (2, 20), (3, 30)];
+    let image = build_account_image(
+        &entries,
+        non_tree_data_size,
+        4,
+        root_offset,
+        Some(0),
+        bitmap.clone(),
+    )
+    .unwrap();
+    let mut account = GrowableAccount::new(image.len());
+    let info = account.info();
+    let pt = {
+        let mut data = info.data.borrow_mut();
+        data.copy_from_slice(&image);
+        MemoryMap::new(data[bitmap].as_mut_ptr(), 4)
+    };
+    let tree = RBTree::load::<u32>(&info, pt, 0).unwrap();
+    let root = tree.get_root::<u32>();
+    testing::corrupt::<u32>(&tree, root.left().sref(), Corruption::Left(root.sref()));
+
+    let data = info.data.borrow();
+    let view = TreeView::<u32>::new(&data, non_tree_data_size, root_offset, Some(0)).unwrap();
+    assert_eq!(view.get(2), Some(20));
+    assert_eq!(view.get(0), None);
+}
+
+#[test]
+fn key_comparisons_stop_at_their_visit_budget() {
+    let (mut first_bitmap, mut second_bitmap) = (bitmap(), bitmap());
+    let first = TestTree::from_shape::<u64>(allocator(&mut first_bitmap), 16, "(B20 B10 B30)");
+    let second = TestTree::from_shape::<u64>(allocator(&mut second_bitmap), 16, "(B20 R10 _)");
+    let both = first.tree.intersection_keys::<u64>(&second.tree, 8, 16);
+    assert_eq!((both.keys, both.done), (vec![10, 20], true));
+    let only = first.tree.difference_keys::<u64>(&second.tree, 8, 16);
+    assert_eq!((only.keys, only.done), (vec![30], true));
+
+    let cut = first.tree.intersection_keys::<u64>(&second.tree, 8, 2);
+    assert_eq!((cut.keys, cut.done), (vec![10], false));
+}
+
+#[test]
+fn delete_errors_carry_their_context() {
+    let mut bitmap = bitmap();
+    let mut test = TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "(B20 B10 B30)");
+    let missing = test.tree.try_remove(99u64).unwrap_err();
+    assert_eq!(missing.error, TreeError::KeyNotFound);
+    assert_eq!(
+        (missing.context.operation, missing.context.phase),
+        (Operation::Remove, Phase::Search)
+    );
+    assert_eq!(missing.context.key_bytes(), 99u64.to_ne_bytes());
+
+    let root = test.tree.get_root::<u64>();
+    let left = root.left();
+    test.corrupt::<u64>(left.sref(), Corruption::Left(root.sref()));
+    let broken = test.tree.try_delete(left).unwrap_err();
+    assert_eq!(broken.error, TreeError::Corrupted { sref: left.sref() });
+    assert_eq!(
+        (
+            broken.context.operation,
+            broken.context.phase,
+            broken.context.sref
+        ),
+        (Operation::Delete, Phase::Fixup, left.sref())
+    );
+}
+
+#[test]
+fn cancel_replace_reuses_the_slot() {
+    let mut bitmap = bitmap();
+    let mut test = TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "(B20 B10 B30)");
+    let sref = test.tree.find_node(20u64).sref();
+    let slot = test.tree.remove_and_reuse(20u64).unwrap().unwrap();
+    assert_eq!((slot.sref(), slot.link()), (sref, sref));
+    assert_eq!(test.tree.insert_reusing(slot, 25u64, 7).unwrap(), sref);
+    assert_eq!(test.assert_valid::<u64>(), 3);
+    assert_eq!(test.tree.iter::<u64>().nth(1), Some((25, 7)));
+    assert!(test.tree.pt.is_allocated(sref as usize));
+}
+
+#[test]
+fn slot_of_another_tree_is_refused() {
+    let (mut first_bitmap, mut second_bitmap) = (bitmap(), bitmap());
+    let mut first = TestTree::from_shape::<u64>(allocator(&mut first_bitmap), 16, "(B20 B10 B30)");
+    let mut second =
+        TestTree::from_shape::<u64>(allocator(&mut second_bitmap), 16, "(B20 B10 B30)");
+    let slot = first.tree.remove_and_reuse(10u64).unwrap().unwrap();
+    let sref = slot.sref();
+    assert_eq!(
+        second.tree.insert_reusing(slot, 15u64, 0),
+        Err(TreeError::ForeignSlot { sref })
+    );
+    assert_eq!(second.assert_valid::<u64>(), 3);
+}
+
+#[test]
+fn exhaustive_small_trees() {
+    assert_eq!(testing::check_exhaustive::<4>(), 576);
+}
+
+#[test]
+fn iteration_across_account_growth() {
+    let mut bitmap = bitmap();
+    assert!(testing::check_realloc_stability(allocator(&mut bitmap)) > 0);
+}
+
+#[test]
+fn only_headerless_v013_layouts_keep_the_rounded_stride() {
+    let node = size_of::<Node<u32>>();
+    let legacy = if NODE_LAYOUT == LAYOUT_EXACT_STRIDE {
+        node & !7
+    } else {
+        node
+    };
+    let entries = [(3u32, 30), (1, 10), (2, 20)];
+
+    let plain = build_account_image(&entries, 8, 4, 0, None, 4..5).unwrap();
+    assert_eq!(plain.len(), 8 + 3 * legacy + node);
+
+    let (non_tree_data_size, bitmap) = framed_layout();
+    let framed = build_account_image(
+        &entries,
+        non_tree_data_size,
+        4,
+        size_of::<TreeHeader>(),
+        Some(0),
+        bitmap.clone(),
+    )
+    .unwrap();
+    assert_eq!(
+        framed.len(),
+        RBTree::region_start(non_tree_data_size) + 4 * node
+    );
+    let view = TreeView::<u32>::new(
+        &framed,
+        non_tree_data_size,
+        size_of::<TreeHeader>(),
+        Some(0),
+    )
+    .unwrap();
+    assert_eq!({ view.header().unwrap().node_layout }, NODE_LAYOUT);
+    assert_eq!(view.iter().collect::<Vec<_>>(), [(1, 10), (2, 20), (3, 30)]);
+
+    // The image carries the allocator state of its nodes.
+    let mut image = framed;
+    let pt = MemoryMap::new(image[bitmap].as_mut_ptr(), 4);
+    assert_eq!((0..4).filter(|&slot| pt.is_allocated(slot)).count(), 3);
+}
+
+#[test]
+fn borrowed_tree_stays_within_its_data() {
+    let (non_tree_data_size, bitmap) = framed_layout();
+    let mut data = vec![0; RBTree::region_start(non_tree_data_size) + 3 * size_of::<Node<u64>>()];
+    let mut tree = RBTreeRef::<u64>::init(
+        &mut data,
+        non_tree_data_size,
+        size_of::<TreeHeader>(),
+        Some(0),
+        bitmap,
+        SLOTS,
+    )
+    .unwrap();
+    let mut key = 0;
+    let full = loop {
+        key += 10;
+        if let Err(error) = tree.insert(key, key as u32) {
+            break error;
+        }
+    };
+    assert!(matches!(full, TreeError::NodeLimit { .. }), "{full:?}");
+    let len = tree.len();
+    assert!(len >= 2);
+
+    let before = tree.header().unwrap().sequence;
+    assert_eq!(tree.upsert(10, 11).unwrap(), Some(10));
+    assert_eq!(
+        tree.entry(10).unwrap().and_modify(|link| *link += 1).get(),
+        Some(12)
+    );
+    if cfg!(feature = "counters") {
+        // Rewriting a link is a change readers must see.
+        assert_eq!({ tree.header().unwrap().sequence }, before + 2);
+    }
+    assert_eq!(tree.entry(5).unwrap().get(), None);
+    assert_eq!(tree.floor(15), Some((10, 12)));
+    assert_eq!(tree.ceiling(15), Some((20, 20)));
+    assert_eq!(tree.predecessor(10), None);
+
+    let removed = tree.remove_range(..=10, |_| {}).unwrap();
+    assert_eq!((removed, tree.len()), (1, len - 1));
+    let sref = tree.entry(5).unwrap().or_insert(50).unwrap();
+    assert_eq!(tree.delete_by_sref(sref).unwrap().map(|r| r.key), Some(5));
+    assert_eq!(tree.pop_first().unwrap(), Some((20, 20)));
+}
+
+#[test]
+fn clear_frees_every_slot() {
+    let (non_tree_data_size, bitmap) = framed_layout();
+    let mut data = vec![0; RBTree::region_start(non_tree_data_size) + 3 * size_of::<Node<u64>>()];
+    let mut tree = RBTreeRef::<u64>::init(
+        &mut data,
+        non_tree_data_size,
+        size_of::<TreeHeader>(),
+        Some(0),
+        bitmap,
+        SLOTS,
+    )
+    .unwrap();
+    let filled =
+        |tree: &mut RBTreeRef<u64>| (1..).take_while(|&key| tree.insert(key, 0).is_ok()).count();
+    let len = filled(&mut tree);
+    assert!(len >= 2);
+    tree.clear().unwrap();
+    assert_eq!(
+        ({ tree.header().unwrap().node_count }, tree.first()),
+        (0, None)
+    );
+    // Every slot is free again.
+    assert_eq!(filled(&mut tree), len);
+}
+
+#[cfg(feature = "strict-handles")]
+#[test]
+fn overlapping_handles_are_refused() {
+    let (non_tree_data_size, bitmap) = framed_layout();
+    let mut data = vec![0; RBTree::region_start(non_tree_data_size)];
+    let tree = RBTree::init::<u64>(
+        &mut data,
+        non_tree_data_size,
+        size_of::<TreeHeader>(),
+        Some(0),
+        bitmap.clone(),
+        SLOTS,
+    )
+    .unwrap();
+    let pt = tree.pt.clone();
+    assert!(matches!(
+        RBTreeRef::<u64>::load(&mut data, pt.clone(), 0),
+        Err(TreeError::AliasedHandle)
+    ));
+    drop(tree);
+    assert!(RBTreeRef::<u64>::load(&mut data, pt, 0).is_ok());
+}
+
+#[test]
+fn key_range_with_minimum_above_maximum_is_refused() {
+    let (non_tree_data_size, bitmap) = framed_layout();
+    let mut data = vec![0; RBTree::region_start(non_tree_data_size)];
+    let tree = RBTree::init::<u64>(
+        &mut data,
+        non_tree_data_size,
+        size_of::<TreeHeader>(),
+        Some(0),
+        bitmap,
+        SLOTS,
+    )
+    .unwrap();
+    assert_eq!(tree.set_key_range(9u64, 3), Err(TreeError::EmptyKeyRange));
+    assert_eq!(tree.key_range::<u64>(), Ok(None));
+    assert_eq!(tree.set_key_range(3u64, 3), Ok(()));
+    assert_eq!(tree.key_range::<u64>(), Ok(Some((3, 3))));
+}
+
+#[test]
+fn init_clears_the_allocator_storage() {
+    let (non_tree_data_size, bitmap) = framed_layout();
+    let mut data = vec![0; RBTree::region_start(non_tree_data_size)];
+    data[bitmap.clone()].fill(0xff);
+    let tree = RBTree::init::<u64>(
+        &mut data,
+        non_tree_data_size,
+        size_of::<TreeHeader>(),
+        Some(0),
+        bitmap.clone(),
+        SLOTS,
+    )
+    .unwrap();
+    assert!((0..SLOTS).all(|slot| !tree.pt.is_allocated(slot)));
+    assert_eq!(
+        RBTree::init::<u64>(&mut data, non_tree_data_size, 0, None, 2..8, SLOTS).err(),
+        Some(TreeError::InvalidLayout)
+    );
+}
+
+#[test]
+fn augmented_trees_report_errors_as_results() {
+    let (non_tree_data_size, bitmap) = framed_layout();
+    let mut account = GrowableAccount::new(RBTree::region_start(non_tree_data_size));
+    let info = account.info();
+    let tree = RBTree::init::<Interval<u64>>(
+        &mut info.data.borrow_mut(),
+        non_tree_data_size,
+        size_of::<TreeHeader>(),
+        Some(0),
+        bitmap,
+        SLOTS,
+    )
+    .unwrap();
+    let mut intervals = IntervalTree::new(tree);
+    assert_eq!(
+        intervals.insert(Interval::new(9u64, 3), 1, &info, &info, &info),
+        Err(TreeError::EmptyInterval)
+    );
+    intervals
+        .insert(Interval::new(3u64, 9), 2, &info, &info, &info)
+        .unwrap();
+    assert_eq!(
+        intervals.find_overlapping(5..6),
+        Some((Interval::new(3, 9), 2))
+    );
+    assert_eq!(intervals.remove(Interval::new(4, 9)), Ok(None));
+    assert_eq!(intervals.remove(Interval::new(3, 9)), Ok(Some(2)));
+    assert_eq!(intervals.find_overlapping(..), None);
+}
+
+#[test]
+fn map_refuses_to_overwrite_through_a_misframed_handle() {
+    let mut account = GrowableAccount::new(RBTree::region_start(framed_layout().0));
+    let info = account.info();
+    let tree = framed_tree::<u64>(&info);
+    let mut map = RBTreeMap::<u64, u64>::new(misframed(&tree), &info, &info, &info);
+    let mut good = RBTreeMap::<u64, u64>::new(tree, &info, &info, &info);
+    assert_eq!(good.insert(7, 70).unwrap(), None);
+    let refused = map.insert(7, 71).unwrap_err();
+    assert_eq!(
+        (refused.error, refused.context.phase),
+        (
+            TreeError::NonTreeDataSizeMismatch {
+                initialized: framed_layout().0 as u32,
+                given: framed_layout().0 + 1
+            },
+            Phase::Validate
+        )
+    );
+    assert_eq!(good.get(&7), Some(70));
+    assert_eq!(good.insert(7, 72).unwrap(), Some(70));
+}
+
+#[test]
+fn entries_check_the_header_before_the_search() {
+    let mut account = GrowableAccount::new(RBTree::region_start(framed_layout().0));
+    let info = account.info();
+    let mut tree = framed_tree::<u64>(&info);
+    tree.upsert(7u64, 70, &info, &info, &info).unwrap();
+    let mut other = misframed(&tree);
+    let mismatch = TreeError::NonTreeDataSizeMismatch {
+        initialized: framed_layout().0 as u32,
+        given: framed_layout().0 + 1,
+    };
+    assert_eq!(other.entry(7u64).err(), Some(mismatch));
+    let refused = other.upsert(7u64, 71, &info, &info, &info).unwrap_err();
+    assert_eq!(
+        (refused.error, refused.context.phase),
+        (mismatch, Phase::Validate)
+    );
+    assert_eq!(
+        tree.entry(7u64)
+            .unwrap()
+            .and_modify(|link| *link += 1)
+            .key(),
+        7
+    );
+    assert_eq!(
+        tree.upsert(7u64, 72, &info, &info, &info).unwrap(),
+        Some(71)
+    );
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Order {
+    price: u64,
+    quantity: u64,
+}
+
+impl KeyOf for Order {
+    type Key = u64;
+
+    fn key_of(&self) -> u64 {
+        self.price
+    }
+}
+
+#[test]
+fn keyed_trees_check_the_header_before_the_search() {
+    let mut account = GrowableAccount::new(RBTree::region_start(framed_layout().0));
+    let info = account.info();
+    let tree = KeyedTree::<Order>::new(framed_tree::<u64>(&info));
+    let mut other = KeyedTree::<Order>::new(misframed(tree.tree()));
+    let order = |quantity| Order {
+        price: 100,
+        quantity,
+    };
+    tree.insert(order(1), &info, &info, &info).unwrap();
+    let mismatch = TreeError::NonTreeDataSizeMismatch {
+        initialized: framed_layout().0 as u32,
+        given: framed_layout().0 + 1,
+    };
+    assert_eq!(other.replace(order(2)), Err(mismatch));
+    assert_eq!(other.remove(100), Err(mismatch));
+    assert_eq!(other.remove(5), Err(mismatch));
+    assert_eq!(tree.replace(order(3)), Ok(Some(order(1))));
+    assert_eq!(tree.get(100), Some(order(3)));
+}
+
+#[test]
+fn growth_stops_at_the_realloc_limit_of_the_instruction() {
+    let region_start = RBTree::region_start(framed_layout().0);
+    let mut account = GrowableAccount::new(region_start);
+    let info = account.info();
+    let tree = framed_tree::<u64>(&info);
+    let nodes = 3 * MAX_PERMITTED_DATA_INCREASE / (2 * size_of::<Node<u64>>());
+    let entries: Vec<(u64, u32)> = (0..nodes as u64).map(|key| (key, key as u32)).collect();
+    let refused = tree.insert_many(&entries, &info, &info, &info).unwrap_err();
+    assert_eq!(refused.error, TreeError::GrowthLimit { instructions: 2 });
+    assert_eq!((info.data_len(), tree.len::<u64>()), (region_start, 0));
+
+    // Growth is counted from the length the handle first saw, so a second
+    // call in the same instruction gains nothing.
+    assert_eq!(tree.grow_toward::<u64>(nodes, &info, &info, &info), Ok(1));
+    assert_eq!(info.data_len(), region_start + MAX_PERMITTED_DATA_INCREASE);
+    assert_eq!(tree.grow_toward::<u64>(nodes, &info, &info, &info), Ok(1));
+    assert_eq!(info.data_len(), region_start + MAX_PERMITTED_DATA_INCREASE);
+}
+
+#[test]
+fn capacity_counts_from_the_aligned_region() {
+    let node = size_of::<Node<u64>>();
+    assert_eq!(RBTree::capacity_from_account::<u64>(6 + node, 6), 0);
+    let region_start = RBTree::region_start(6);
+    assert_eq!(
+        RBTree::capacity_from_account::<u64>(region_start + node, 6),
+        1
+    );
+    assert_eq!(
+        RBTree::capacity_from_account::<u64>(region_start + 3 * node - 1, 6),
+        2
+    );
+    assert_eq!(RBTree::capacity_from_account::<u64>(3, 6), 0);
+}
+
+#[test]
+fn slab_repair_needs_an_allocator_of_its_own() {
+    let mut bitmap = bitmap();
+    let test = TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "(B20 B10 B30)");
+    assert_eq!(
+        test.tree.repair_from_slab::<u64>(16),
+        Err(TreeError::SharedAllocator)
+    );
+    assert_eq!(test.to_shape::<u64>(), "(B20 B10 B30)");
+
+    let mut account = GrowableAccount::new(RBTree::region_start(framed_layout().0));
+    let info = account.info();
+    let tree = framed_tree::<u64>(&info);
+    for key in [5u64, 1, 9, 3, 7] {
+        tree.try_insert(key, key as u32, &info, &info, &info)
+            .unwrap();
+    }
+    unsafe { *tree.root = rb_tree::NULL_NODE };
+    assert_eq!(tree.repair_from_slab::<u64>(SLOTS as u32), Ok(5));
+    let keys: Vec<u64> = tree.iter::<u64>().map(|(key, _)| key).collect();
+    assert_eq!(keys, [1, 3, 5, 7, 9]);
+}
+
+#[test]
+fn reserve_is_bounded_by_the_allocator() {
+    const FEW: usize = 8;
+    let (non_tree_data_size, bitmap) = framed_layout();
+    let region_start = RBTree::region_start(non_tree_data_size);
+    let mut account = GrowableAccount::new(region_start);
+    let info = account.info();
+    let tree = RBTree::init::<u64>(
+        &mut info.data.borrow_mut(),
+        non_tree_data_size,
+        size_of::<TreeHeader>(),
+        Some(0),
+        bitmap,
+        FEW,
+    )
+    .unwrap();
+
+    assert_eq!(
+        tree.reserve::<u64>(FEW + 1, &info, &info, &info),
+        Err(TreeError::AllocFailed)
+    );
+    assert_eq!(info.data_len(), region_start);
+
+    tree.reserve::<u64>(FEW, &info, &info, &info).unwrap();
+    let reserved = info.data_len();
+    assert_eq!(
+        RBTree::capacity_from_account::<u64>(reserved, non_tree_data_size),
+        FEW
+    );
+    for key in 0..FEW as u64 {
+        tree.try_insert(key, 0, &info, &info, &info).unwrap();
+    }
+    assert_eq!((info.data_len(), tree.len::<u64>()), (reserved, FEW));
+}
+
+#[test]
+fn remove_many_takes_one_duplicate_per_repeat() {
+    let mut bitmap = bitmap();
+    let mut test = TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "(B20 B10 B30)");
+    for link in [21, 22] {
+        test.insert(20u64, link).unwrap();
+    }
+    let links = test
+        .tree
+        .remove_many(&[20u64, 30, 20, 5, 20, 20, 5])
+        .unwrap();
+    let mut taken = [links[0], links[2], links[4], links[5]];
+    taken.sort_unstable();
+    assert_eq!(taken, [0, 21, 22, rb_tree::NULL_NODE]);
+    assert_eq!(
+        (links[1], links[3], links[6]),
+        (2, rb_tree::NULL_NODE, rb_tree::NULL_NODE)
+    );
+    assert_eq!(test.to_shape::<u64>(), "B10");
+    test.assert_valid::<u64>();
+}
+
+#[test]
+fn remove_first_drains_a_key_oldest_first() {
+    let mut bitmap = bitmap();
+    let mut test = TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "(B20 B10 B30)");
+    for link in [rb_tree::NULL_NODE, 7] {
+        test.insert(20u64, link).unwrap();
+    }
+    let drained: Vec<_> = (0..4)
+        .map(|_| test.tree.remove_first(20u64).unwrap())
+        .collect();
+    assert_eq!(drained, [Some(0), Some(rb_tree::NULL_NODE), Some(7), None]);
+    assert_eq!(test.tree.remove_first(5u64), Ok(None));
+    test.assert_valid::<u64>();
+}
+
+#[test]
+fn normalized_keys_are_found_or_rejected() {
+    let mut account = GrowableAccount::new(RBTree::region_start(framed_layout().0));
+    let info = account.info();
+    let tree = framed_tree::<u64>(&info);
+    let down = TickSize::new(10, Rounding::Down);
+    let exact = TickSize::new(10, Rounding::Exact);
+    let sref = tree
+        .insert_normalized(107u64, 1, &down, &info, &info, &info)
+        .unwrap();
+    assert_eq!(
+        tree.find_normalized(103u64, &down).map(|node| node.sref()),
+        Ok(sref)
+    );
+    assert_eq!(
+        tree.find_normalized(100u64, &exact).map(|node| node.key()),
+        Ok(100)
+    );
+    assert!(tree.find_normalized(90u64, &exact).unwrap().is_null());
+    assert_eq!(
+        tree.find_normalized(103u64, &exact).map(|node| node.sref()),
+        Err(TreeError::NonConformingKey)
+    );
+    let refused = tree
+        .insert_normalized(103u64, 2, &exact, &info, &info, &info)
+        .unwrap_err();
+    assert_eq!(
+        (refused.error, refused.context.phase),
+        (TreeError::NonConformingKey, Phase::Validate)
+    );
+}
+
+/// Drains `iter` alternating between its ends, starting at the back if
+/// `back_first`, and returns the items in front-to-back order.
+fn drain_alternating<I: DoubleEndedIterator>(mut iter: I, back_first: bool) -> Vec<I::Item> {
+    let (mut front, mut back) = (Vec::new(), Vec::new());
+    let mut from_back = back_first;
+    loop {
+        let item = if from_back {
+            iter.next_back()
+        } else {
+            iter.next()
+        };
+        let Some(item) = item else {
+            break;
+        };
+        if from_back {
+            back.push(item);
+        } else {
+            front.push(item);
+        }
+        from_back = !from_back;
+    }
+    assert!(iter.next().is_none() && iter.next_back().is_none());
+    front.extend(back.into_iter().rev());
+    front
+}
+
+#[test]
+fn iterators_meet_in_the_middle() {
+    for len in [0u64, 1, 2, 3, 8, 9] {
+        let mut bitmap = bitmap();
+        let mut test = TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "_");
+        let mut srefs = Vec::new();
+        // Scrambled insertion order, so srefs and key order differ.
+        for i in 0..len {
+            let key = (i * 5) % len;
+            srefs.push(test.insert(key, key as u32 * 10).unwrap());
+        }
+        let entries: Vec<(u64, u32)> = (0..len).map(|key| (key, key as u32 * 10)).collect();
+        srefs.sort_unstable();
+        for back_first in [false, true] {
+            assert_eq!(
+                drain_alternating(test.tree.iter::<u64>(), back_first),
+                entries
+            );
+            assert_eq!(
+                drain_alternating(test.tree.range::<u64, _>(..), back_first),
+                entries
+            );
+            let slab: Vec<u32> = drain_alternating(test.tree.iter_slab::<u64>(16), back_first)
+                .iter()
+                .map(|node| node.sref())
+                .collect();
+            assert_eq!(slab, srefs);
+        }
+    }
+}
+
+#[test]
+fn take_n_stops_at_either_end() {
+    let mut bitmap = bitmap();
+    let mut test = TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "_");
+    assert!(test.tree.take_n_smallest::<u64>(3).is_empty());
+    for key in [5u64, 1, 8, 3, 9, 2, 7, 4, 6] {
+        test.insert(key, key as u32).unwrap();
+    }
+    let ascending: Vec<(u64, u32)> = (1..=9).map(|key| (key, key as u32)).collect();
+    let descending: Vec<(u64, u32)> = ascending.iter().rev().copied().collect();
+    for k in [0, 1, 4, 9, 20] {
+        assert_eq!(test.tree.take_n_smallest::<u64>(k), ascending[..k.min(9)]);
+        assert_eq!(test.tree.take_n_largest::<u64>(k), descending[..k.min(9)]);
+    }
+}
+
+#[test]
+fn extreme_entries_read_without_node_pointers() {
+    let mut bitmap = bitmap();
+    let mut test = TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "_");
+    assert_eq!(
+        (test.tree.min_key::<u64>(), test.tree.max_entry::<u64>()),
+        (None, None)
+    );
+    for key in [40u64, 10, 30, 20] {
+        test.insert(key, key as u32 + 1).unwrap();
+    }
+    assert_eq!(
+        (test.tree.min_key(), test.tree.max_key()),
+        (Some(10u64), Some(40u64))
+    );
+    assert_eq!(test.tree.min_entry(), Some((10u64, 11)));
+    assert_eq!(test.tree.max_entry(), Some((40u64, 41)));
+}
+
+#[test]
+fn neighbours_of_a_kept_sref() {
+    let mut bitmap = bitmap();
+    let mut test = TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "(B20 B10 B30)");
+    let cursor = test.tree.find_node(20u64).sref();
+    assert_eq!(test.tree.next_after_sref::<u64>(cursor).key(), 30);
+    assert_eq!(test.tree.prev_before_sref::<u64>(cursor).key(), 10);
+    let last = test.tree.find_node(30u64).sref();
+    assert!(test.tree.next_after_sref::<u64>(last).is_null());
+
+    // A cursor whose node was removed, or that never named one, leads nowhere.
+    test.tree.remove_entry(20u64).unwrap();
+    assert!(test.tree.next_after_sref::<u64>(cursor).is_null());
+    assert!(test.tree.prev_before_sref::<u64>(cursor).is_null());
+    assert!(test.tree.next_after_sref::<u64>(SLOTS as u32).is_null());
+}
+
+#[test]
+fn levels_aggregate_in_one_walk() {
+    let mut bitmap = bitmap();
+    let mut test = TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "_");
+    for (link, key) in [101u64, 205, 103, 150, 201, 310].into_iter().enumerate() {
+        test.insert(key, link as u32).unwrap();
+    }
+    let level = |key: u64| key / 100;
+    assert_eq!(test.tree.aggregate_levels(2, level), [(1, 3, 0), (2, 2, 4)]);
+    assert_eq!(
+        test.tree.aggregate_levels_rev(5, level),
+        [(3, 1, 5), (2, 2, 1), (1, 3, 3)]
+    );
+    assert!(test.tree.aggregate_levels(0, level).is_empty());
+}
+
+#[test]
+fn unique_inserts_report_the_node_in_the_way() {
+    let mut account = GrowableAccount::new(RBTree::region_start(framed_layout().0));
+    let info = account.info();
+    let tree = framed_tree::<u64>(&info);
+    let sref = tree.insert_unique(8u64, 80, &info, &info, &info).unwrap();
+    let duplicate = tree
+        .insert_unique(8u64, 81, &info, &info, &info)
+        .unwrap_err();
+    assert_eq!(
+        (
+            duplicate.error,
+            duplicate.context.phase,
+            duplicate.context.sref
+        ),
+        (
+            TreeError::DuplicateKey { sref, link: 80 },
+            Phase::Search,
+            sref
+        )
+    );
+    assert_eq!(tree.len::<u64>(), 1);
+
+    let refused = misframed(&tree)
+        .insert_unique(9u64, 90, &info, &info, &info)
+        .unwrap_err();
+    assert_eq!(refused.context.phase, Phase::Validate);
+    assert!(matches!(
+        refused.error,
+        TreeError::NonTreeDataSizeMismatch { .. }
+    ));
+    assert_eq!(tree.len::<u64>(), 1);
+}
+
+#[test]
+fn links_resolve_during_the_walk() {
+    let mut bitmap = bitmap();
+    let mut test = TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "_");
+    let records = ["ask", "bid", "stop"];
+    for (link, key) in [30u64, 10, 20].into_iter().enumerate() {
+        test.insert(key, link as u32).unwrap();
+    }
+    let mut resolved = 0;
+    let walked: Vec<(u64, &str)> = test
+        .tree
+        .iter_with(|link| {
+            resolved += 1;
+            records[link as usize]
+        })
+        .collect();
+    assert_eq!(walked, [(10, "bid"), (20, "stop"), (30, "ask")]);
+    assert_eq!(resolved, 3);
+}
+
+#[test]
+fn slot_audit_classifies_every_slot() {
+    let mut bitmap = bitmap();
+    let mut test = TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "(B20 B10 B30)");
+    // A slot taken from the allocator but never written.
+    let stray = test.tree.pt.clone().alloc().unwrap() as u32;
+    let removed = test.tree.remove_entry(10u64).unwrap().unwrap();
+    let audit = test.tree.audit_slots::<u64>(16);
+    assert_eq!((audit.allocated, audit.free), (3, 13));
+    assert_eq!((audit.stale, audit.mismatched), (1, vec![stray]));
+    assert!(!test.tree.pt.is_allocated(removed.sref as usize));
+}
+
+#[test]
+fn header_tracks_the_last_mutation() {
+    let mut account = GrowableAccount::new(RBTree::region_start(framed_layout().0));
+    let info = account.info();
+    let mut tree = framed_tree::<u64>(&info);
+    let sequence = |tree: &RBTree| tree.header().unwrap().sequence;
+    let start = sequence(&tree);
+    tree.try_insert(5u64, 50, &info, &info, &info).unwrap();
+    tree.try_insert(6u64, 60, &info, &info, &info).unwrap();
+    tree.remove_entry(5u64).unwrap();
+    tree.mark_slot(1_234);
+    // Failed mutations leave no trace.
+    assert!(misframed(&tree)
+        .try_insert(7u64, 70, &info, &info, &info)
+        .is_err());
+    assert!(tree.try_remove(9u64).is_err());
+
+    let bumps = if cfg!(feature = "counters") { 3 } else { 0 };
+    assert_eq!(sequence(&tree), start + bumps);
+    assert_eq!({ tree.header().unwrap().last_slot }, 1_234);
+}
+
+#[test]
+fn best_price_respects_the_side_of_the_limit() {
+    let mut storage = bitmap();
+    let empty = TestTree::from_shape::<u64>(allocator(&mut storage), 16, "_");
+    assert!(empty
+        .tree
+        .best_price_at_or_better(u64::MAX, Side::Ask)
+        .is_null());
+
+    let mut bitmap = bitmap();
+    let test = TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "(B20 B10 B30)");
+    let best = |limit: u64, side| test.tree.best_price_at_or_better(limit, side);
+    assert_eq!(best(15, Side::Ask).key(), 10);
+    assert_eq!(best(10, Side::Ask).key(), 10);
+    assert!(best(9, Side::Ask).is_null());
+    assert_eq!(best(25, Side::Bid).key(), 30);
+    assert_eq!(best(30, Side::Bid).key(), 30);
+    assert!(best(31, Side::Bid).is_null());
+}
+
+#[test]
+fn link_conversions_refuse_the_sentinel() {
+    assert_eq!(Link::try_from(7usize), Ok(Link(7)));
+    assert_eq!(usize::try_from(Link(7)), Ok(7));
+    assert_eq!(u16::try_from(Link(7)), Ok(7));
+    assert_eq!(Link::try_from(NULL_ORDER as u16), Err(LinkConversionError));
+    assert_eq!(
+        Link::try_from(NULL_ORDER as usize),
+        Err(LinkConversionError)
+    );
+    assert_eq!(usize::try_from(Link::NULL), Err(LinkConversionError));
+    assert_eq!(u16::try_from(Link(1 << 16)), Err(LinkConversionError));
+    assert_eq!(Link::try_from(1usize << 40), Err(LinkConversionError));
+
+    let mut bitmap = bitmap();
+    let test = TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "(B20 B10 B30)");
+    assert_eq!(test.tree.find_node(30u64).try_link(), Some(Link(2)));
+    assert_eq!(test.tree.find_node(25u64).try_link(), None);
+}
+
+#[test]
+fn export_keeps_shape_and_colors() {
+    fn node(color: Color, key: u64, left: Tree<u64>, right: Tree<u64>) -> Tree<u64> {
+        Tree::Node {
+            color,
+            key,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+    let mut bitmap = bitmap();
+    let test = TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "(B20 (R10 B5 _) B30)");
+    let leaf = |key| node(Color::Black, key, Tree::Leaf, Tree::Leaf);
+    assert_eq!(
+        test.tree.export::<u64>(),
+        node(
+            Color::Black,
+            20,
+            node(Color::Red, 10, leaf(5), Tree::Leaf),
+            leaf(30)
+        )
+    );
+    let mut storage = self::bitmap();
+    let empty = TestTree::from_shape::<u64>(allocator(&mut storage), 16, "_");
+    assert_eq!(empty.tree.export::<u64>(), Tree::Leaf);
+}
+
+#[test]
+fn slab_order_ignores_the_links() {
+    let mut bitmap = bitmap();
+    let mut test =
+        TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "(B20 (R10 B5 B15) B30)");
+    test.tree.remove_entry(5u64).unwrap();
+    let root = test.tree.get_root::<u64>();
+    test.corrupt::<u64>(root.sref(), Corruption::Left(rb_tree::NULL_NODE));
+    assert_eq!(test.tree.iter::<u64>().count(), 2);
+    let slab: Vec<(u32, u64)> = test
+        .tree
+        .iter_slab::<u64>(16)
+        .map(|node| (node.sref(), node.key()))
+        .collect();
+    // The shape takes slots in the order its nodes are written.
+    assert_eq!(slab, [(0, 20), (1, 10), (3, 15), (4, 30)]);
+    assert!(test.tree.iter_slab::<u64>(0).next().is_none());
+}
+
+#[test]
+fn pruning_removes_old_records_a_batch_at_a_time() {
+    let mut account = GrowableAccount::new(RBTree::region_start(framed_layout().0));
+    let info = account.info();
+    let mut tree = framed_tree::<u64>(&info);
+    // Links are the slots the records were placed in.
+    for (key, slot) in [(1u64, 90), (2, 10), (3, 95), (4, 20), (5, 30), (6, 99)] {
+        tree.try_insert(key, slot, &info, &info, &info).unwrap();
+    }
+    let slot_of = |link: u32| u64::from(link);
+    assert_eq!(
+        misframed(&tree).prune_links_below::<u64>(50, slot_of, 2),
+        Err(TreeError::NonTreeDataSizeMismatch {
+            initialized: framed_layout().0 as u32,
+            given: framed_layout().0 + 1
+        })
+    );
+    assert_eq!(tree.len::<u64>(), 6);
+    assert_eq!(
+        tree.prune_links_below::<u64>(50, slot_of, 2),
+        Ok(vec![10, 20])
+    );
+    assert_eq!(tree.prune_links_below::<u64>(50, slot_of, 2), Ok(vec![30]));
+    assert_eq!(tree.prune_links_below::<u64>(50, slot_of, 2), Ok(vec![]));
+    let keys: Vec<u64> = tree.iter::<u64>().map(|(key, _)| key).collect();
+    assert_eq!(keys, [1, 3, 6]);
+}
+
+#[test]
+fn compaction_fills_holes_a_few_moves_at_a_time() {
+    let mut bitmap = bitmap();
+    let mut test =
+        TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "(B20 (R10 B5 B15) B30)");
+    for key in [10u64, 5] {
+        test.tree.remove_entry(key).unwrap();
+    }
+    let srefs = |test: &TestTree| -> Vec<u32> {
+        test.tree
+            .iter_slab::<u64>(16)
+            .map(|node| node.sref())
+            .collect()
+    };
+    assert_eq!(srefs(&test), [0, 3, 4]);
+
+    let first = test.tree.compact_step::<u64>(1).unwrap();
+    assert_eq!((first.moves, first.done), (vec![(4, 1)], false));
+    let rest = test.tree.compact_step::<u64>(8).unwrap();
+    assert_eq!((rest.moves, rest.done), (vec![(3, 2)], true));
+    assert_eq!(srefs(&test), [0, 1, 2]);
+    assert_eq!(test.to_shape::<u64>(), "(B20 B15 B30)");
+    test.assert_valid::<u64>();
+
+    test.tree.region_len.set(UNBOUNDED_REGION);
+    let unbounded = test.tree.compact_step::<u64>(1).unwrap_err();
+    assert_eq!(
+        (unbounded.error, unbounded.context.operation),
+        (TreeError::InvalidLayout, Operation::Compact)
+    );
+}
+
+#[test]
+fn merged_books_read_in_key_order() {
+    let mut first_bitmap = bitmap();
+    let mut second_bitmap = self::bitmap();
+    let first = TestTree::from_shape::<u64>(allocator(&mut first_bitmap), 16, "(B20 B10 B30)");
+    let second = TestTree::from_shape::<u64>(allocator(&mut second_bitmap), 16, "(B20 B5 B25)");
+    let empty_bitmap = &mut self::bitmap();
+    let empty = TestTree::from_shape::<u64>(allocator(empty_bitmap), 16, "_");
+
+    let forward: Vec<(u64, Origin)> = merge_iter::<u64>(&first.tree, &second.tree)
+        .map(|(key, _, origin)| (key, origin))
+        .collect();
+    assert_eq!(
+        forward,
+        [
+            (5, Origin::Second),
+            (10, Origin::First),
+            (20, Origin::First),
+            (20, Origin::Second),
+            (25, Origin::Second),
+            (30, Origin::First),
+        ]
+    );
+    let mut backward: Vec<(u64, Origin)> = merge_iter::<u64>(&first.tree, &second.tree)
+        .rev()
+        .map(|(key, _, origin)| (key, origin))
+        .collect();
+    backward.reverse();
+    assert_eq!(backward, forward);
+
+    let mut both_ends = merge_iter::<u64>(&first.tree, &second.tree);
+    assert_eq!(both_ends.next().map(|entry| entry.0), Some(5));
+    assert_eq!(both_ends.next_back().map(|entry| entry.0), Some(30));
+    let middle: Vec<u64> = both_ends.map(|(key, _, _)| key).collect();
+    assert_eq!(middle, [10, 20, 20, 25]);
+
+    let only_second: Vec<Origin> = merge_iter::<u64>(&empty.tree, &second.tree)
+        .map(|(_, _, origin)| origin)
+        .collect();
+    assert_eq!(only_second, [Origin::Second; 3]);
+    assert_eq!(merge_iter::<u64>(&empty.tree, &empty.tree).next(), None);
+}
+
+#[test]
+fn range_sums_cover_the_bounds_asked_for() {
+    let mut account = GrowableAccount::new(RBTree::region_start(framed_layout().0));
+    let info = account.info();
+    let tree = framed_tree::<u64>(&info);
+    let refused = SumTree::<u64>::new(misframed(&tree)).insert(1, 0, 5, &info, &info, &info);
+    assert_eq!(
+        refused,
+        Err(TreeError::NonTreeDataSizeMismatch {
+            initialized: framed_layout().0 as u32,
+            given: framed_layout().0 + 1
+        })
+    );
+    let depth = SumTree::<u64>::new(tree);
+    assert_eq!(depth.sum_in_range(..), 0);
+    for (price, quantity) in [(100u64, 5), (101, 7), (101, 1), (103, 2), (105, 10)] {
+        depth
+            .insert(price, 0, quantity, &info, &info, &info)
+            .unwrap();
+    }
+    assert_eq!(depth.sum_in_range(..), 25);
+    assert_eq!(depth.sum_in_range(101..=103), 10);
+    assert_eq!(depth.sum_in_range(101..103), 8);
+    assert_eq!(depth.sum_in_range(102..104), 2);
+    assert_eq!(depth.sum_in_range(..101), 5);
+    assert_eq!(
+        depth.sum_in_range((Bound::Excluded(101), Bound::Unbounded)),
+        12
+    );
+    assert_eq!(depth.sum_in_range(106..), 0);
+    assert_eq!(
+        depth.sum_in_range((Bound::Included(103), Bound::Excluded(101))),
+        0
+    );
+}
+
+#[test]
+fn ranks_and_selections_follow_key_order() {
+    let mut account = GrowableAccount::new(RBTree::region_start(framed_layout().0));
+    let info = account.info();
+    let tree = framed_tree::<u64>(&info);
+    assert_eq!(
+        RankTree::<u64>::new(misframed(&tree)).insert(1, 0, &info, &info, &info),
+        Err(TreeError::NonTreeDataSizeMismatch {
+            initialized: framed_layout().0 as u32,
+            given: framed_layout().0 + 1
+        })
+    );
+    let mut ranks = RankTree::<u64>::new(tree);
+    assert!(ranks.is_empty());
+    assert_eq!(ranks.select(0), None);
+    // Ascending inserts rotate at every level, so the sizes are rebuilt.
+    for key in 1u64..=20 {
+        ranks
+            .insert(key * 10, key as u32, &info, &info, &info)
+            .unwrap();
+    }
+    assert_eq!(ranks.len(), 20);
+    assert_eq!(ranks.rank(10), 0);
+    assert_eq!(ranks.rank(15), 1);
+    assert_eq!(ranks.rank(200), 19);
+    assert_eq!(ranks.rank(201), 20);
+    assert_eq!(ranks.select(0), Some((10, 1)));
+    assert_eq!(ranks.select(19), Some((200, 20)));
+    assert_eq!(ranks.select(20), None);
+
+    for key in [10u64, 100, 110, 200] {
+        assert!(ranks.remove(key).unwrap().is_some());
+    }
+    assert_eq!(ranks.remove(100), Ok(None));
+    assert_eq!(ranks.len(), 16);
+    assert_eq!(ranks.rank(120), 8);
+    assert_eq!(ranks.select(8), Some((120, 12)));
+    let selected: Vec<u64> = (0..ranks.len())
+        .map(|n| ranks.select(n).unwrap().0)
+        .collect();
+    let keys: Vec<u64> = (2..=19)
+        .filter(|&key| key != 10 && key != 11)
+        .map(|key| key * 10)
+        .collect();
+    assert_eq!(selected, keys);
+}
+
+#[test]
+fn subtree_sums_follow_weight_changes() {
+    let mut account = GrowableAccount::new(RBTree::region_start(framed_layout().0));
+    let info = account.info();
+    let mut depth = SumTree::<u64>::new(framed_tree::<u64>(&info));
+    assert!(depth.node_at_depth(1).is_null());
+    let mut srefs = Vec::new();
+    for price in 1u64..=8 {
+        srefs.push(
+            depth
+                .insert(price, price as u32, 10, &info, &info, &info)
+                .unwrap(),
+        );
+    }
+    assert_eq!(depth.total(), 80);
+    assert_eq!(depth.node_at_depth(1).key(), 1);
+    assert_eq!(depth.node_at_depth(25).key(), 3);
+    assert_eq!(depth.node_at_depth(80).key(), 8);
+    assert!(depth.node_at_depth(81).is_null());
+
+    // A partial fill of the order at price 3.
+    assert!(depth.set_weight(srefs[2], 4));
+    assert_eq!(depth.total(), 74);
+    assert_eq!(depth.node_at_depth(25).key(), 4);
+    assert_eq!(depth.sum_in_range(..=3), 24);
+
+    assert_eq!(depth.remove(3), Ok(Some(3)));
+    assert!(!depth.set_weight(srefs[2], 10));
+    assert_eq!(depth.total(), 70);
+    assert_eq!(depth.node_at_depth(21).key(), 4);
+}
+
+#[test]
+fn deletes_under_a_cursor_move_it_or_are_refused() {
+    let mut bitmap = bitmap();
+    let mut test = TestTree::from_shape::<u64>(
+        allocator(&mut bitmap),
+        16,
+        "(B20 (B10 B5 B15) (B30 B25 B35))",
+    );
+    let mut cursor = test.tree.cursor_at::<u64>(10);
+    assert_eq!(
+        cursor.remove(30),
+        Ok(Some(Removed {
+            key: 30,
+            link: 4,
+            sref: 4
+        }))
+    );
+    assert_eq!(cursor.current(), Some((10, 1, 1)));
+    // Taking the current node moves the cursor on, as remove_current does.
+    assert_eq!(
+        cursor.remove(10).unwrap().map(|removed| removed.key),
+        Some(10)
+    );
+    assert_eq!(cursor.current(), Some((15, 3, 3)));
+    assert_eq!(
+        cursor.delete_by_sref(3).unwrap().map(|removed| removed.key),
+        Some(15)
+    );
+    assert_eq!(cursor.current(), Some((20, 0, 0)));
+    assert_eq!(cursor.remove(10), Ok(None));
+
+    let mut strict = test.tree.cursor_at::<u64>(25).strict();
+    assert_eq!(strict.remove(25), Err(TreeError::CursorActive { sref: 5 }));
+    assert_eq!(
+        strict.delete_by_sref(5),
+        Err(TreeError::CursorActive { sref: 5 })
+    );
+    assert_eq!(strict.current(), Some((25, 5, 5)));
+    assert_eq!(
+        strict.remove(35).unwrap().map(|removed| removed.key),
+        Some(35)
+    );
+    assert_eq!(strict.remove_current(), Ok(Some((25, 5, 5))));
+    assert_eq!(strict.current(), None);
+    let keys: Vec<u64> = test.tree.iter::<u64>().map(|(key, _)| key).collect();
+    assert_eq!(keys, [5, 20]);
+    test.assert_valid::<u64>();
+}
+
+#[test]
+fn drain_hands_back_every_entry_and_frees_its_slot() {
+    let mut account = GrowableAccount::new(RBTree::region_start(framed_layout().0));
+    let info = account.info();
+    let mut tree = framed_tree::<u64>(&info);
+    for key in 1u64..=4 {
+        tree.try_insert(key, key as u32 * 10, &info, &info, &info)
+            .unwrap();
+    }
+    let refused = misframed(&tree).drain::<u64>().map(|_| ()).unwrap_err();
+    assert_eq!(
+        (refused.context.operation, refused.context.phase),
+        (Operation::Drain, Phase::Validate)
+    );
+    assert_eq!(tree.len::<u64>(), 4);
+    // Dropping the iterator early still frees the rest.
+    let first: Vec<(u64, u32)> = tree
+        .drain::<u64>()
+        .unwrap()
+        .take(2)
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(first, [(1, 10), (2, 20)]);
+    assert!(tree.is_empty());
+    assert_eq!(tree.len::<u64>(), 0);
+    assert!((0..4).all(|slot| !tree.pt.is_allocated(slot)));
+
+    let mut bitmap = bitmap();
+    let mut test = TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "(B20 B10 B30)");
+    test.tree.pt.dealloc(0).unwrap();
+    let drained: Vec<Result<u64, TreeError>> = test
+        .tree
+        .drain::<u64>()
+        .unwrap()
+        .map(|entry| entry.map(|(key, _)| key).map_err(|e| e.error))
+        .collect();
+    assert_eq!(drained, [Ok(10), Err(TreeError::DeallocFailed { sref: 0 })]);
+    assert!(test.tree.pt.is_allocated(2));
+}
+
+#[test]
+fn sorted_builds_are_balanced_or_leave_the_tree_empty() {
+    let region_start = RBTree::region_start(framed_layout().0);
+    let mut account = GrowableAccount::new(region_start);
+    let info = account.info();
+    let tree = framed_tree::<u64>(&info);
+    let refusal = |tree: &RBTree, entries: &[(u64, u32)]| {
+        let refused = tree
+            .build_from_sorted(entries, &info, &info, &info)
+            .unwrap_err();
+        assert_eq!(refused.context.operation, Operation::BuildFromSorted);
+        refused.error
+    };
+    assert_eq!(
+        refusal(&misframed(&tree), &[(1, 0)]),
+        TreeError::NonTreeDataSizeMismatch {
+            initialized: framed_layout().0 as u32,
+            given: framed_layout().0 + 1
+        }
+    );
+    assert_eq!(refusal(&tree, &[(2, 0), (1, 1)]), TreeError::Unsorted);
+    let nodes = 3 * MAX_PERMITTED_DATA_INCREASE / (2 * size_of::<Node<u64>>());
+    let too_many: Vec<(u64, u32)> = (0..nodes as u64).map(|key| (key, 0)).collect();
+    assert_eq!(
+        refusal(&tree, &too_many),
+        TreeError::GrowthLimit { instructions: 2 }
+    );
+    assert!(tree.is_empty());
+    assert!(!tree.pt.is_allocated(0));
+
+    // Equal keys keep the order they were given in.
+    let entries: Vec<(u64, u32)> = [1u64, 2, 2, 2, 3, 5, 8, 13, 21, 34]
+        .iter()
+        .zip(0..)
+        .map(|(&key, link)| (key, link))
+        .collect();
+    tree.build_from_sorted(&entries, &info, &info, &info)
+        .unwrap();
+    assert_eq!(tree.iter::<u64>().collect::<Vec<_>>(), entries);
+    assert_eq!(tree.len::<u64>(), entries.len());
+    assert_eq!(
+        testing::assert_valid_root(tree.get_root::<u64>()),
+        entries.len()
+    );
+    assert_eq!(refusal(&tree, &[(40, 0)]), TreeError::NotEmpty);
+}
+
+#[test]
+fn batches_insert_all_or_nothing() {
+    let region_start = RBTree::region_start(framed_layout().0);
+    let mut account = GrowableAccount::new(region_start);
+    let info = account.info();
+    let tree = framed_tree::<u64>(&info);
+    for key in [2u64, 5] {
+        tree.try_insert(key, key as u32, &info, &info, &info)
+            .unwrap();
+    }
+    let refused = misframed(&tree)
+        .insert_many(&[(1u64, 1)], &info, &info, &info)
+        .unwrap_err();
+    assert_eq!(
+        (refused.context.operation, refused.context.phase),
+        (Operation::InsertMany, Phase::Validate)
+    );
+    let too_many: Vec<(u64, u32)> = (0..=SLOTS as u64).map(|key| (key, 0)).collect();
+    let refused = tree
+        .insert_many(&too_many, &info, &info, &info)
+        .unwrap_err();
+    assert_eq!(
+        (refused.error, refused.context.phase),
+        (TreeError::AllocFailed, Phase::Alloc)
+    );
+    assert_eq!(tree.len::<u64>(), 2);
+    assert!(!tree.pt.is_allocated(2));
+
+    assert_eq!(
+        tree.insert_many::<u64>(&[], &info, &info, &info),
+        Ok(vec![])
+    );
+    let srefs = tree
+        .insert_many(&[(5u64, 50), (2, 20), (1, 10)], &info, &info, &info)
+        .unwrap();
+    assert_eq!(srefs, [2, 3, 4]);
+    assert!(info.data_len() >= region_start + 5 * size_of::<Node<u64>>());
+    let entries: Vec<(u64, u32)> = tree.iter::<u64>().collect();
+    assert_eq!(entries, [(1, 10), (2, 2), (2, 20), (5, 5), (5, 50)]);
+    assert_eq!(testing::assert_valid_root(tree.get_root::<u64>()), 5);
+}
+
+#[test]
+fn range_removal_takes_the_whole_band() {
+    let mut account = GrowableAccount::new(RBTree::region_start(framed_layout().0));
+    let info = account.info();
+    let mut tree = framed_tree::<u64>(&info);
+    for key in [10u64, 20, 20, 30, 40, 50] {
+        tree.try_insert(key, key as u32, &info, &info, &info)
+            .unwrap();
+    }
+    let mut seen = Vec::new();
+    assert_eq!(
+        misframed(&tree).remove_range(20u64..40, |removed| seen.push(removed.key)),
+        Err(TreeError::NonTreeDataSizeMismatch {
+            initialized: framed_layout().0 as u32,
+            given: framed_layout().0 + 1
+        })
+    );
+    assert_eq!((seen.len(), tree.len::<u64>()), (0, 6));
+
+    assert_eq!(
+        tree.remove_range(20u64..40, |removed| seen.push(removed.key)),
+        Ok(3)
+    );
+    assert_eq!(seen, [20, 20, 30]);
+    assert_eq!(
+        tree.remove_range(21u64..=29, |_| panic!("nothing to remove")),
+        Ok(0)
+    );
+    assert_eq!(
+        tree.remove_range(45u64.., |removed| seen.push(removed.key)),
+        Ok(1)
+    );
+    let keys: Vec<u64> = tree.iter::<u64>().map(|(key, _)| key).collect();
+    assert_eq!(keys, [10, 40]);
+    assert_eq!(testing::assert_valid_root(tree.get_root::<u64>()), 2);
+    // The freed slots are handed out again.
+    assert_eq!(
+        tree.insert_many(&[(1u64, 0), (2, 0), (3, 0), (4, 0)], &info, &info, &info)
+            .unwrap()
+            .len(),
+        4
+    );
+    assert!(!tree.pt.is_allocated(6));
+}
+
+#[test]
+fn splits_leave_two_valid_trees() {
+    let mut account = GrowableAccount::new(RBTree::region_start(framed_layout().0));
+    let info = account.info();
+    let mut tree = framed_tree::<u64>(&info);
+    // A header recording no root word, as trees sharing it need.
+    let shared = TreeHeader::new(framed_layout().0).with_key::<u64>();
+    unsafe { tree.header.write_unaligned(shared) };
+    let keys: Vec<(u64, u32)> = (1u64..=10).map(|key| (key * 10, key as u32)).collect();
+    tree.insert_many(&keys, &info, &info, &info).unwrap();
+    let mut upper_root = 0;
+    let mut upper = sibling(&tree, &mut upper_root);
+
+    assert_eq!(
+        tree.split_at(50u64, &mut misframed(&tree)),
+        Err(TreeError::NonTreeDataSizeMismatch {
+            initialized: framed_layout().0 as u32,
+            given: framed_layout().0 + 1
+        })
+    );
+    let mut same_root = misframed(&tree);
+    same_root.non_tree_data_size -= 1;
+    assert_eq!(
+        tree.split_at(50u64, &mut same_root),
+        Err(TreeError::AliasedHandle)
+    );
+    let mut other_bitmap = self::bitmap();
+    let mut elsewhere = TestTree::from_shape::<u64>(allocator(&mut other_bitmap), 16, "_");
+    assert_eq!(
+        tree.split_at(50u64, &mut elsewhere.tree),
+        Err(TreeError::InvalidLayout)
+    );
+
+    tree.split_at(45u64, &mut upper).unwrap();
+    let lower_keys: Vec<u64> = tree.iter::<u64>().map(|(key, _)| key).collect();
+    let upper_keys: Vec<u64> = upper.iter::<u64>().map(|(key, _)| key).collect();
+    assert_eq!(lower_keys, [10, 20, 30, 40]);
+    assert_eq!(upper_keys, [50, 60, 70, 80, 90, 100]);
+    assert_eq!(testing::assert_valid_root(tree.get_root::<u64>()), 4);
+    assert_eq!(testing::assert_valid_root(upper.get_root::<u64>()), 6);
+    assert_eq!(tree.split_at(0u64, &mut upper), Err(TreeError::NotEmpty));
+    // Nothing was allocated, so the shared allocator still holds ten slots.
+    assert!(tree.pt.is_allocated(9) && !tree.pt.is_allocated(10));
+}
+
+#[test]
+fn merges_join_disjoint_trees_and_refuse_overlaps() {
+    let mut account = GrowableAccount::new(RBTree::region_start(framed_layout().0));
+    let info = account.info();
+    let mut low = framed_tree::<u64>(&info);
+    let shared = TreeHeader::new(framed_layout().0).with_key::<u64>();
+    unsafe { low.header.write_unaligned(shared) };
+    let keys: Vec<(u64, u32)> = (1u64..=20).map(|key| (key * 10, key as u32)).collect();
+    low.insert_many(&keys, &info, &info, &info).unwrap();
+    let (mut middle_root, mut high_root) = (0, 0);
+    let mut middle = sibling(&low, &mut middle_root);
+    let mut high = sibling(&low, &mut high_root);
+    low.split_at(100u64, &mut middle).unwrap();
+    middle.split_at(150u64, &mut high).unwrap();
+
+    assert_eq!(
+        high.merge::<u64>(&mut misframed(&low)),
+        Err(TreeError::NonTreeDataSizeMismatch {
+            initialized: framed_layout().0 as u32,
+            given: framed_layout().0 + 1
+        })
+    );
+    // The lower keys may come from either tree.
+    high.merge::<u64>(&mut low).unwrap();
+    assert!(low.is_empty());
+    let keys_of = |tree: &RBTree| -> Vec<u64> { tree.iter::<u64>().map(|(key, _)| key).collect() };
+    assert_eq!(
+        keys_of(&high),
+        [10, 20, 30, 40, 50, 60, 70, 80, 90, 150, 160, 170, 180, 190, 200]
+    );
+    assert_eq!(testing::assert_valid_root(high.get_root::<u64>()), 15);
+
+    assert_eq!(high.merge::<u64>(&mut middle), Err(TreeError::Overlapping));
+    assert_eq!(keys_of(&middle), [100, 110, 120, 130, 140]);
+    high.merge_overlapping::<u64>(&mut middle).unwrap();
+    assert!(middle.is_empty());
+    assert_eq!(high.iter::<u64>().collect::<Vec<_>>(), keys);
+    assert_eq!(testing::assert_valid_root(high.get_root::<u64>()), 20);
+    // Merging an empty tree changes nothing.
+    high.merge::<u64>(&mut low).unwrap();
+    assert_eq!(testing::assert_valid_root(high.get_root::<u64>()), 20);
+}
+
+#[test]
+fn growth_is_funded_by_the_payer_given() {
+    let region_start = RBTree::region_start(framed_layout().0);
+    let mut account = GrowableAccount::new(region_start);
+    let info = account.info();
+    let mut vault_account = GrowableAccount::new(0);
+    let vault = vault_account.info();
+    let rent = Rent::default();
+    let tree = framed_tree::<u64>(&info);
+    **info.try_borrow_mut_lamports().unwrap() = rent.minimum_balance(region_start);
+
+    let balance = info.lamports();
+    let needed = match tree.try_insert(1u64, 1, &info, Payer::PreFunded, &info) {
+        Err(TreeError::NotRentExempt {
+            needed,
+            balance: seen,
+        }) if seen == balance => needed - balance,
+        other => panic!("pre-funded growth was not refused: {other:?}"),
+    };
+    assert_eq!((info.data_len(), tree.len::<u64>()), (region_start, 0));
+    assert!(!tree.pt.is_allocated(0));
+
+    // A program-owned vault keeps its own rent-exempt minimum.
+    **vault.try_borrow_mut_lamports().unwrap() = rent.minimum_balance(0) + needed - 1;
+    assert_eq!(
+        tree.try_insert(1u64, 1, &info, Payer::ProgramOwned(&vault), &info),
+        Err(TreeError::InsufficientFunds {
+            needed,
+            available: needed - 1
+        })
+    );
+    assert_eq!(info.lamports(), balance);
+
+    **vault.try_borrow_mut_lamports().unwrap() += 1;
+    assert_eq!(Payer::ProgramOwned(&vault).available(), needed);
+    tree.try_insert(1u64, 1, &info, Payer::ProgramOwned(&vault), &info)
+        .unwrap();
+    assert_eq!(Payer::ProgramOwned(&vault).available(), 0);
+    assert_eq!(info.lamports(), rent.minimum_balance(info.data_len()));
+}
+
+#[test]
+fn pre_funded_growth_only_reallocates() {
+    let region_start = RBTree::region_start(framed_layout().0);
+    let mut account = GrowableAccount::new(region_start);
+    let info = account.info();
+    let tree = framed_tree::<u64>(&info);
+    let rent = Rent::default();
+    let balance = info.lamports();
+    // No system program is needed: the tree account stands in for it.
+    tree.try_insert(1u64, 1, &info, Payer::PreFunded, &info)
+        .unwrap();
+    assert!(info.data_len() > region_start);
+    assert_eq!(info.lamports(), balance);
+    assert!(Payer::PreFunded.account().is_none());
+    assert_eq!(Payer::PreFunded.available(), 0);
+
+    let len = info.data_len();
+    **info.try_borrow_mut_lamports().unwrap() = rent.minimum_balance(len);
+    let refused = (2u64..)
+        .map(|key| tree.try_insert(key, 0, &info, Payer::PreFunded, &info))
+        .find(Result::is_err)
+        .unwrap();
+    assert!(matches!(
+        refused,
+        Err(TreeError::NotRentExempt { needed, balance })
+            if balance == rent.minimum_balance(len) && needed > balance
+    ));
+    // Inserts that fit the account as it is need no rent at all.
+    assert_eq!(info.data_len(), len);
+    assert_eq!(
+        tree.len::<u64>(),
+        RBTree::capacity_from_account::<u64>(len, framed_layout().0)
+    );
+}
+
+#[test]
+fn shrinking_refunds_the_rent_of_the_free_tail() {
+    let region_start = RBTree::region_start(framed_layout().0);
+    let mut account = GrowableAccount::new(region_start);
+    let info = account.info();
+    let mut recipient_account = GrowableAccount::new(0);
+    let recipient = recipient_account.info();
+    let mut tree = framed_tree::<u64>(&info);
+    for key in 1u64..=4 {
+        tree.try_insert(key, 0, &info, &info, &info).unwrap();
+    }
+    // Free the two last slots.
+    for key in [3u64, 4] {
+        tree.remove_entry(key).unwrap();
+    }
+    assert_eq!(
+        misframed(&tree).shrink_to_fit::<u64>(&info, &recipient),
+        Err(TreeError::NonTreeDataSizeMismatch {
+            initialized: framed_layout().0 as u32,
+            given: framed_layout().0 + 1
+        })
+    );
+    let mut unbounded = misframed(&tree);
+    unbounded.non_tree_data_size -= 1;
+    unbounded.region_len.set(UNBOUNDED_REGION);
+    assert_eq!(
+        unbounded.shrink_to_fit::<u64>(&info, &recipient),
+        Err(TreeError::InvalidLayout)
+    );
+
+    let rent = Rent::default();
+    let (balance, received) = (info.lamports(), recipient.lamports());
+    // The account cannot pay itself: its lamports are already borrowed.
+    assert_eq!(
+        tree.shrink_to_fit::<u64>(&info, &info),
+        Err(TreeError::RentTransferFailed)
+    );
+    let len = info.data_len();
+    assert_eq!(
+        RBTree::capacity_from_account::<u64>(len, framed_layout().0),
+        2
+    );
+    let excess = balance - rent.minimum_balance(len);
+    assert_eq!(tree.shrink_to_fit::<u64>(&info, &recipient), Ok(excess));
+    assert_eq!(info.lamports(), rent.minimum_balance(len));
+    assert_eq!(recipient.lamports(), received + excess);
+    assert_eq!(tree.shrink_to_fit::<u64>(&info, &recipient), Ok(0));
+
+    // The tree grows again from the new end.
+    tree.try_insert(5u64, 0, &info, &info, &info).unwrap();
+    assert_eq!(
+        tree.iter::<u64>().map(|(key, _)| key).collect::<Vec<_>>(),
+        [1, 2, 5]
+    );
+}
+
+#[test]
+fn alloc_stats_count_holes_below_the_highest_slot() {
+    let mut empty_bitmap = bitmap();
+    let empty = TestTree::from_shape::<u64>(allocator(&mut empty_bitmap), 16, "_");
+    let stats = empty.tree.alloc_stats::<u64>().unwrap();
+    assert_eq!(
+        stats,
+        AllocStats {
+            allocated: 0,
+            free: 16,
+            highest: None,
+            holes: 0
+        }
+    );
+    assert_eq!(stats.fragmentation(), 0.0);
+
+    let mut bitmap = self::bitmap();
+    let mut test =
+        TestTree::from_shape::<u64>(allocator(&mut bitmap), 16, "(B20 (R10 B5 B15) B30)");
+    for key in [5u64, 30] {
+        test.tree.remove_entry(key).unwrap();
+    }
+    let stats = test.tree.alloc_stats::<u64>().unwrap();
+    assert_eq!(
+        stats,
+        AllocStats {
+            allocated: 3,
+            free: 13,
+            highest: Some(3),
+            holes: 1
+        }
+    );
+    assert_eq!(stats.fragmentation(), 0.25);
+
+    test.tree.region_len.set(UNBOUNDED_REGION);
+    assert_eq!(
+        test.tree.alloc_stats::<u64>(),
+        Err(TreeError::InvalidLayout)
+    );
+}
+
+#[test]
+fn wide_links_keep_all_their_bits() {
+    let mut account = GrowableAccount::new(RBTree::region_start(framed_layout().0));
+    let info = account.info();
+    let tree = framed_tree::<u64>(&info);
+    let order_id = |key: u64| (key << 40) | 0xdead_beef;
+    assert_eq!(
+        misframed(&tree).try_insert_value(1u64, order_id(1), &info, &info, &info),
+        Err(TreeError::NonTreeDataSizeMismatch {
+            initialized: framed_layout().0 as u32,
+            given: framed_layout().0 + 1
+        })
+    );
+    let mut srefs = Vec::new();
+    for key in [30u64, 10, 20] {
+        srefs.push(
+            tree.try_insert_value(key, order_id(key), &info, &info, &info)
+                .unwrap(),
+        );
+    }
+    assert_eq!(srefs, [0, 1, 2]);
+    let map = RBTreeMap::<u64, u64>::new(tree, &info, &info, &info);
+    for key in [10u64, 20, 30] {
+        assert_eq!(map.get(&key), Some(order_id(key)));
+    }
+    assert_eq!(map.get(&15), None);
+}
+
+#[cfg(feature = "borsh")]
+#[test]
+fn snapshots_carry_contents_and_metadata_across_accounts() {
+    let mut source_account = GrowableAccount::new(RBTree::region_start(framed_layout().0));
+    let source_info = source_account.info();
+    let source = framed_tree::<u64>(&source_info);
+    source.set_key_range(1u64, 100).unwrap();
+    for key in [40u64, 10, 30, 10] {
+        source
+            .try_insert(key, key as u32, &source_info, &source_info, &source_info)
+            .unwrap();
+    }
+    source.mark_slot(77);
+    assert_eq!(
+        misframed(&source).snapshot::<u64>(),
+        Err(TreeError::NonTreeDataSizeMismatch {
+            initialized: framed_layout().0 as u32,
+            given: framed_layout().0 + 1
+        })
+    );
+    let snapshot = source.snapshot::<u64>().unwrap();
+    let bytes = borsh::to_vec(&snapshot).unwrap();
+    let decoded = Snapshot::<u64>::try_from_slice(&bytes).unwrap();
+    assert_eq!(decoded, snapshot);
+
+    let mut account = GrowableAccount::new(RBTree::region_start(framed_layout().0));
+    let info = account.info();
+    let tree = framed_tree::<u64>(&info);
+    let refused = |snapshot: &Snapshot<u64>| tree.restore(snapshot, &info, &info, &info);
+    let mut unsorted = decoded.clone();
+    unsorted.entries.swap(0, 3);
+    assert_eq!(refused(&unsorted), Err(TreeError::Unsorted));
+    let mut narrow = decoded.clone();
+    narrow.key_range = Some((20, 100));
+    assert_eq!(refused(&narrow), Err(TreeError::KeyOutOfRange));
+    narrow.key_range = Some((100, 1));
+    assert_eq!(refused(&narrow), Err(TreeError::EmptyKeyRange));
+    assert!(tree.is_empty());
+
+    tree.restore(&decoded, &info, &info, &info).unwrap();
+    assert_eq!(
+        tree.iter::<u64>().collect::<Vec<_>>(),
+        [(10, 10), (10, 10), (30, 30), (40, 40)]
+    );
+    assert_eq!(tree.sequence(), source.sequence());
+    assert_eq!(tree.header().map(|header| header.last_slot), Some(77));
+    assert_eq!(tree.key_range::<u64>(), Ok(Some((1, 100))));
+    assert_eq!(refused(&decoded), Err(TreeError::NotEmpty));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn views_serialize_contents_and_structure() {
+    let (non_tree_data_size, bitmap) = framed_layout();
+    let root_offset = size_of::<TreeHeader>();
+    let entries = [(1u32, 10), (2, 20), (3, 30)];
+    let image = build_account_image(
+        &entries,
+        non_tree_data_size,
+        4,
+        root_offset,
+        Some(0),
+        bitmap.clone(),
+    )
+    .unwrap();
+    let view = TreeView::<u32>::new(&image, non_tree_data_size, root_offset, Some(0)).unwrap();
+    assert_eq!(
+        serde_json::to_value(&view).unwrap(),
+        json!({"root": 1, "entries": [[1, 10], [2, 20], [3, 30]]})
+    );
+    let leaf = |sref: u32, key: u32| json!({"sref": sref, "color": "Black", "key": key, "value": key * 10, "left": null, "right": null});
+    assert_eq!(
+        serde_json::to_value(view.with_structure()).unwrap()["nodes"],
+        json!([
+            {"sref": 1, "color": "Black", "key": 2, "value": 20, "left": 0, "right": 2},
+            leaf(0, 1),
+            leaf(2, 3),
+        ])
+    );
+
+    // A cycle in corrupted links ends the walk at the node count the data
+    // can hold.
+    let mut account = GrowableAccount::new(image.len());
+    let info = account.info();
+    let pt = {
+        let mut data = info.data.borrow_mut();
+        data.copy_from_slice(&image);
+        MemoryMap::new(data[bitmap].as_mut_ptr(), 4)
+    };
+    let tree = RBTree::load::<u32>(&info, pt, 0).unwrap();
+    let root = tree.get_root::<u32>();
+    testing::corrupt::<u32>(&tree, root.left().sref(), Corruption::Left(root.sref()));
+    let data = info.data.borrow();
+    let corrupted = TreeView::<u32>::new(&data, non_tree_data_size, root_offset, Some(0)).unwrap();
+    let walked = serde_json::to_value(corrupted.with_structure()).unwrap();
+    assert_eq!(walked["nodes"].as_array().map(Vec::len), Some(4));
+    assert_eq!(walked["entries"].as_array().map(Vec::len), Some(4));
+}
+
+#[test]
+fn readers_open_fetched_bytes_without_a_runtime() {
+    let (non_tree_data_size, bitmap) = framed_layout();
+    let root_offset = size_of::<TreeHeader>();
+    let entries = [(5u32, 50), (1, 10), (3, 30), (3, 31)];
+    let mut image = build_account_image(
+        &entries,
+        non_tree_data_size,
+        8,
+        root_offset,
+        Some(0),
+        bitmap.clone(),
+    )
+    .unwrap();
+
+    let reader = RBTreeReader::<u32>::from_bytes(&image, non_tree_data_size).unwrap();
+    assert_eq!(reader.len(), 4);
+    assert!(!reader.is_empty());
+    assert_eq!(reader.find(5), Some(50));
+    assert!(reader.contains_key(3) && !reader.contains_key(4));
+    assert_eq!((reader.min(), reader.max()), (Some((1, 10)), Some((5, 50))));
+    assert_eq!(
+        reader.iter_rev().collect::<Vec<_>>(),
+        [(5, 50), (3, 31), (3, 30), (1, 10)]
+    );
+    assert_eq!(
+        reader.iter_from(2).collect::<Vec<_>>(),
+        [(3, 30), (3, 31), (5, 50)]
+    );
+
+    assert_eq!(
+        RBTreeReader::<u32>::from_bytes(&image[..non_tree_data_size - 1], non_tree_data_size).err(),
+        Some(TreeError::InvalidLayout)
+    );
+    assert_eq!(
+        RBTreeReader::<u32>::from_bytes(&image, non_tree_data_size - 1).err(),
+        Some(TreeError::InvalidHeader)
+    );
+    // A header that does not record the root needs the layout spelled out.
+    let shared = TreeHeader::new(non_tree_data_size).with_key::<u32>();
+    unsafe { (image.as_mut_ptr() as *mut TreeHeader).write_unaligned(shared) };
+    assert_eq!(
+        RBTreeReader::<u32>::from_bytes(&image, non_tree_data_size).err(),
+        Some(TreeError::InvalidHeader)
+    );
+    let view = TreeView::<u32>::new(&image, non_tree_data_size, root_offset, Some(0)).unwrap();
+    let reader = RBTreeReader::from_view(view);
+    assert_eq!(reader.len(), 4);
+    assert_eq!(reader.find(1), Some(10));
+
+    let empty =
+        build_account_image::<u32>(&[], non_tree_data_size, 8, root_offset, Some(0), bitmap)
+            .unwrap();
+    let reader = RBTreeReader::<u32>::from_bytes(&empty, non_tree_data_size).unwrap();
+    assert!(reader.is_empty());
+    assert_eq!(
+        (reader.len(), reader.min(), reader.find(1)),
+        (0, None, None)
+    );
+}
@@ -0,0 +1,57 @@
+//! Builds the crate and runs the `core` tests under the feature combinations
+//! that matter, so code behind a feature that no default build enables cannot
+//! rot unnoticed. `build.rs` generates one test per combination listed in
+//! `[package.metadata.feature-matrix]`; each runs nested cargo builds, so
+//! they are ignored by default:
+//!
+//! ```text
+//! cargo test --test feature_matrix -- --ignored
+//! ```
+//!
+//! A new feature must be added to a combination before
+//! `every_declared_feature_is_listed` passes.
+
+use std::{env, path::Path, process::Command};
+
+include!(concat!(env!("OUT_DIR"), "/feature_matrix.rs"));
+
+fn cargo(args: &[&str], features: &str) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let status = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+        .current_dir(dir)
+        .args(args)
+        .args(["--no-default-features", "--features", features])
+        .env("CARGO_TARGET_DIR", dir.join("target/feature-matrix"))
+        .status()
+        .expect("failed to run cargo");
+    assert!(
+        status.success(),
+        "`cargo {}` failed with features [{features}]",
+        args.join(" ")
+    );
+}
+
+/// Builds the library with `features`, and runs the `core` tests when they
+/// include `test-utils`.
+fn check_combination(features: &str) {
+    cargo(&["build", "--lib"], features);
+    if features.split(',').any(|name| name == "test-utils") {
+        cargo(&["test", "--test", "core"], features);
+    }
+}
+
+#[test]
+fn every_declared_feature_is_listed() {
+    assert!(
+        !DECLARED_FEATURES.is_empty(),
+        "no features found in Cargo.toml"
+    );
+    for feature in DECLARED_FEATURES {
+        assert!(
+            COMBINATIONS
+                .iter()
+                .any(|combination| combination.split(',').any(|name| name == *feature)),
+            "feature `{feature}` is in no combination of the matrix"
+        );
+    }
+}